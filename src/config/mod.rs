@@ -1,47 +1,14 @@
-use std::env;
+mod settings;
 
-pub fn base_rpc_url() -> String {
-    env::var("RPC_URL").unwrap()
-}
-
-pub fn transaction_fee() -> String {
-    env::var("TRANSACTION_FEE").unwrap_or("0".to_string())
-}
-
-pub fn multisend_address() -> String {
-    env::var("MULTISEND_ADDRESS").unwrap()
-}
-
-pub fn factory_address() -> String {
-    env::var("FACTORY_ADDRESS").unwrap()
-}
-
-pub fn exec_tx_refunder_address() -> String {
-    env::var("EXEC_TRANSACTION_REFUNDER_ADDRESS").unwrap()
-}
+pub use settings::{ChainConfig, Config, RelayMethodConfig, ValidationRule, BUNDLE_SIGNER_KEY, DEFAULT_KEY, ITX_KEY};
 
-pub fn chain_id() -> u64 {
-    env::var("CHAIN_ID").unwrap().parse().unwrap()
-}
-
-pub fn default_key_bytes() -> String {
-    env::var("DEFAULT_KEY_BYTES").unwrap()
-}
+#[cfg(test)]
+use std::sync::Arc;
 
-pub fn itx_key_bytes() -> String {
-    env::var("ITX_KEY_BYTES").unwrap()
-}
-
-
-pub fn scheme() -> String {
-    env::var("SCHEME").unwrap_or(String::from("https"))
-}
-
-fn usize_with_default(key: &str, default: usize) -> usize {
-    match env::var(key) {
-        Ok(value) => value.parse().unwrap(),
-        Err(_) => default
-    }
+/// Minimal valid configuration for tests
+#[cfg(test)]
+pub fn test_config() -> Arc<Config> {
+    Arc::new(Config::from_values(settings::test_values()).unwrap())
 }
 
 pub fn build_number() -> Option<String> {
@@ -50,4 +17,4 @@ pub fn build_number() -> Option<String> {
 
 pub fn version() -> String {
     option_env!("VERSION").unwrap_or(env!("CARGO_PKG_VERSION")).to_string()
-}
\ No newline at end of file
+}
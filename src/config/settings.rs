@@ -0,0 +1,838 @@
+//! Typed configuration of the relayer, loaded once at startup and validated before the server is launched.
+
+use crate::providers::ethereum::backend::{BackendType, RouteGroup};
+use crate::providers::ethereum::hash::H32;
+use crate::providers::ethereum::key::PrivateKey;
+use crate::providers::ethereum::types::Bytes;
+use anyhow::Result;
+use ethereum_types::{Address, U256};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+
+pub const DEFAULT_KEY: &str = "DEFAULT_KEY_BYTES";
+pub const ITX_KEY: &str = "ITX_KEY_BYTES";
+pub const BUNDLE_SIGNER_KEY: &str = "BUNDLE_SIGNER_KEY_BYTES";
+
+const RELAY_BACKEND: &str = "RELAY_BACKEND";
+
+/// Variables of a chain that can be shared with the default chain via `SHARED_CHAIN_VARIABLES`, the `RPC_URL`
+/// always has to be set per chain.
+const CHAIN_VARIABLES: &[&str] = &[
+    DEFAULT_KEY, ITX_KEY, "BUNDLE_RPC_URL", BUNDLE_SIGNER_KEY, "TRANSACTION_FEE", "ITX_MIN_BALANCE",
+    "MIN_RELAYER_BALANCE", "MULTISEND_ADDRESS", "BATCH_MULTISEND_ADDRESS", "FACTORY_ADDRESS",
+    "SAFE_PROXY_FACTORY_ADDRESS", "RELAY_METHODS", "TREASURY_ADDRESS", "VAULT_START_BLOCK",
+    "MAX_GAS_PRICE",
+];
+
+/// Validation of the call data of a relayed method, the rule also defines the parameters of the method.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ValidationRule {
+    SafeExecTransaction,
+    VaultExecTransaction,
+    VaultUpdateConfig
+}
+
+impl FromStr for ValidationRule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "safe_exec_transaction" => Ok(ValidationRule::SafeExecTransaction),
+            "vault_exec_transaction" => Ok(ValidationRule::VaultExecTransaction),
+            "vault_update_config" => Ok(ValidationRule::VaultUpdateConfig),
+            _ => anyhow::bail!("Unknown validation rule {}", s)
+        }
+    }
+}
+
+/// Method that can be relayed via ITX, calls of it are wrapped in the refunder that pays the fee.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RelayMethodConfig {
+    pub selector: H32,
+    pub refunder: Address,
+    pub rule: ValidationRule
+}
+
+/// Values that can be configured per chain. Chains other than the default chain are listed in `CHAINS`
+/// and configured with `CHAIN_<chain id>_<variable>`. Only the variables listed in `SHARED_CHAIN_VARIABLES` are
+/// taken from the default chain if they are not set for a chain, the `TRANSACTION_FEE` is required for every chain.
+#[derive(Clone)]
+pub struct ChainConfig {
+    pub chain_id: u64,
+    pub rpc_url: String,
+    pub default_key_bytes: String,
+    pub itx_key_bytes: Option<String>,
+    pub bundle_rpc_url: Option<String>,
+    pub bundle_signer_key_bytes: Option<String>,
+    pub transaction_fee: U256,
+    pub itx_min_balance: U256,
+    /// Minimum balance in wei the relayer needs to be ready
+    pub min_relayer_balance: U256,
+    /// Highest gas price in wei the relayer pays, relays are refused while the node reports a higher gas price
+    pub max_gas_price: Option<U256>,
+    pub multisend_address: Address,
+    /// Multi send contract used for batches, has to allow direct calls (e.g. MultiSendCallOnly).
+    /// Batching is disabled on chains without it.
+    pub batch_multisend_address: Option<Address>,
+    pub factory_address: Option<Address>,
+    pub safe_proxy_factory_address: Option<Address>,
+    /// Methods that can be relayed (`RELAY_METHODS`), all other methods are rejected
+    pub relay_methods: Vec<RelayMethodConfig>,
+    pub treasury_address: Option<Address>,
+    /// Block from which on the vault events are scanned for the vault nonces, e.g. the deployment block of the factory
+    pub vault_start_block: u64,
+}
+
+impl ChainConfig {
+    /// Key configuration that might be missing, used to check the keys without panicking.
+    pub fn key_bytes(&self, key: &str) -> Option<&str> {
+        match key {
+            DEFAULT_KEY => Some(&self.default_key_bytes),
+            ITX_KEY => self.itx_key_bytes.as_deref(),
+            BUNDLE_SIGNER_KEY => self.bundle_signer_key_bytes.as_deref(),
+            _ => None
+        }
+    }
+
+    pub fn itx_enabled(&self) -> bool {
+        self.itx_key_bytes.is_some()
+    }
+
+    /// Generic relays are only accepted for the methods of the configured refunders
+    pub fn has_refunders(&self) -> bool {
+        !self.relay_methods.is_empty()
+    }
+}
+
+/// Configuration read from the optional TOML file in `CONFIG_FILE` and the environment,
+/// environment variables take precedence over the file.
+pub struct Config {
+    /// Chain used by the routes without a chain id (CHAIN_ID)
+    pub default_chain_id: u64,
+    pub chains: HashMap<u64, Arc<ChainConfig>>,
+    pub batch_window_ms: u64,
+    pub batch_max_size: usize,
+    /// Relay backends by variable name (`RELAY_BACKEND`, `RELAY_BACKEND_<GROUP>`, `RELAY_BACKEND_TENANT_<TENANT>`)
+    pub relay_backends: HashMap<String, BackendType>,
+    /// Number of blocks a bundle is submitted for before giving up
+    pub bundle_retry_blocks: u64,
+    /// Tenants by api key
+    pub api_keys: HashMap<String, String>,
+    pub redis_url: Option<String>,
+    pub job_workers: usize,
+    pub job_max_attempts: usize,
+    /// Time in seconds an idempotency key is remembered
+    pub idempotency_window_secs: usize,
+    /// Maximum number of pending relayer transactions for the relayer to be ready
+    pub max_nonce_backlog: usize,
+    pub admin_token: Option<String>,
+    /// Proxies whose `X-Forwarded-For` header is used for the client address (rate limits)
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Relaying is paused while this file exists
+    pub pause_file: Option<String>,
+    /// Bearer token of the inbound webhook, the webhook is disabled if not set
+    pub webhook_token: Option<String>,
+    /// Bearer token of `/metrics`, the admin token is used if not set
+    pub metrics_token: Option<String>,
+    pub webhook_max_attempts: usize,
+    pub scheme: String,
+}
+
+impl Config {
+    /// Loads the configuration from the environment and the file in `CONFIG_FILE` (if set).
+    pub fn load() -> Result<Config> {
+        let mut values = match env::var("CONFIG_FILE").ok().filter(|it| !it.is_empty()) {
+            Some(path) => {
+                let content = fs::read_to_string(&path)
+                    .map_err(|err| anyhow::anyhow!("Could not read config file {}: {}", path, err))?;
+                parse_toml(&content).map_err(|err| anyhow::anyhow!("Invalid config file {}: {}", path, err))?
+            },
+            None => HashMap::new()
+        };
+        values.extend(env::vars());
+        Config::from_values(values)
+    }
+
+    /// Builds the configuration from raw values keyed by variable name, all invalid values are reported at once.
+    pub fn from_values(values: HashMap<String, String>) -> Result<Config> {
+        let mut values = Values {
+            values: values.into_iter().filter(|(_, value)| !value.is_empty()).collect(),
+            errors: Vec::new(),
+            scope: String::new(),
+            shared: HashSet::new()
+        };
+        values.shared = values.shared_variables();
+        let default_chain_id = values.required("CHAIN_ID").unwrap_or_default();
+        let mut chains = HashMap::new();
+        chains.insert(default_chain_id, Arc::new(values.chain(default_chain_id)));
+        for chain_id in values.chain_ids() {
+            if chain_id != default_chain_id {
+                values.scope = format!("CHAIN_{}_", chain_id);
+                chains.insert(chain_id, Arc::new(values.chain(chain_id)));
+                values.scope = String::new();
+            }
+        }
+        let config = Config {
+            default_chain_id,
+            chains,
+            batch_window_ms: values.with_default("BATCH_WINDOW_MS", 2000),
+            batch_max_size: values.with_default("BATCH_MAX_SIZE", 20),
+            relay_backends: values.relay_backends(),
+            bundle_retry_blocks: values.with_default("BUNDLE_RETRY_BLOCKS", 25),
+            api_keys: values.api_keys(),
+            redis_url: values.redis_url(),
+            job_workers: values.with_default("JOB_WORKERS", 4),
+            job_max_attempts: values.with_default("JOB_MAX_ATTEMPTS", 5),
+            idempotency_window_secs: values.with_default("IDEMPOTENCY_WINDOW_SECS", 24 * 60 * 60),
+            max_nonce_backlog: values.with_default("MAX_NONCE_BACKLOG", 10),
+            admin_token: values.optional("ADMIN_TOKEN"),
+            trusted_proxies: values.ip_addresses("TRUSTED_PROXIES"),
+            pause_file: values.optional("PAUSE_FILE"),
+            webhook_token: values.optional("WEBHOOK_TOKEN"),
+            metrics_token: values.optional("METRICS_TOKEN"),
+            webhook_max_attempts: values.with_default("WEBHOOK_MAX_ATTEMPTS", 8),
+            scheme: values.scheme(),
+        };
+        if config.relay_backends.values().any(|backend| *backend == BackendType::Bundle) {
+            for chain in config.chains.values() {
+                if chain.bundle_rpc_url.is_none() || chain.bundle_signer_key_bytes.is_none() {
+                    values.errors.push(format!(
+                        "BUNDLE_RPC_URL and {} are required for the bundle backend on chain {}",
+                        BUNDLE_SIGNER_KEY, chain.chain_id
+                    ));
+                }
+            }
+        }
+        values.errors.extend(config.missing_itx_keys());
+        if !values.errors.is_empty() {
+            anyhow::bail!("Invalid configuration:\n  {}", values.errors.join("\n  "))
+        }
+        Ok(config)
+    }
+
+    /// Relay backend configured for a tenant or route group, tenant configuration takes precedence.
+    pub fn relay_backend_type(&self, group: &str, tenant: Option<&str>) -> Option<BackendType> {
+        let backends = &self.relay_backends;
+        tenant.and_then(|tenant| backends.get(&format!("{}_TENANT_{}", RELAY_BACKEND, tenant.to_uppercase())))
+            .or_else(|| backends.get(&format!("{}_{}", RELAY_BACKEND, group.to_uppercase())))
+            .or_else(|| backends.get(RELAY_BACKEND))
+            .copied()
+    }
+
+    /// Route groups that relay via ITX (for all or some tenants) on chains without an ITX key. Generic relays use
+    /// ITX by default, but are only checked on chains with refunders.
+    fn missing_itx_keys(&self) -> Vec<String> {
+        let tenant_prefix = format!("{}_TENANT_", RELAY_BACKEND);
+        let mut tenants: Vec<Option<&str>> = self.relay_backends.keys()
+            .filter_map(|key| key.strip_prefix(&tenant_prefix))
+            .map(Some)
+            .collect();
+        tenants.sort();
+        tenants.insert(0, None);
+        let mut chains: Vec<&Arc<ChainConfig>> = self.chains.values().filter(|chain| !chain.itx_enabled()).collect();
+        chains.sort_by_key(|chain| chain.chain_id);
+        let mut errors = Vec::new();
+        for chain in chains {
+            for group in RouteGroup::ALL.iter().filter(|group| **group != RouteGroup::Generic || chain.has_refunders()) {
+                let itx_tenants: Vec<String> = tenants.iter()
+                    .filter(|tenant| {
+                        self.relay_backend_type(group.name(), **tenant).unwrap_or_else(|| group.default_backend())
+                            == BackendType::Itx
+                    })
+                    .map(|tenant| match tenant {
+                        Some(tenant) => format!("tenant {}", tenant.to_lowercase()),
+                        None => String::from("default")
+                    })
+                    .collect();
+                if !itx_tenants.is_empty() {
+                    errors.push(format!(
+                        "{} is required on chain {} for the itx backend of the {} routes ({})",
+                        ITX_KEY, chain.chain_id, group.name(), itx_tenants.join(", ")
+                    ));
+                }
+            }
+        }
+        errors
+    }
+
+    pub fn chain(&self, chain_id: u64) -> Option<Arc<ChainConfig>> {
+        self.chains.get(&chain_id).cloned()
+    }
+
+    pub fn default_chain(&self) -> Arc<ChainConfig> {
+        self.chains[&self.default_chain_id].clone()
+    }
+}
+
+fn toml_value(key: &str, value: toml::Value) -> Result<String> {
+    Ok(match value {
+        toml::Value::String(value) => value,
+        toml::Value::Integer(value) => value.to_string(),
+        toml::Value::Float(value) => value.to_string(),
+        toml::Value::Boolean(value) => value.to_string(),
+        _ => anyhow::bail!("{} has to be a string, number or boolean", key)
+    })
+}
+
+/// `[[relay_methods]]` tables with a `selector`, `refunder` and `rule`, converted to `RELAY_METHODS` entries.
+fn relay_methods_value(key: &str, value: toml::Value) -> Result<String> {
+    let methods = match value {
+        toml::Value::Array(methods) => methods,
+        value => return toml_value(key, value)
+    };
+    let entries = methods.iter()
+        .map(|method| {
+            let field = |name: &str| method.get(name).and_then(toml::Value::as_str)
+                .ok_or_else(|| anyhow::anyhow!("{} entries need a {}", key, name));
+            Ok(format!("{}:{}:{}", field("selector")?, field("refunder")?, field("rule")?))
+        })
+        .collect::<Result<Vec<String>>>()?;
+    Ok(entries.join(","))
+}
+
+fn file_value(name: &str, value: toml::Value) -> Result<String> {
+    if name.ends_with("RELAY_METHODS") {
+        relay_methods_value(name, value)
+    } else {
+        toml_value(name, value)
+    }
+}
+
+/// Top level entries of the config file, keys are matched case insensitive against the variable names.
+/// Chains are configured in `[chains.<chain id>]` tables, relay methods in `[[relay_methods]]` tables.
+fn parse_toml(content: &str) -> Result<HashMap<String, String>> {
+    let table: toml::value::Table = toml::from_str(content)?;
+    let mut values = HashMap::new();
+    for (key, value) in table {
+        match value {
+            toml::Value::Table(chains) if key.to_lowercase() == "chains" => {
+                let mut chain_ids = Vec::new();
+                for (chain_id, chain) in chains {
+                    let chain = match chain {
+                        toml::Value::Table(chain) => chain,
+                        _ => anyhow::bail!("chains.{} has to be a table", chain_id)
+                    };
+                    for (key, value) in chain {
+                        let name = format!("CHAIN_{}_{}", chain_id, key.to_uppercase());
+                        values.insert(name.clone(), file_value(&name, value)?);
+                    }
+                    chain_ids.push(chain_id);
+                }
+                values.insert("CHAINS".to_string(), chain_ids.join(","));
+            },
+            value => {
+                let name = key.to_uppercase();
+                values.insert(name.clone(), file_value(&name, value)?);
+            }
+        }
+    }
+    Ok(values)
+}
+
+struct Values {
+    values: HashMap<String, String>,
+    errors: Vec<String>,
+    /// Prefix of the variables of the chain that is currently read, empty for the default chain
+    scope: String,
+    /// Variables chains take from the default chain if they are not set for the chain
+    shared: HashSet<String>
+}
+
+impl Values {
+    /// Name of the variable that is used for the key, chains only fall back to the shared variables of the
+    /// default chain.
+    fn name(&self, key: &str) -> String {
+        let scoped = format!("{}{}", self.scope, key);
+        if self.scope.is_empty() || self.values.contains_key(&scoped) || !self.shared.contains(key) {
+            scoped
+        } else {
+            key.to_string()
+        }
+    }
+
+    fn optional(&self, key: &str) -> Option<String> {
+        self.values.get(&self.name(key)).cloned()
+    }
+
+    fn parse<T, F: FnOnce(&str) -> Result<T>>(&mut self, key: &str, expected: &str, parser: F) -> Option<T> {
+        let name = self.name(key);
+        let value = self.values.get(&name)?;
+        match parser(value) {
+            Ok(parsed) => Some(parsed),
+            Err(_) => {
+                self.errors.push(format!("{} has to be {}, got \"{}\"", name, expected, value));
+                None
+            }
+        }
+    }
+
+    fn check_required<T>(&mut self, key: &str, value: Option<T>) -> Option<T> {
+        let name = self.name(key);
+        if !self.values.contains_key(&name) {
+            self.errors.push(format!("{} is required", name));
+        }
+        value
+    }
+
+    /// Comma separated list of per chain variables that chains share with the default chain
+    fn shared_variables(&mut self) -> HashSet<String> {
+        let variables = match self.values.get("SHARED_CHAIN_VARIABLES") {
+            Some(variables) => variables.clone(),
+            None => return HashSet::new()
+        };
+        let mut shared = HashSet::new();
+        for variable in variables.split(',').map(|variable| variable.trim()).filter(|variable| !variable.is_empty()) {
+            let variable = variable.to_uppercase();
+            if CHAIN_VARIABLES.contains(&variable.as_str()) {
+                shared.insert(variable);
+            } else {
+                self.errors.push(format!("SHARED_CHAIN_VARIABLES can't contain \"{}\"", variable));
+            }
+        }
+        shared
+    }
+
+    fn chain_ids(&mut self) -> Vec<u64> {
+        let chain_ids = match self.values.get("CHAINS") {
+            Some(chain_ids) => chain_ids.clone(),
+            None => return Vec::new()
+        };
+        chain_ids.split(',').map(|chain_id| chain_id.trim()).filter(|chain_id| !chain_id.is_empty())
+            .filter_map(|chain_id| match chain_id.parse() {
+                Ok(chain_id) => Some(chain_id),
+                Err(_) => {
+                    self.errors.push(format!("CHAINS has to be a list of chain ids, got \"{}\"", chain_id));
+                    None
+                }
+            })
+            .collect()
+    }
+
+    fn chain(&mut self, chain_id: u64) -> ChainConfig {
+        ChainConfig {
+            chain_id,
+            rpc_url: self.url("RPC_URL").unwrap_or_default(),
+            default_key_bytes: self.key(DEFAULT_KEY, true).unwrap_or_default(),
+            itx_key_bytes: self.key(ITX_KEY, false),
+            bundle_rpc_url: self.optional_url("BUNDLE_RPC_URL"),
+            bundle_signer_key_bytes: self.key(BUNDLE_SIGNER_KEY, false),
+            transaction_fee: self.fee(),
+            itx_min_balance: self.amount("ITX_MIN_BALANCE"),
+            min_relayer_balance: self.amount("MIN_RELAYER_BALANCE"),
+            max_gas_price: self.parse("MAX_GAS_PRICE", "an amount in wei", |value| Ok(U256::from_dec_str(value)?)),
+            batch_multisend_address: self.optional_address("BATCH_MULTISEND_ADDRESS"),
+            multisend_address: self.address("MULTISEND_ADDRESS").unwrap_or_default(),
+            factory_address: self.optional_address("FACTORY_ADDRESS"),
+            safe_proxy_factory_address: self.optional_address("SAFE_PROXY_FACTORY_ADDRESS"),
+            relay_methods: self.relay_methods(),
+            treasury_address: self.optional_address("TREASURY_ADDRESS"),
+            vault_start_block: self.with_default("VAULT_START_BLOCK", 0),
+        }
+    }
+
+    fn required<T: FromStr>(&mut self, key: &str) -> Option<T> {
+        let value = self.parse(key, "a number", |value| value.parse().map_err(|_| anyhow::anyhow!("Invalid")));
+        self.check_required(key, value)
+    }
+
+    fn with_default<T: FromStr>(&mut self, key: &str, default: T) -> T {
+        self.parse(key, "a number", |value| value.parse().map_err(|_| anyhow::anyhow!("Invalid")))
+            .unwrap_or(default)
+    }
+
+    fn amount(&mut self, key: &str) -> U256 {
+        self.parse(key, "an amount in wei", |value| Ok(U256::from_dec_str(value)?))
+            .unwrap_or_default()
+    }
+
+    /// The fee defaults to 0 on the default chain, other chains have to set it (or share it)
+    fn fee(&mut self) -> U256 {
+        let fee = self.parse("TRANSACTION_FEE", "an amount in wei", |value| Ok(U256::from_dec_str(value)?));
+        if self.scope.is_empty() {
+            fee.unwrap_or_default()
+        } else {
+            self.check_required("TRANSACTION_FEE", fee).unwrap_or_default()
+        }
+    }
+
+    fn optional_address(&mut self, key: &str) -> Option<Address> {
+        self.parse(key, "an address", |value| Ok(serde_json::from_value(Value::String(value.to_string()))?))
+    }
+
+    fn address(&mut self, key: &str) -> Option<Address> {
+        let value = self.optional_address(key);
+        self.check_required(key, value)
+    }
+
+    fn optional_url(&mut self, key: &str) -> Option<String> {
+        self.parse(key, "a url", |value| {
+            reqwest::Url::parse(value)?;
+            Ok(value.to_string())
+        })
+    }
+
+    fn redis_url(&mut self) -> Option<String> {
+        self.parse("REDIS_URL", "a redis url", |value| {
+            redis::Client::open(value)?;
+            Ok(value.to_string())
+        })
+    }
+
+    fn url(&mut self, key: &str) -> Option<String> {
+        let value = self.optional_url(key);
+        self.check_required(key, value)
+    }
+
+    fn key(&mut self, key: &str, required: bool) -> Option<String> {
+        let name = self.name(key);
+        let value = match self.values.get(&name) {
+            // The key itself is not part of the error
+            Some(value) if PrivateKey::from_hex_str(value).is_err() => {
+                self.errors.push(format!("{} has to be a hex encoded private key", name));
+                None
+            },
+            value => value.cloned()
+        };
+        if required {
+            self.check_required(key, value)
+        } else {
+            value
+        }
+    }
+
+    /// Comma separated list of IP addresses
+    fn ip_addresses(&mut self, key: &str) -> Vec<IpAddr> {
+        self.parse(key, "a list of ip addresses", |value| {
+            value.split(',').map(|address| address.trim()).filter(|address| !address.is_empty())
+                .map(|address| Ok(address.parse::<IpAddr>()?))
+                .collect()
+        }).unwrap_or_default()
+    }
+
+    fn relay_backends(&mut self) -> HashMap<String, BackendType> {
+        let mut keys: Vec<String> = self.values.keys()
+            .filter(|key| key.as_str() == RELAY_BACKEND || key.starts_with(&format!("{}_", RELAY_BACKEND)))
+            .cloned()
+            .collect();
+        keys.sort();
+        keys.into_iter()
+            .filter_map(|key| {
+                let backend = self.parse(&key, "one of direct, itx or bundle", BackendType::from_str)?;
+                Some((key, backend))
+            })
+            .collect()
+    }
+
+    /// Comma separated list of `<api key>:<tenant>` pairs
+    fn api_keys(&mut self) -> HashMap<String, String> {
+        let entries = match self.values.get("API_KEYS") {
+            Some(entries) => entries.clone(),
+            None => return HashMap::new()
+        };
+        let mut api_keys = HashMap::new();
+        let entries = entries.split(',').map(|entry| entry.trim()).enumerate().filter(|(_, entry)| !entry.is_empty());
+        for (position, entry) in entries {
+            let parts: Vec<&str> = entry.splitn(2, ':').collect();
+            match parts.as_slice() {
+                [api_key, tenant] if !api_key.is_empty() && !tenant.is_empty() => {
+                    api_keys.insert(api_key.to_string(), tenant.to_string());
+                },
+                // The entry contains the api key, so only its position is reported
+                _ => self.errors.push(format!("API_KEYS entry {} has to be <api key>:<tenant>", position + 1))
+            }
+        }
+        api_keys
+    }
+
+    /// Comma separated list of `<selector>:<refunder>:<validation rule>` entries, a selector can only be listed once
+    fn relay_methods(&mut self) -> Vec<RelayMethodConfig> {
+        self.parse("RELAY_METHODS", "a list of <selector>:<refunder>:<validation rule> entries", |value| {
+            let mut methods: Vec<RelayMethodConfig> = Vec::new();
+            for entry in value.split(',').map(|entry| entry.trim()).filter(|entry| !entry.is_empty()) {
+                let parts: Vec<&str> = entry.split(':').map(|part| part.trim()).collect();
+                let (selector, refunder, rule) = match parts.as_slice() {
+                    [selector, refunder, rule] => (*selector, *refunder, *rule),
+                    _ => anyhow::bail!("Invalid entry {}", entry)
+                };
+                let selector_bytes: Bytes = serde_json::from_value(Value::String(selector.to_string()))?;
+                anyhow::ensure!(selector_bytes.0.len() == 4, "Invalid selector {}", selector);
+                let mut method = RelayMethodConfig {
+                    selector: H32::default(),
+                    refunder: serde_json::from_value(Value::String(refunder.to_string()))?,
+                    rule: rule.parse()?
+                };
+                method.selector.copy_from_slice(&selector_bytes.0);
+                anyhow::ensure!(
+                    methods.iter().all(|other| other.selector != method.selector),
+                    "Duplicate selector {}", selector
+                );
+                methods.push(method);
+            }
+            Ok(methods)
+        }).unwrap_or_default()
+    }
+
+    fn scheme(&mut self) -> String {
+        self.parse("SCHEME", "http or https", |value| match value {
+            "http" | "https" => Ok(value.to_string()),
+            _ => anyhow::bail!("Invalid scheme")
+        }).unwrap_or_else(|| String::from("https"))
+    }
+}
+
+#[cfg(test)]
+const TEST_KEY: &str = "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+
+/// Minimal valid configuration for tests
+#[cfg(test)]
+pub fn test_values() -> HashMap<String, String> {
+    vec![
+        ("RPC_URL", "http://localhost:8545"),
+        ("CHAIN_ID", "4"),
+        (DEFAULT_KEY, TEST_KEY),
+        ("MULTISEND_ADDRESS", "0x8d29be29923b68abfdd21e541b9374737b49cdad"),
+    ].into_iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with(overrides: &[(&str, &str)]) -> Result<Config> {
+        let mut values = test_values();
+        values.extend(overrides.iter().map(|(key, value)| (key.to_string(), value.to_string())));
+        Config::from_values(values)
+    }
+
+    #[test]
+    fn defaults() {
+        let config = with(&[]).unwrap();
+        let chain = config.default_chain();
+        assert_eq!(config.default_chain_id, 4);
+        assert_eq!(chain.chain_id, 4);
+        assert_eq!(chain.transaction_fee, U256::zero());
+        assert!(chain.batch_multisend_address.is_none());
+        assert!(chain.factory_address.is_none());
+        assert!(chain.max_gas_price.is_none());
+        assert_eq!(config.chains.len(), 1);
+        assert_eq!(config.job_workers, 4);
+        assert_eq!(config.scheme, "https");
+        assert!(config.relay_backends.is_empty());
+    }
+
+    #[test]
+    fn empty_values_are_unset() {
+        let config = with(&[("TREASURY_ADDRESS", ""), ("BATCH_MULTISEND_ADDRESS", "")]).unwrap();
+        let chain = config.default_chain();
+        assert!(chain.treasury_address.is_none());
+        assert!(chain.batch_multisend_address.is_none());
+    }
+
+    #[test]
+    fn parses_typed_values() {
+        let config = with(&[
+            ("TRANSACTION_FEE", "1000000000000000"),
+            ("TREASURY_ADDRESS", "0x0000000000000000000000000000000000000042"),
+            ("MAX_GAS_PRICE", "100000000000"),
+            ("RELAY_BACKEND_SAFE", "Bundle"),
+            ("BUNDLE_RPC_URL", "https://relay.example"),
+            (BUNDLE_SIGNER_KEY, TEST_KEY),
+            ("API_KEYS", "key-a:alpha, key-b:beta"),
+            ("TRUSTED_PROXIES", "10.0.0.1, ::1"),
+        ]).unwrap();
+        let chain = config.default_chain();
+        assert_eq!(chain.transaction_fee, U256::from(1_000_000_000_000_000u64));
+        assert_eq!(chain.treasury_address, Some(Address::from_low_u64_be(0x42)));
+        assert_eq!(chain.max_gas_price, Some(U256::from(100_000_000_000u64)));
+        assert_eq!(config.relay_backends.get("RELAY_BACKEND_SAFE"), Some(&BackendType::Bundle));
+        assert_eq!(config.api_keys.get("key-b").map(String::as_str), Some("beta"));
+        assert_eq!(config.trusted_proxies, vec!["10.0.0.1".parse::<IpAddr>().unwrap(), "::1".parse().unwrap()]);
+        assert!(with(&[("TRUSTED_PROXIES", "10.0.0.1,proxy")]).is_err());
+        let config = with(&[("REDIS_URL", "redis://redis:6379")]).unwrap();
+        assert_eq!(config.redis_url.as_deref(), Some("redis://redis:6379"));
+        let error = with(&[("REDIS_URL", "http://redis:6379")]).err().unwrap().to_string();
+        assert!(error.contains("REDIS_URL has to be a redis url"));
+    }
+
+    #[test]
+    fn chains_fall_back_to_shared_variables() {
+        let config = with(&[
+            ("CHAINS", "100, 5"),
+            ("SHARED_CHAIN_VARIABLES", "default_key_bytes, MULTISEND_ADDRESS, TRANSACTION_FEE"),
+            ("TRANSACTION_FEE", "1000"),
+            ("TREASURY_ADDRESS", "0x0000000000000000000000000000000000000042"),
+            ("CHAIN_100_RPC_URL", "https://rpc.gnosischain.com"),
+            ("CHAIN_100_TRANSACTION_FEE", "0"),
+            ("CHAIN_5_RPC_URL", "https://goerli.example"),
+            ("CHAIN_5_MULTISEND_ADDRESS", "0x0000000000000000000000000000000000000005"),
+        ]).unwrap();
+        assert_eq!(config.chains.len(), 3);
+        let gnosis_chain = config.chain(100).unwrap();
+        assert_eq!(gnosis_chain.rpc_url, "https://rpc.gnosischain.com");
+        assert_eq!(gnosis_chain.transaction_fee, U256::zero());
+        assert_eq!(gnosis_chain.multisend_address, config.default_chain().multisend_address);
+        assert!(gnosis_chain.treasury_address.is_none());
+        let goerli = config.chain(5).unwrap();
+        assert_eq!(goerli.transaction_fee, U256::from(1000));
+        assert_eq!(goerli.multisend_address, Address::from_low_u64_be(5));
+        assert_eq!(goerli.default_key_bytes, TEST_KEY);
+        assert!(config.chain(1).is_none());
+    }
+
+    #[test]
+    fn chains_do_not_fall_back_to_unshared_variables() {
+        let error = with(&[
+            ("CHAINS", "100"),
+            ("TRANSACTION_FEE", "1000"),
+            ("FACTORY_ADDRESS", "0x0000000000000000000000000000000000000042"),
+            ("CHAIN_100_RPC_URL", "https://rpc.gnosischain.com"),
+        ]).err().unwrap().to_string();
+        assert!(error.contains("CHAIN_100_TRANSACTION_FEE is required"));
+        assert!(error.contains("CHAIN_100_MULTISEND_ADDRESS is required"));
+        assert!(error.contains("CHAIN_100_DEFAULT_KEY_BYTES is required"));
+
+        let config = with(&[
+            ("CHAINS", "100"),
+            ("FACTORY_ADDRESS", "0x0000000000000000000000000000000000000042"),
+            ("CHAIN_100_RPC_URL", "https://rpc.gnosischain.com"),
+            ("CHAIN_100_TRANSACTION_FEE", "0"),
+            ("CHAIN_100_MULTISEND_ADDRESS", "0x0000000000000000000000000000000000000005"),
+            (&format!("CHAIN_100_{}", DEFAULT_KEY), TEST_KEY),
+        ]).unwrap();
+        assert!(config.chain(100).unwrap().factory_address.is_none());
+
+        let error = with(&[("SHARED_CHAIN_VARIABLES", "RPC_URL")]).err().unwrap().to_string();
+        assert!(error.contains("SHARED_CHAIN_VARIABLES can't contain \"RPC_URL\""));
+    }
+
+    #[test]
+    fn chains_require_their_own_rpc_url() {
+        let error = with(&[("CHAINS", "100")]).err().unwrap().to_string();
+        assert!(error.contains("CHAIN_100_RPC_URL is required"));
+        let error = with(&[("CHAINS", "gnosis")]).err().unwrap().to_string();
+        assert!(error.contains("CHAINS has to be a list of chain ids"));
+    }
+
+    #[test]
+    fn reports_all_errors() {
+        let mut values = test_values();
+        values.remove("MULTISEND_ADDRESS");
+        values.insert("CHAIN_ID".to_string(), "rinkeby".to_string());
+        values.insert("TRANSACTION_FEE".to_string(), "0.1".to_string());
+        values.insert(ITX_KEY.to_string(), "0x1234".to_string());
+        let error = Config::from_values(values).err().unwrap().to_string();
+        assert!(error.contains("MULTISEND_ADDRESS is required"));
+        assert!(error.contains("CHAIN_ID has to be a number, got \"rinkeby\""));
+        assert!(error.contains("TRANSACTION_FEE has to be an amount in wei"));
+        assert!(error.contains("ITX_KEY_BYTES has to be a hex encoded private key"));
+        assert!(!error.contains("0x1234"));
+    }
+
+    #[test]
+    fn bundle_backend_requires_endpoint() {
+        let error = with(&[("RELAY_BACKEND", "bundle")]).err().unwrap().to_string();
+        assert!(error.contains("BUNDLE_RPC_URL and BUNDLE_SIGNER_KEY_BYTES are required"));
+        assert!(with(&[("RELAY_BACKEND", "flashbots")]).is_err());
+    }
+
+    #[test]
+    fn itx_backend_requires_itx_key() {
+        let error = with(&[("RELAY_BACKEND_TENANT_ACME", "itx")]).err().unwrap().to_string();
+        assert!(error.contains("ITX_KEY_BYTES is required on chain 4 for the itx backend of the safe routes (tenant acme)"));
+
+        // Generic relays default to ITX, but are only possible with a refunder
+        assert!(with(&[]).is_ok());
+        let refunder = ("RELAY_METHODS", "0x6a761202:0x0000000000000000000000000000000000000042:safe_exec_transaction");
+        let error = with(&[refunder]).err().unwrap().to_string();
+        assert!(error.contains("for the itx backend of the generic routes (default)"));
+        assert!(with(&[refunder, ("RELAY_BACKEND_GENERIC", "direct")]).is_ok());
+        assert!(with(&[refunder, (ITX_KEY, TEST_KEY)]).is_ok());
+
+        let error = with(&[
+            ("RELAY_BACKEND", "itx"),
+            (ITX_KEY, TEST_KEY),
+            ("CHAINS", "100"),
+            ("SHARED_CHAIN_VARIABLES", "DEFAULT_KEY_BYTES,MULTISEND_ADDRESS,TRANSACTION_FEE"),
+            ("CHAIN_100_RPC_URL", "https://rpc.gnosischain.com"),
+        ]).err().unwrap().to_string();
+        assert!(error.contains("ITX_KEY_BYTES is required on chain 100 for the itx backend of the vault routes"));
+        assert!(!error.contains("on chain 4"));
+    }
+
+    #[test]
+    fn parses_relay_methods() {
+        let config = with(&[(
+            "RELAY_METHODS",
+            "0x6a761202:0x0000000000000000000000000000000000000001:safe_exec_transaction, \
+             0xD4E5F6A7:0x0000000000000000000000000000000000000002:Vault_Update_Config"
+        ), ("RELAY_BACKEND_GENERIC", "direct")]).unwrap();
+        assert_eq!(config.default_chain().relay_methods, vec![
+            RelayMethodConfig {
+                selector: [0x6a, 0x76, 0x12, 0x02],
+                refunder: Address::from_low_u64_be(1),
+                rule: ValidationRule::SafeExecTransaction
+            },
+            RelayMethodConfig {
+                selector: [0xd4, 0xe5, 0xf6, 0xa7],
+                refunder: Address::from_low_u64_be(2),
+                rule: ValidationRule::VaultUpdateConfig
+            },
+        ]);
+        assert!(with(&[]).unwrap().default_chain().relay_methods.is_empty());
+
+        let refunder = "0x0000000000000000000000000000000000000001";
+        for invalid in &[
+            format!("0x6a7612:{}:safe_exec_transaction", refunder),
+            "0x6a761202:0x01:safe_exec_transaction".to_string(),
+            format!("0x6a761202:{}:exec_transaction", refunder),
+            format!("0x6a761202:{}", refunder),
+            format!("0x6a761202:{0}:safe_exec_transaction,0x6A761202:{0}:vault_exec_transaction", refunder),
+        ] {
+            let error = with(&[("RELAY_METHODS", invalid.as_str()), ("RELAY_BACKEND_GENERIC", "direct")]).err();
+            assert!(error.unwrap().to_string().contains("RELAY_METHODS has to be a list of"));
+        }
+    }
+
+    #[test]
+    fn api_key_errors_do_not_leak_keys() {
+        let error = with(&[("API_KEYS", "key-a:alpha,secret-key")]).err().unwrap().to_string();
+        assert!(error.contains("API_KEYS entry 2"));
+        assert!(!error.contains("secret-key"));
+    }
+
+    #[test]
+    fn parses_toml_file() {
+        let values = parse_toml(
+            "rpc_url = \"http://node:8545\"\nchain_id = 4\n\n[chains.100]\nrpc_url = \"http://gnosis:8545\"\n"
+        ).unwrap();
+        assert_eq!(values.get("RPC_URL").map(String::as_str), Some("http://node:8545"));
+        assert_eq!(values.get("CHAIN_ID").map(String::as_str), Some("4"));
+        assert_eq!(values.get("CHAIN_100_RPC_URL").map(String::as_str), Some("http://gnosis:8545"));
+        assert_eq!(values.get("CHAINS").map(String::as_str), Some("100"));
+        assert!(parse_toml("[relayer]\nid = 1\n").is_err());
+    }
+
+    #[test]
+    fn parses_toml_relay_methods() {
+        let values = parse_toml(concat!(
+            "[[relay_methods]]\nselector = \"0x6a761202\"\nrefunder = \"0x01\"\nrule = \"safe_exec_transaction\"\n",
+            "[[relay_methods]]\nselector = \"0xd4e5f6a7\"\nrefunder = \"0x02\"\nrule = \"vault_update_config\"\n",
+            "[[chains.100.relay_methods]]\n",
+            "selector = \"0x6a761202\"\nrefunder = \"0x03\"\nrule = \"safe_exec_transaction\"\n"
+        )).unwrap();
+        assert_eq!(
+            values.get("RELAY_METHODS").map(String::as_str),
+            Some("0x6a761202:0x01:safe_exec_transaction,0xd4e5f6a7:0x02:vault_update_config")
+        );
+        assert_eq!(
+            values.get("CHAIN_100_RELAY_METHODS").map(String::as_str),
+            Some("0x6a761202:0x03:safe_exec_transaction")
+        );
+        assert!(parse_toml("[[relay_methods]]\nselector = \"0x6a761202\"\n").is_err());
+    }
+}
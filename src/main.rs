@@ -18,18 +18,67 @@ mod utils;
 mod providers;
 
 use dotenv::dotenv;
+use utils::chains::ChainRoutes;
 use utils::cors::{CORS};
+use utils::logging::{self, RequestIds};
+use utils::metrics::RequestMetrics;
 use routes::active_routes;
 use crate::routes::error_catchers;
+use crate::services::audit::AuditLog;
+use crate::services::batch::BatchQueue;
+use crate::services::idempotency::Idempotency;
+use crate::services::jobs::JobQueue;
+use crate::services::metrics;
+use crate::services::settings::Settings;
+use crate::services::webhooks::Webhooks;
+use crate::providers::store::Store;
+use crate::config::Config;
+use anyhow::Context as _;
+use std::sync::Arc;
+
+/// Reports errors at startup the same way as an invalid configuration and stops the relayer.
+fn exit_on_error<T>(result: anyhow::Result<T>) -> T {
+    result.unwrap_or_else(|err| {
+        log::error!("{:#}", err);
+        std::process::exit(1)
+    })
+}
 
 fn main() {
     dotenv().ok();
-    env_logger::init();
-    
+    logging::init();
+
+    let config = Arc::new(exit_on_error(Config::load()));
+    let client = reqwest::blocking::Client::new();
+    let store = exit_on_error(Store::from_config(&config));
+    let settings = exit_on_error(
+        Settings::load(store.clone(), config.pause_file.clone()).context("Could not load the runtime settings")
+    );
+    settings.start_refresh();
+    metrics::start_refresh(config.clone(), client.clone());
+    let webhooks = Webhooks::new(store.clone(), config.webhook_max_attempts);
+    exit_on_error(webhooks.start().context("Could not start the webhook deliveries"));
+    let audit = AuditLog::new(store.clone(), webhooks.clone());
+    audit.start_tracking(config.clone(), client.clone());
+    let job_queue = store.clone().map(|store| exit_on_error(
+        JobQueue::start(config.clone(), client.clone(), store, settings.clone(), audit.clone())
+            .context("Could not start the job queue")
+    ));
     rocket::ignite()
         .mount("/", active_routes())
-        .manage(reqwest::blocking::Client::new())
+        .manage(BatchQueue::start(config.clone(), client.clone(), store.clone(), settings.clone(), audit.clone()))
+        .manage(job_queue)
+        .manage(Idempotency::new(store.clone(), config.idempotency_window_secs))
+        .manage(store)
+        .manage(settings)
+        .manage(audit)
+        .manage(webhooks)
+        .manage(client)
+        .manage(config)
+        .attach(RequestIds())
+        .attach(ChainRoutes())
         .attach(CORS())
+        .attach(RequestMetrics())
         .register(error_catchers())
         .launch();
 }
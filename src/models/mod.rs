@@ -1,13 +1,18 @@
+use crate::providers::ethereum::backend::{BackendType, RouteGroup};
 use crate::providers::ethereum::types::Bytes;
 use serde::{Deserialize, Serialize};
-use ethereum_types::{Address, U256};
+use ethereum_types::{Address, H256, U256};
+use std::collections::HashMap;
 
 #[derive(Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct About {
     pub name: String,
     pub version: String,
-    pub build_number: Option<String>
+    pub build_number: Option<String>,
+    pub chain_id: u64,
+    pub relayer_balance: Option<U256>,
+    pub itx_balance: Option<U256>
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -27,7 +32,7 @@ pub struct PrepareResult {
     pub transaction: SafeTransaction
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct ExecutePayload {
     pub wallet: Address,
@@ -35,7 +40,7 @@ pub struct ExecutePayload {
     pub transaction: SafeTransaction
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct DeployPayload {
     pub implementation: Address,
@@ -45,8 +50,60 @@ pub struct DeployPayload {
     pub nonce: U256
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SafeDeployPayload {
+    pub singleton: Address,
+    pub owners: Vec<Address>,
+    pub threshold: U256,
+    /// Optional delegate call performed during setup (e.g. to enable modules)
+    #[serde(default)]
+    pub to: Address,
+    #[serde(default)]
+    pub data: Bytes,
+    pub fallback_handler: Address,
+    pub payment_token: Address,
+    pub payment: U256,
+    pub payment_receiver: Address,
+    pub salt_nonce: U256
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
+pub struct PredictPayload {
+    pub implementation: Address,
+    pub initializer: Bytes,
+    pub nonce: U256
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DeploymentPrediction {
+    pub address: Address,
+    pub proxy_factory: Address,
+    pub deployed: bool
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum DeploymentState {
+    Pending,
+    Success,
+    Failed
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DeploymentStatus {
+    pub transaction_hash: H256,
+    pub state: DeploymentState,
+    pub wallet: Option<Address>,
+    pub block_number: Option<U256>,
+    pub initialized: bool
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
 pub struct SafeTransaction {
     pub to: Address,
     pub value: U256,
@@ -55,10 +112,338 @@ pub struct SafeTransaction {
     pub safe_tx_gas: U256
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct GenericRelayData {
     pub to: Address,
     pub method: String,
     pub method_data: Bytes
-}
\ No newline at end of file
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum RelayState {
+    Pending,
+    Success,
+    Failed
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RelayStatus {
+    pub relay_hash: H256,
+    pub state: RelayState,
+    pub broadcasts: Vec<H256>,
+    pub mined_transaction: Option<H256>,
+    pub block_number: Option<U256>
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RefunderInfo {
+    pub address: Address,
+    pub fee: U256,
+    pub token: Address,
+    pub owner: Address
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct WithdrawPayload {
+    pub token: Address,
+    /// Defaults to the configured treasury
+    pub target: Option<Address>
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchItemReceipt {
+    pub batch_id: u64,
+    /// Position of the transaction in the batch
+    pub index: usize
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum BatchState {
+    /// Waiting for the batch window to close
+    Queued,
+    Pending,
+    Success,
+    Failed
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchItemStatus {
+    pub index: usize,
+    pub wallet: Address,
+    pub state: RelayState
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchStatus {
+    pub batch_id: u64,
+    pub state: BatchState,
+    pub transaction_hash: Option<H256>,
+    pub block_number: Option<U256>,
+    /// Set if the batch could not be submitted
+    pub error: Option<String>,
+    pub items: Vec<BatchItemStatus>
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct JobReceipt {
+    pub job_id: u64
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum JobState {
+    Queued,
+    Running,
+    /// Failed with a temporary error, will be retried
+    Retrying,
+    Succeeded,
+    Failed
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct JobStatus {
+    pub job_id: u64,
+    pub state: JobState,
+    pub attempts: usize,
+    /// Hash returned by the relay backend once the job succeeded
+    pub result: Option<String>,
+    pub error: Option<String>,
+    pub created_at: i64,
+    pub updated_at: i64
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct HealthCheck {
+    pub name: String,
+    /// Chain the check belongs to, `None` for checks that are not chain specific
+    pub chain_id: Option<u64>,
+    pub healthy: bool,
+    pub details: Option<String>
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Readiness {
+    pub ready: bool,
+    pub checks: Vec<HealthCheck>
+}
+
+/// Settings that can be changed at runtime via the admin routes, they take precedence over the configuration.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RuntimeSettings {
+    /// Incremented with every change, updates have to be based on the current version
+    pub version: u64,
+    /// Fee overrides by chain id
+    #[serde(default)]
+    pub fees: HashMap<u64, FeeSettings>,
+    #[serde(default)]
+    pub allowlists: Allowlists,
+    pub rate_limit: Option<RateLimit>,
+    #[serde(default)]
+    pub pauses: Vec<Pause>
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct FeeSettings {
+    pub transaction_fee: Option<U256>,
+    pub multisend_address: Option<Address>
+}
+
+/// Lists that are not set allow everybody
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Allowlists {
+    pub wallets: Option<Vec<Address>>,
+    /// Requests without an API key are rejected if tenants are listed
+    pub tenants: Option<Vec<String>>
+}
+
+/// Relay requests per tenant (or client address for requests without an API key) in a fixed window
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RateLimit {
+    pub requests: u64,
+    pub window_secs: u64
+}
+
+/// Relay routes that are rejected, read-only routes keep working
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct Pause {
+    /// All route groups if not set, pausing `safe` also pauses batches
+    pub group: Option<RouteGroup>,
+    /// All chains if not set
+    pub chain_id: Option<u64>,
+    pub reason: Option<String>
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SettingsChange {
+    pub version: u64,
+    pub changed_at: i64,
+    /// Id of the admin request that made the change
+    pub request_id: String,
+    pub previous: RuntimeSettings,
+    pub settings: RuntimeSettings
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum RelayOutcome {
+    /// The relay backend did not accept the transaction
+    Failed,
+    Submitted,
+    Mined,
+    Reverted
+}
+
+/// Call performed by the relayed transaction, multiSend transactions are decoded into their calls
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RelayedCall {
+    pub operation: u8,
+    pub to: Address,
+    pub value: U256,
+    pub data: Bytes
+}
+
+/// Audit record of a relay attempt
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RelayRecord {
+    pub id: u64,
+    pub chain_id: u64,
+    pub tenant: Option<String>,
+    pub request_id: Option<String>,
+    /// Callback URL set with the request, notified instead of the tenant webhook
+    #[serde(default)]
+    pub callback_url: Option<String>,
+    pub service: String,
+    pub backend: Option<BackendType>,
+    pub wallet: Option<Address>,
+    pub calls: Vec<RelayedCall>,
+    /// Fee the relay pays to the relayer, in wei or in the fee token
+    pub fee: U256,
+    /// Token the fee is paid in, `None` for fees in the native currency
+    #[serde(default)]
+    pub fee_token: Option<Address>,
+    /// Fee that was collected, set once the relay is mined. Safe transactions that fail don't pay the fee
+    pub collected_fee: Option<U256>,
+    /// Number of relays submitted in the same transaction, the gas is shared by a batch
+    pub batch_size: usize,
+    pub outcome: RelayOutcome,
+    /// Hash returned by the relay backend (relay hash for ITX)
+    pub hash: Option<String>,
+    pub error: Option<String>,
+    pub transaction_hash: Option<H256>,
+    pub block_number: Option<U256>,
+    pub gas_used: Option<U256>,
+    pub effective_gas_price: Option<U256>,
+    pub created_at: i64,
+    /// Time the record was last updated, for mined relays the time the receipt was found
+    pub updated_at: i64
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RelayRecordPage {
+    pub count: usize,
+    /// Offset of the next page, if there are more records
+    pub next_offset: Option<usize>,
+    pub results: Vec<RelayRecord>
+}
+
+/// Income and costs of a mined relay
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RelayAccount {
+    pub record_id: u64,
+    pub chain_id: u64,
+    pub tenant: Option<String>,
+    pub wallet: Option<Address>,
+    pub service: String,
+    /// Day the relay was mined (UTC)
+    pub day: String,
+    pub fee_token: Option<Address>,
+    /// Fee collected, reverted relays and failed Safe transactions don't collect the fee
+    pub income: U256,
+    /// Gas used times the effective gas price in wei, shared by the relays of a batch
+    pub gas_cost: U256,
+    /// Amount the relay lost, only known for fees in the native currency
+    pub loss: Option<U256>
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountingSummary {
+    /// Day, tenant or wallet, `None` for relays without tenant or wallet
+    pub key: Option<String>,
+    pub relays: usize,
+    /// Fees in the native currency in wei
+    pub income: U256,
+    /// Fees paid in tokens, by token
+    pub token_income: HashMap<Address, U256>,
+    pub gas_cost: U256,
+    /// Number of relays that lost money
+    pub losses: usize,
+    pub loss_amount: U256
+}
+
+/// Webhook of a tenant, the secret is used to sign the deliveries
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookRegistration {
+    pub url: String,
+    pub secret: String
+}
+
+/// Registered webhook as returned to the tenant, without the secret
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Webhook {
+    pub url: String
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum WebhookEventType {
+    Broadcast,
+    Mined,
+    Reverted,
+    /// The transaction was resubmitted with a different hash (e.g. a higher gas price by ITX)
+    Replaced,
+    /// The backend gave up the transaction before it was included (e.g. a bundle that was not included in time)
+    Dropped
+}
+
+/// Payload of a webhook delivery
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookEvent {
+    pub event: WebhookEventType,
+    /// Id of the relay in the audit log
+    pub relay_id: u64,
+    pub chain_id: u64,
+    pub request_id: Option<String>,
+    pub wallet: Option<Address>,
+    /// Hash returned by the relay backend (relay hash for ITX)
+    pub hash: Option<String>,
+    pub transaction_hash: Option<H256>,
+    pub block_number: Option<U256>,
+    pub timestamp: i64
+}
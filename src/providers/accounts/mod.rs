@@ -2,20 +2,19 @@ pub mod safe;
 pub mod utils;
 pub mod vault;
 
-use crate::config::{multisend_address, transaction_fee};
 use crate::models::{SafeTransaction};
 use crate::providers::ethereum::{EthereumProvider};
 use crate::providers::ethereum::types::Bytes;
-use utils::decode_multisend_bytes;
+use crate::utils::errors::Rejection;
+use utils::try_decode_multisend_bytes;
 use ethereum_types::{Address, U256};
-use serde_json;
 
 use anyhow::Result;
 
-pub fn check_payment_tx(eth_provider: &EthereumProvider, payment_tx: &SafeTransaction, fee: U256) -> Result<()> {
+pub fn check_payment_tx(relayer: Address, payment_tx: &SafeTransaction, fee: U256) -> Result<()> {
     anyhow::ensure!(payment_tx.operation == 0, "Payment should be call");
     anyhow::ensure!(
-        payment_tx.to == eth_provider.account(),
+        payment_tx.to == relayer,
         "Payment should go to relayer"
     );
     anyhow::ensure!(
@@ -26,12 +25,17 @@ pub fn check_payment_tx(eth_provider: &EthereumProvider, payment_tx: &SafeTransa
     Ok(())
 }
 
+/// Checks that the transaction pays the fee, an invalid payment is rejected and never retried.
 pub fn check_fee(eth_provider: &EthereumProvider, to: Address, value: U256, data: &Vec<u8>, operation: u8) -> Result<()> {
-    let fee = U256::from_dec_str(&transaction_fee())?;
+    check_fee_payment(eth_provider, to, value, data, operation)
+        .map_err(|err| Rejection::unprocessable(err.to_string()).into())
+}
+
+fn check_fee_payment(eth_provider: &EthereumProvider, to: Address, value: U256, data: &[u8], operation: u8) -> Result<()> {
+    let fee = eth_provider.chain().transaction_fee;
     if fee != U256::zero() {
         // Check payment
-        let ms_address =
-            serde_json::from_value(serde_json::value::Value::String(multisend_address()))?;
+        let ms_address = eth_provider.chain().multisend_address;
         anyhow::ensure!(
             to == ms_address,
             "Fee payment requires multisend"
@@ -44,13 +48,13 @@ pub fn check_fee(eth_provider: &EthereumProvider, to: Address, value: U256, data
             value == U256::zero(),
             "Delegate call should not contain value"
         );
-        let txs = decode_multisend_bytes(data);
+        let txs = try_decode_multisend_bytes(data)?;
         anyhow::ensure!(
             txs.len() > 1,
             "There should be at least 1 user tx + the payment tx"
         );
         let payment_tx = &txs[txs.len() - 1];
-        check_payment_tx(eth_provider, payment_tx, fee)?;
+        check_payment_tx(eth_provider.account()?, payment_tx, fee)?;
     };
     Ok(())
 }
@@ -1,10 +1,13 @@
 use super::{Account, Estimation};
 use crate::models::ExecutePayload;
+use crate::providers::ethereum::hash::keccak256;
 use crate::providers::ethereum::types::Bytes;
+use crate::providers::ethereum::revert::RevertError;
 use crate::providers::ethereum::{to_string_result, Call, CallOptions, EthereumProvider};
+use crate::utils::metrics;
 use anyhow::Result;
 use ethabi_contract::use_contract;
-use ethereum_types::{Address, U256};
+use ethereum_types::{Address, H256, U256};
 
 pub struct SafeAccount<'a> {
     pub eth_provider: &'a EthereumProvider<'a>,
@@ -34,7 +37,7 @@ impl Account for SafeAccount<'_> {
             data: Some(data.clone()),
             gas: None,
             gas_price: None,
-            from: Some(self.eth_provider.account()),
+            from: Some(self.eth_provider.account()?),
         };
         let options = CallOptions {
             block: "latest".to_string(),
@@ -43,7 +46,9 @@ impl Account for SafeAccount<'_> {
         let mut estimate = u64::from_str_radix(estimate_result.trim_start_matches("0x"), 16)?;
         estimate += estimate / 4;
         let mut success = false;
+        let mut iterations = 0;
         while !success && estimate < 20_000_000 {
+            iterations += 1;
             call.gas = Some(U256::from(estimate));
             let simulate_result = self.eth_provider.call(&call, &options)?;
             let bytes: Bytes = to_string_result(simulate_result)?.into();
@@ -53,9 +58,52 @@ impl Account for SafeAccount<'_> {
                 estimate = estimate * 2;
             }
         }
+        metrics::ESTIMATION_ITERATIONS.observe(iterations as f64);
         if !success {
             anyhow::bail!("Cannot estimate transaction with success");
         }
         Ok(Estimation { wallet, estimate: U256::from(estimate), data })
     }
 }
+
+impl SafeAccount<'_> {
+    /// Executes the estimated transaction with its gas limit on the latest block.
+    /// Returns why it would fail, errors of the node are returned as errors.
+    pub fn failure(&self, estimation: &Estimation) -> Result<Option<String>> {
+        let call = Call {
+            to: Some(estimation.wallet),
+            value: None,
+            data: Some(estimation.data.clone()),
+            gas: Some(estimation.estimate),
+            gas_price: None,
+            from: Some(self.eth_provider.account()?),
+        };
+        let options = CallOptions {
+            block: "latest".to_string(),
+        };
+        let result = match to_string_result(self.eth_provider.call(&call, &options)?) {
+            Ok(result) => result,
+            Err(err) => return match err.downcast_ref::<RevertError>() {
+                Some(revert) => Ok(Some(revert.to_string())),
+                None => Err(err)
+            }
+        };
+        let bytes: Bytes = result.into();
+        let success = safe::functions::exec_transaction::decode_output(&bytes.0)?;
+        Ok(if success { None } else { Some("Safe transaction would fail".to_string()) })
+    }
+}
+
+/// Hash of the Safe transaction and its signatures. It identifies a relay request independent of the current nonce
+/// of the Safe, so the request is also recognized after it was mined.
+pub fn payload_hash(payload: &ExecutePayload) -> H256 {
+    H256::from(keccak256(&ethabi::encode(&[
+        ethabi::Token::Address(payload.wallet),
+        ethabi::Token::Address(payload.transaction.to),
+        ethabi::Token::Uint(payload.transaction.value),
+        ethabi::Token::Bytes(payload.transaction.data.0.clone()),
+        ethabi::Token::Uint(U256::from(payload.transaction.operation)),
+        ethabi::Token::Uint(payload.transaction.safe_tx_gas),
+        ethabi::Token::Bytes(payload.signatures.0.clone()),
+    ])))
+}
@@ -2,16 +2,31 @@ use crate::models::{SafeTransaction};
 use crate::providers::ethereum::types::Bytes;
 use ethereum_types::{Address, U256};
 use ethabi_contract::use_contract;
+use anyhow::Result;
 use std::convert::TryInto;
 
 use_contract!(multisend, "./res/multisend.json");
 
-pub fn decode_multisend_bytes(bytes: &Vec<u8>) -> Vec<SafeTransaction> {
+/// Reads a length word, fails if it is longer than the data so that it can not overflow.
+fn length(word: &[u8], bytes: &[u8]) -> Result<usize> {
+    let length = U256::from(word);
+    anyhow::ensure!(length <= U256::from(bytes.len()), "Invalid multisend length {}", length);
+    Ok(length.as_usize())
+}
+
+/// End of a section of the data, fails if it is behind the end of the data.
+fn end(start: usize, length: usize, bytes: &[u8]) -> Result<usize> {
+    start.checked_add(length)
+        .filter(|end| *end <= bytes.len())
+        .ok_or_else(|| anyhow::anyhow!("Invalid multisend data length"))
+}
+
+/// Decodes the transactions of a multiSend call, fails for invalid data.
+pub fn try_decode_multisend_bytes(bytes: &[u8]) -> Result<Vec<SafeTransaction>> {
     let mut txs = vec![];
     let multisend_start = &multisend::functions::multi_send::encode_input(vec![])[0..36];
-    if multisend_start != &bytes[0..36] { panic!("Invalid multisend bytes") }
-    let multisend_data_length = U256::from(&bytes[36..68]).as_usize();
-    if multisend_data_length + 68 > bytes.len() { panic!("Invalid multisend data length") }
+    anyhow::ensure!(bytes.len() >= 68 && multisend_start == &bytes[0..36], "Invalid multisend bytes");
+    end(68, length(&bytes[36..68], bytes)?, bytes)?;
     let mut bytes_index = 68;
     // We should have always at least 85 bytes of data (check to avoid running into padding)
     while bytes_index + 85 <= bytes.len() {
@@ -29,10 +44,11 @@ pub fn decode_multisend_bytes(bytes: &Vec<u8>) -> Vec<SafeTransaction> {
             .try_into()
             .expect("32 bytes for data length");
         bytes_index += 32;
-        let data_length = U256::from(data_length_bytes).as_usize();
+        let data_length = length(data_length_bytes, bytes)?;
         log::debug!("data_length: {}", data_length);
-        let data_bytes: &[u8] = &bytes[bytes_index..bytes_index+data_length];
-        bytes_index += data_length;
+        let data_end = end(bytes_index, data_length, bytes)?;
+        let data_bytes: &[u8] = &bytes[bytes_index..data_end];
+        bytes_index = data_end;
         txs.push(SafeTransaction {
             operation: operation_bytes,
             to: Address::from(address_bytes),
@@ -41,5 +57,38 @@ pub fn decode_multisend_bytes(bytes: &Vec<u8>) -> Vec<SafeTransaction> {
             safe_tx_gas: U256::zero(),
         });
     }
-    txs
-}
\ No newline at end of file
+    Ok(txs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn multisend_bytes(data_length: [u8; 32]) -> Vec<u8> {
+        let mut transaction = vec![0u8];
+        transaction.extend_from_slice(&[1u8; 20]);
+        transaction.extend_from_slice(&[0u8; 32]);
+        transaction.extend_from_slice(&data_length);
+        transaction.extend_from_slice(&[0u8; 4]);
+        multisend::functions::multi_send::encode_input(transaction)
+    }
+
+    #[test]
+    fn decodes_multisend_transactions() {
+        let mut data_length = [0u8; 32];
+        data_length[31] = 4;
+        let transactions = try_decode_multisend_bytes(&multisend_bytes(data_length)).unwrap();
+        assert_eq!(transactions.len(), 1);
+        assert_eq!(transactions[0].to, Address::from([1u8; 20]));
+        assert_eq!(transactions[0].data, Bytes(vec![0u8; 4]));
+    }
+
+    #[test]
+    fn lengths_beyond_the_data_are_rejected() {
+        assert!(try_decode_multisend_bytes(&multisend_bytes([0xff; 32])).is_err());
+
+        let mut bytes = multisend_bytes([0u8; 32]);
+        bytes[36..68].copy_from_slice(&[0xff; 32]);
+        assert!(try_decode_multisend_bytes(&bytes).is_err());
+    }
+}
@@ -1,13 +1,36 @@
-use super::{Account, Estimation};
+use super::{check_payment_tx, Account, Estimation};
+use crate::models::SafeTransaction;
+use crate::providers::ethereum::hash::keccak256;
+use crate::providers::ethereum::revert::RevertError;
 use crate::providers::ethereum::types::Bytes;
-use crate::providers::ethereum::{to_string_result, Call, CallOptions, EthereumProvider};
+use crate::providers::ethereum::{to_string_result, to_typed_result, Call, CallOptions, EthereumProvider, Log};
+use crate::utils::errors::Rejection;
+use crate::utils::metrics;
 use serde::{Deserialize, Serialize};
 use anyhow::Result;
+use ethabi::{ParamType, Token};
 use ethabi_contract::use_contract;
 use ethereum_types::{Address, H256, U256};
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::sync::{Mutex, PoisonError};
 
+/// Blocks behind the latest block after which the vault events are not expected to be reorged anymore
+const CONFIRMATIONS: u64 = 12;
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Vault nonce up to a scanned block, newer blocks are scanned on the next nonce lookup
+#[derive(Debug, Clone, Copy)]
+struct NonceCheckpoint {
+    next_block: u64,
+    nonce: U256
+}
+
+lazy_static! {
+    /// Confirmed vault nonces by chain and vault
+    static ref NONCE_CHECKPOINTS: Mutex<HashMap<(u64, Address), NonceCheckpoint>> = Mutex::new(HashMap::new());
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct VaultConfigPayload {
     pub wallet: Address,
@@ -31,7 +54,60 @@ pub struct VaultConfigFee {
     pub hook: Bytes
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Transaction executed by the vault as part of a config update, used to pay the relayer.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VaultHook {
+    pub to: Address,
+    pub value: U256,
+    pub data: Bytes,
+    pub operation: u8
+}
+
+impl VaultHook {
+    pub fn encode(&self) -> Bytes {
+        Bytes(ethabi::encode(&[
+            Token::Address(self.to),
+            Token::Uint(self.value),
+            Token::Bytes(self.data.0.clone()),
+            Token::Uint(U256::from(self.operation))
+        ]))
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let parts = ethabi::decode(&[ParamType::Address, ParamType::Uint(256), ParamType::Bytes, ParamType::Uint(8)], bytes)?;
+        match parts.as_slice() {
+            [Token::Address(to), Token::Uint(value), Token::Bytes(data), Token::Uint(operation)] => {
+                anyhow::ensure!(*operation <= U256::one(), "Invalid hook operation {}", operation);
+                Ok(VaultHook {
+                    to: *to,
+                    value: *value,
+                    data: Bytes(data.clone()),
+                    operation: operation.low_u32() as u8
+                })
+            },
+            _ => anyhow::bail!("Could not decode hook")
+        }
+    }
+
+    /// Checks that the hook pays exactly the fee to the relayer.
+    pub fn check_payment(&self, relayer: Address, fee: U256) -> Result<()> {
+        check_payment_tx(relayer, &self.clone().into(), fee)
+    }
+}
+
+impl From<VaultHook> for SafeTransaction {
+    fn from(hook: VaultHook) -> Self {
+        SafeTransaction {
+            to: hook.to,
+            value: hook.value,
+            data: hook.data,
+            operation: hook.operation,
+            safe_tx_gas: U256::zero()
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct VaultPayload {
     pub wallet: Address,
@@ -39,7 +115,7 @@ pub struct VaultPayload {
     pub transaction: VaultTransaction
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct VaultTransaction {
     pub to: Address,
@@ -58,8 +134,141 @@ pub struct VaultAccount<'a> {
 use_contract!(stateless_vault, "./res/vault.json");
 
 impl VaultAccount<'_> {
+    pub fn tx_hash(&self, payload: &VaultPayload) -> Result<H256> {
+        let data = stateless_vault::functions::generate_tx_hash::encode_input(
+            payload.transaction.to,
+            payload.transaction.value,
+            payload.transaction.data.clone(),
+            payload.transaction.operation,
+            payload.transaction.min_available_gas,
+            payload.transaction.nonce,
+            payload.transaction.meta_hash
+        );
+        let result = self.eth_provider.view(payload.wallet, data)?;
+        Ok(stateless_vault::functions::generate_tx_hash::decode_output(&result.0)?)
+    }
+
+    pub fn config_change_hash(&self, payload: &VaultConfigPayload) -> Result<H256> {
+        // The vault hashes the signers packed, which pads each address to 32 bytes
+        let signers: Vec<u8> = payload.signers.iter()
+            .flat_map(|signer| ethabi::encode(&[Token::Address(*signer)]))
+            .collect();
+        let data = stateless_vault::functions::generate_config_change_hash::encode_input(
+            payload.implementation,
+            signers,
+            payload.threshold,
+            payload.signature_validator,
+            payload.request_guard,
+            payload.fallback_handler,
+            payload.hook.clone(),
+            payload.nonce,
+            payload.meta_hash
+        );
+        let result = self.eth_provider.view(payload.wallet, data)?;
+        Ok(stateless_vault::functions::generate_config_change_hash::decode_output(&result.0)?)
+    }
+
+    /// Nonce the vault expects next. The vault has no getter for it, so it is taken from the events of the executed
+    /// transactions and config updates, which emit the nonce they used, and of the configurations. Only the blocks
+    /// after the last confirmed block of the previous lookup are scanned, starting at the `vault_start_block`.
+    pub fn nonce(&self, wallet: Address) -> Result<U256> {
+        let chain = self.eth_provider.chain();
+        let key = (chain.chain_id, wallet);
+        let checkpoint = NONCE_CHECKPOINTS.lock().unwrap_or_else(PoisonError::into_inner).get(&key).copied();
+        let from_block = checkpoint.map_or(chain.vault_start_block, |checkpoint| checkpoint.next_block);
+        let latest_result = to_string_result(self.eth_provider.block_number()?)?;
+        let latest = u64::from_str_radix(latest_result.trim_start_matches("0x"), 16)?;
+        let confirmed = latest.saturating_sub(CONFIRMATIONS);
+
+        let configuration = H256::from(keccak256("Configuration(address,address[],uint256,address,address,address,uint256)"));
+        let topics = [
+            configuration,
+            H256::from(keccak256("ExecutionSuccess(uint256,bytes32)")),
+            H256::from(keccak256("ExecutionFailure(uint256,bytes32)")),
+            H256::from(keccak256("ConfigurationUpdate(uint256,bytes32)"))
+        ];
+        let logs: Vec<Log> = if from_block <= latest {
+            to_typed_result(self.eth_provider.logs(&wallet, &topics, from_block, latest)?)?
+        } else {
+            Vec::new()
+        };
+        let mut confirmed_nonce = checkpoint.map_or_else(U256::zero, |checkpoint| checkpoint.nonce);
+        let mut nonce = confirmed_nonce;
+        for log in logs {
+            let next = match log.topics.first() {
+                Some(topic) if *topic == configuration => {
+                    let params = [
+                        ParamType::Address, ParamType::Array(Box::new(ParamType::Address)), ParamType::Uint(256),
+                        ParamType::Address, ParamType::Address, ParamType::Address, ParamType::Uint(256)
+                    ];
+                    match ethabi::decode(&params, &log.data.0)?.pop() {
+                        Some(Token::Uint(current_nonce)) => current_nonce,
+                        _ => anyhow::bail!("Could not decode vault configuration")
+                    }
+                },
+                Some(_) => match log.topics.get(1) {
+                    Some(used_nonce) => U256::from_big_endian(used_nonce.as_bytes()) + 1,
+                    None => anyhow::bail!("Vault event without nonce")
+                },
+                None => continue
+            };
+            nonce = nonce.max(next);
+            if log.block_number.map_or(false, |block| block <= U256::from(confirmed)) {
+                confirmed_nonce = confirmed_nonce.max(next);
+            }
+        }
+        if confirmed >= from_block {
+            NONCE_CHECKPOINTS.lock().unwrap_or_else(PoisonError::into_inner)
+                .insert(key, NonceCheckpoint { next_block: confirmed + 1, nonce: confirmed_nonce });
+        }
+        Ok(nonce)
+    }
+
+    fn check_nonce(&self, wallet: Address, nonce: U256) -> Result<()> {
+        let vault_nonce = self.nonce(wallet)?;
+        anyhow::ensure!(
+            nonce == vault_nonce,
+            Rejection::unprocessable(format!("Invalid nonce {}, the vault expects nonce {}", nonce, vault_nonce))
+        );
+        Ok(())
+    }
+
+    /// Checks that the validation data is valid for the hash and the current nonce of the vault.
+    fn check_validation_data(&self, wallet: Address, hash: H256, nonce: U256, validation_data: &Bytes) -> Result<()> {
+        let data = stateless_vault::functions::check_validation_data::encode_input(
+            hash,
+            nonce,
+            validation_data.clone()
+        );
+        match self.eth_provider.view(wallet, data) {
+            Ok(result) => {
+                let (_, recovered_owners) = stateless_vault::functions::check_validation_data::decode_output(&result.0)?;
+                log::debug!("recovered owners: {}", recovered_owners);
+                Ok(())
+            },
+            Err(err) => match err.downcast_ref::<RevertError>() {
+                Some(revert) => anyhow::bail!(Rejection::unprocessable(
+                    format!("Invalid validation data for nonce {}: {}", nonce, revert.reason)
+                )),
+                None => Err(err)
+            }
+        }
+    }
+
+    pub fn validate(&self, payload: &VaultPayload) -> Result<()> {
+        self.check_nonce(payload.wallet, payload.transaction.nonce)?;
+        let hash = self.tx_hash(payload)?;
+        self.check_validation_data(payload.wallet, hash, payload.transaction.nonce, &payload.validation_data)
+    }
+
+    pub fn validate_config_update(&self, payload: &VaultConfigPayload) -> Result<()> {
+        self.check_nonce(payload.wallet, payload.nonce)?;
+        let hash = self.config_change_hash(payload)?;
+        self.check_validation_data(payload.wallet, hash, payload.nonce, &payload.validation_data)
+    }
 
     pub fn estimate_config_update(&self, payload: &VaultConfigPayload) -> Result<Estimation> {
+        self.validate_config_update(payload)?;
         let wallet = payload.wallet;
         let data: Bytes = stateless_vault::functions::update_config::encode_input(
             payload.implementation,
@@ -79,7 +288,7 @@ impl VaultAccount<'_> {
             data: Some(data.clone()),
             gas: None,
             gas_price: None,
-            from: Some(self.eth_provider.account()),
+            from: Some(self.eth_provider.account()?),
         };
         let options = CallOptions {
             block: "latest".to_string(),
@@ -95,7 +304,7 @@ impl VaultAccount<'_> {
 impl Account for VaultAccount<'_> {
     type Payload = VaultPayload;
     fn estimate(&self, payload: &Self::Payload) -> Result<Estimation> {
-        // TODO check wallet
+        self.validate(payload)?;
         let wallet = payload.wallet;
         let data: Bytes = stateless_vault::functions::exec_transaction::encode_input(
             payload.transaction.to,
@@ -114,7 +323,7 @@ impl Account for VaultAccount<'_> {
             data: Some(data.clone()),
             gas: None,
             gas_price: None,
-            from: Some(self.eth_provider.account()),
+            from: Some(self.eth_provider.account()?),
         };
         let options = CallOptions {
             block: "latest".to_string(),
@@ -123,7 +332,9 @@ impl Account for VaultAccount<'_> {
         let mut estimate = u64::from_str_radix(estimate_result.trim_start_matches("0x"), 16)?;
         estimate += estimate / 4;
         let mut success = false;
+        let mut iterations = 0;
         while !success && estimate < 20_000_000 {
+            iterations += 1;
             call.gas = Some(U256::from(estimate));
             let simulate_result = self.eth_provider.call(&call, &options)?;
             let bytes: Bytes = to_string_result(simulate_result)?.into();
@@ -133,9 +344,216 @@ impl Account for VaultAccount<'_> {
                 estimate = estimate * 2;
             }
         }
+        metrics::ESTIMATION_ITERATIONS.observe(iterations as f64);
         if !success {
             anyhow::bail!("Cannot estimate transaction with success");
         }
         Ok(Estimation { wallet, estimate: U256::from(estimate), data })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ChainConfig;
+    use crate::providers::ethereum::mock::{MockRequest, MockRpcServer};
+    use rustc_hex::ToHex;
+    use serde_json::{json, Value};
+    use std::sync::Arc;
+
+    const VAULT: [u8; 20] = [3u8; 20];
+
+    fn hex(bytes: &[u8]) -> String {
+        format!("0x{}", bytes.to_hex::<String>())
+    }
+
+    /// Block the vault server reports as latest, blocks up to 52 are confirmed
+    const LATEST_BLOCK: u64 = 64;
+
+    fn used_nonce_log(event: &str, used_nonce: u64, block: u64) -> Value {
+        json!({
+            "address": Address::from(VAULT),
+            "topics": [hex(&keccak256(event)), hex(H256::from_low_u64_be(used_nonce).as_bytes()), hex(&[0u8; 32])],
+            "data": "0x",
+            "blockNumber": format!("0x{:x}", block)
+        })
+    }
+
+    fn configuration_log(current_nonce: u64, block: u64) -> Value {
+        let data = ethabi::encode(&[
+            Token::Address(Address::zero()), Token::Array(vec![Token::Address(Address::from([4u8; 20]))]),
+            Token::Uint(U256::one()), Token::Address(Address::zero()), Token::Address(Address::zero()),
+            Token::Address(Address::zero()), Token::Uint(U256::from(current_nonce))
+        ]);
+        json!({
+            "address": Address::from(VAULT),
+            "topics": [hex(&keccak256("Configuration(address,address[],uint256,address,address,address,uint256)"))],
+            "data": hex(&data),
+            "blockNumber": format!("0x{:x}", block)
+        })
+    }
+
+    fn block_param(request: &MockRequest, name: &str) -> u64 {
+        let block = request.params[0][name].as_str().unwrap_or_default();
+        u64::from_str_radix(block.trim_start_matches("0x"), 16).unwrap()
+    }
+
+    /// Vault that executed transactions with nonce 0 and 1, the latter is not confirmed yet. All validation data
+    /// is valid.
+    fn vault_server() -> MockRpcServer {
+        MockRpcServer::start(|request| match request.method.as_str() {
+            "eth_blockNumber" => Ok(json!(format!("0x{:x}", LATEST_BLOCK))),
+            "eth_getLogs" => {
+                let (from, to) = (block_param(request, "fromBlock"), block_param(request, "toBlock"));
+                let logs: Vec<(u64, Value)> = vec![
+                    (16, configuration_log(0, 16)),
+                    (32, used_nonce_log("ExecutionSuccess(uint256,bytes32)", 0, 32)),
+                    (60, used_nonce_log("ExecutionFailure(uint256,bytes32)", 1, 60))
+                ];
+                Ok(json!(logs.into_iter()
+                    .filter(|(block, _)| from <= *block && *block <= to)
+                    .map(|(_, log)| log)
+                    .collect::<Vec<_>>()))
+            },
+            "eth_call" => {
+                let data = request.params[0]["data"].as_str().unwrap_or_default();
+                let check_validation_data = hex(&stateless_vault::functions::check_validation_data::encode_input(
+                    H256::zero(), U256::zero(), Vec::<u8>::new()
+                )[..4]);
+                if data.starts_with(&check_validation_data) {
+                    Ok(json!(hex(&ethabi::encode(&[Token::FixedBytes(vec![0u8; 32]), Token::Uint(U256::one())]))))
+                } else {
+                    Ok(json!(hex(&[1u8; 32])))
+                }
+            },
+            _ => Err(json!({ "code": -32601, "message": "Method not found" }))
+        })
+    }
+
+    fn payload(nonce: u64) -> VaultPayload {
+        VaultPayload {
+            wallet: Address::from(VAULT),
+            validation_data: Bytes(vec![0xaa]),
+            transaction: VaultTransaction {
+                to: Address::from([5u8; 20]),
+                value: U256::zero(),
+                data: Bytes(vec![]),
+                operation: 0,
+                min_available_gas: U256::zero(),
+                nonce: U256::from(nonce),
+                meta_hash: H256::zero()
+            }
+        }
+    }
+
+    #[test]
+    fn vault_nonce_follows_the_used_nonces() {
+        let server = vault_server();
+        let client = reqwest::blocking::Client::new();
+        let eth_provider = EthereumProvider::with_url(&client, server.url.clone());
+        let account = VaultAccount { eth_provider: &eth_provider };
+
+        assert_eq!(account.nonce(Address::from(VAULT)).unwrap(), U256::from(2));
+        assert!(account.validate(&payload(2)).is_ok());
+        assert_eq!(server.requests_for("eth_call").len(), 2);
+    }
+
+    #[test]
+    fn stale_and_future_nonces_are_rejected() {
+        let server = vault_server();
+        let client = reqwest::blocking::Client::new();
+        let eth_provider = EthereumProvider::with_url(&client, server.url.clone());
+        let account = VaultAccount { eth_provider: &eth_provider };
+
+        for nonce in &[1, 3] {
+            let err = account.validate(&payload(*nonce)).unwrap_err();
+            let rejection = err.downcast_ref::<Rejection>().unwrap();
+            assert_eq!(rejection.status, rocket::http::Status::UnprocessableEntity);
+            assert!(rejection.reason.contains("expects nonce 2"));
+        }
+        assert!(server.requests_for("eth_call").is_empty());
+    }
+
+    #[test]
+    fn vault_nonce_only_scans_new_blocks() {
+        let server = vault_server();
+        let client = reqwest::blocking::Client::new();
+        let chain = ChainConfig {
+            chain_id: 1011,
+            rpc_url: server.url.clone(),
+            vault_start_block: 5,
+            ..(*crate::config::test_config().default_chain()).clone()
+        };
+        let eth_provider = EthereumProvider::for_chain(&client, Arc::new(chain));
+        let account = VaultAccount { eth_provider: &eth_provider };
+
+        assert_eq!(account.nonce(Address::from(VAULT)).unwrap(), U256::from(2));
+        // The unconfirmed execution is scanned again
+        assert_eq!(account.nonce(Address::from(VAULT)).unwrap(), U256::from(2));
+
+        let scans = server.requests_for("eth_getLogs");
+        assert_eq!(scans.len(), 2);
+        assert_eq!((block_param(&scans[0], "fromBlock"), block_param(&scans[0], "toBlock")), (5, LATEST_BLOCK));
+        assert_eq!(block_param(&scans[1], "fromBlock"), LATEST_BLOCK - CONFIRMATIONS + 1);
+    }
+
+    fn payment_hook(to: Address, fee: U256) -> VaultHook {
+        VaultHook {
+            to,
+            value: fee,
+            data: Bytes(vec![]),
+            operation: 0
+        }
+    }
+
+    #[test]
+    fn hook_round_trip() {
+        let hook = VaultHook {
+            to: Address::from_low_u64_be(0x42),
+            value: U256::from(1_000_000),
+            data: Bytes(vec![0xca, 0xfe]),
+            operation: 1
+        };
+        assert_eq!(VaultHook::decode(&hook.encode().0).unwrap(), hook);
+    }
+
+    #[test]
+    fn hook_matches_abi_encoding() {
+        let relayer = Address::from_low_u64_be(0x42);
+        let fee = U256::from(1_000_000);
+        let encoded = ethabi::encode(
+            &[Token::Address(relayer), Token::Uint(fee), Token::Bytes(vec![]), Token::Uint(U256::zero())]
+        );
+        assert_eq!(payment_hook(relayer, fee).encode().0, encoded);
+    }
+
+    #[test]
+    fn hook_decode_rejects_invalid_input() {
+        assert!(VaultHook::decode(&[]).is_err());
+        let invalid_operation = ethabi::encode(
+            &[Token::Address(Address::zero()), Token::Uint(U256::zero()), Token::Bytes(vec![]), Token::Uint(U256::from(2))]
+        );
+        assert!(VaultHook::decode(&invalid_operation).is_err());
+    }
+
+    #[test]
+    fn hook_payment_validation() {
+        let relayer = Address::from_low_u64_be(0x42);
+        let fee = U256::from(1_000_000);
+        assert!(payment_hook(relayer, fee).check_payment(relayer, fee).is_ok());
+
+        let wrong_recipient = payment_hook(Address::from_low_u64_be(0x43), fee);
+        assert!(wrong_recipient.check_payment(relayer, fee).is_err());
+
+        let wrong_amount = payment_hook(relayer, fee - 1);
+        assert!(wrong_amount.check_payment(relayer, fee).is_err());
+
+        let mut with_data = payment_hook(relayer, fee);
+        with_data.data = Bytes(vec![0x00]);
+        assert!(with_data.check_payment(relayer, fee).is_err());
+
+        let mut delegate_call = payment_hook(relayer, fee);
+        delegate_call.operation = 1;
+        assert!(delegate_call.check_payment(relayer, fee).is_err());
+    }
+}
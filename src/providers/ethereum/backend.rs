@@ -0,0 +1,752 @@
+//! Backends that submit the transactions of the relayer to the network.
+
+use super::hash::keccak256;
+use super::key::PrivateKey;
+use super::transaction::Transaction;
+use super::types::Bytes;
+use super::{
+    to_result_from_output, to_string_result, to_typed_result, Bundle, EthereumProvider, ItxBalance, ItxTransaction,
+    KeyType, TransactionInfo, TransactionReceipt
+};
+use crate::config::{ChainConfig, Config};
+use crate::utils::errors::Rejection;
+use crate::utils::logging;
+use crate::utils::metrics;
+use anyhow::Result;
+use ethereum_types::{Address, H256, U256};
+use lazy_static::lazy_static;
+use rocket::http::Status;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+use thiserror::Error;
+
+lazy_static! {
+    /// Next nonce of the relayer account by chain and account, `None` until it is read from the node
+    static ref NONCES: Mutex<HashMap<(u64, Address), Arc<Mutex<Option<U256>>>>> = Mutex::new(HashMap::new());
+}
+
+/// Transaction that should be submitted by the relayer.
+pub struct RelayTransaction {
+    pub to: Address,
+    pub data: Bytes,
+    pub gas: U256,
+    /// Fee in wei the relayer receives for the transaction, the gas costs of direct and bundle relays may not exceed
+    /// it. Relays without a fee in the native coin use 0 and are not checked.
+    pub fee: U256
+}
+
+pub trait RelayBackend {
+    /// Submits the transaction and returns the hash that can be used to track it.
+    fn relay(&self, eth_provider: &EthereumProvider, tx: &RelayTransaction) -> Result<String>;
+
+    fn backend_type(&self) -> BackendType;
+}
+
+/// Transaction signed by the relayer, passed to the checkpoint of the provider before it is broadcast,
+/// so that a relay that is retried can be resumed without signing the payload again.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SignedRelay {
+    pub backend: BackendType,
+    /// Hash of the signed transaction, for ITX the hash of the signed relay request
+    pub hash: H256,
+    /// Nonce of the relayer account, not used by ITX
+    pub nonce: Option<U256>,
+    /// Signed transaction, not used by ITX
+    pub raw: Option<Bytes>
+}
+
+/// Called with the hash of a transaction that the backend gave up and the reason, so that the relay can be marked
+/// as failed. Only bundles are given up, transactions in the public mempool stay pending.
+pub type OnDropped = Arc<dyn Fn(&str, &str) + Send + Sync>;
+
+/// Relay that was signed before and cannot be resumed without signing it again
+#[derive(Debug, Error)]
+#[error("{0}")]
+pub struct NotResubmitted(pub String);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum BackendType {
+    Direct,
+    Itx,
+    Bundle
+}
+
+impl FromStr for BackendType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "direct" => Ok(BackendType::Direct),
+            "itx" => Ok(BackendType::Itx),
+            "bundle" => Ok(BackendType::Bundle),
+            _ => anyhow::bail!("Unknown relay backend {}", s)
+        }
+    }
+}
+
+/// Groups of routes that can be configured to use a different backend and can be paused.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub enum RouteGroup {
+    Safe,
+    Vault,
+    Deployment,
+    Generic,
+    Batch
+}
+
+impl RouteGroup {
+    pub const ALL: [RouteGroup; 5] =
+        [RouteGroup::Safe, RouteGroup::Vault, RouteGroup::Deployment, RouteGroup::Generic, RouteGroup::Batch];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RouteGroup::Safe => "safe",
+            RouteGroup::Vault => "vault",
+            RouteGroup::Deployment => "deployment",
+            RouteGroup::Generic => "generic",
+            RouteGroup::Batch => "batch"
+        }
+    }
+
+    pub fn default_backend(&self) -> BackendType {
+        match self {
+            // Generic relaying depends on the ITX refunders
+            RouteGroup::Generic => BackendType::Itx,
+            _ => BackendType::Direct
+        }
+    }
+}
+
+/// Resolves the backend for a route group, a backend configured for the tenant takes precedence.
+pub fn backend_type(config: &Config, group: RouteGroup, tenant: Option<&str>) -> Result<BackendType> {
+    Ok(config.relay_backend_type(group.name(), tenant).unwrap_or_else(|| group.default_backend()))
+}
+
+pub fn relay_backend(
+    config: &Config,
+    chain: &ChainConfig,
+    backend_type: BackendType,
+    on_dropped: OnDropped
+) -> Result<Box<dyn RelayBackend>> {
+    Ok(match backend_type {
+        BackendType::Direct => Box::new(DirectBackend),
+        BackendType::Itx => Box::new(ItxBackend),
+        BackendType::Bundle => Box::new(BundleBackend {
+            url: chain.bundle_rpc_url.clone()
+                .ok_or_else(|| anyhow::anyhow!("No bundle endpoint configured for chain {}", chain.chain_id))?,
+            retry_blocks: config.bundle_retry_blocks,
+            on_dropped
+        })
+    })
+}
+
+fn pending_nonce(eth_provider: &EthereumProvider) -> Result<U256> {
+    let nonce_result = to_string_result(eth_provider.nonce()?)?;
+    Ok(U256::from(u64::from_str_radix(nonce_result.trim_start_matches("0x"), 16)?))
+}
+
+/// Submits with the next nonce of the relayer account of the chain. Submissions of the same account are serialized
+/// and the nonce is counted locally, so concurrent relays never read the same `pending` nonce. After a failed
+/// submission the nonce is read from the node again.
+fn with_next_nonce<T, F>(eth_provider: &EthereumProvider, submit: F) -> Result<T>
+where
+    F: FnOnce(U256) -> Result<T>
+{
+    let key = (eth_provider.chain().chain_id, eth_provider.account()?);
+    let slot = NONCES.lock().unwrap_or_else(PoisonError::into_inner).entry(key).or_default().clone();
+    let mut next = slot.lock().unwrap_or_else(PoisonError::into_inner);
+    let nonce = match *next {
+        Some(nonce) => nonce,
+        None => pending_nonce(eth_provider)?
+    };
+    let result = submit(nonce);
+    *next = if result.is_ok() { Some(nonce + 1) } else { None };
+    result
+}
+
+/// Forgets the nonce that is counted locally for the relayer account, so that the next relay reads the nonce from
+/// the node again and reuses the nonce of a transaction that was given up.
+fn reset_nonce(eth_provider: &EthereumProvider) -> Result<()> {
+    let key = (eth_provider.chain().chain_id, eth_provider.account()?);
+    let slot = NONCES.lock().unwrap_or_else(PoisonError::into_inner).get(&key).cloned();
+    if let Some(slot) = slot {
+        *slot.lock().unwrap_or_else(PoisonError::into_inner) = None;
+    }
+    Ok(())
+}
+
+/// Gas price the node suggests for the transaction. The relay is refused if the gas price is above the
+/// `MAX_GAS_PRICE` of the chain or if the fee of the relay doesn't cover the gas costs.
+fn relay_gas_price(eth_provider: &EthereumProvider, tx: &RelayTransaction) -> Result<U256> {
+    let gas_price_result = to_string_result(eth_provider.gas_price()?)?;
+    let gas_price = U256::from(u64::from_str_radix(gas_price_result.trim_start_matches("0x"), 16)?);
+    if let Some(max_gas_price) = eth_provider.chain().max_gas_price {
+        if gas_price > max_gas_price {
+            log::warn!("Gas price {} is above the maximum of {}", gas_price, max_gas_price);
+            anyhow::bail!(Rejection::new(Status::ServiceUnavailable, "Relaying is currently not available"));
+        }
+    }
+    let costs = tx.gas.saturating_mul(gas_price);
+    anyhow::ensure!(
+        tx.fee.is_zero() || costs <= tx.fee,
+        Rejection::unprocessable(format!("Fee of {} wei does not cover the gas costs of {} wei", tx.fee, costs))
+    );
+    Ok(gas_price)
+}
+
+fn signable_transaction(tx: &RelayTransaction, nonce: U256, gas_price: U256) -> Transaction<'_> {
+    Transaction {
+        to: Some(tx.to),
+        value: U256::zero(),
+        data: &tx.data,
+        gas: tx.gas,
+        gas_price,
+        nonce,
+    }
+}
+
+/// Signs the transaction with the next nonce and passes it to the checkpoint of the provider.
+fn sign_with_checkpoint(eth_provider: &EthereumProvider, backend: BackendType, tx: &Transaction) -> Result<Bytes> {
+    let signed = eth_provider.sign_transaction(tx)?;
+    eth_provider.checkpoint(&SignedRelay {
+        backend,
+        hash: H256::from(keccak256(&signed.0)),
+        nonce: Some(tx.nonce),
+        raw: Some(signed.clone())
+    })?;
+    Ok(signed)
+}
+
+/// Result of a relay that was signed before, without signing it again. Transactions the node knows are returned as
+/// they are, unknown direct transactions are broadcast again as they were signed. Bundles and ITX relays are not
+/// resubmitted.
+pub fn resume(eth_provider: &EthereumProvider, signed: &SignedRelay) -> Result<String> {
+    let hash = format!("{:?}", signed.hash);
+    let raw = match (&signed.raw, signed.backend) {
+        (Some(raw), BackendType::Direct) | (Some(raw), BackendType::Bundle) => raw,
+        _ => anyhow::bail!(NotResubmitted(format!("Relay {} was submitted before and is not submitted again", hash)))
+    };
+    let known: Option<TransactionInfo> = to_typed_result(eth_provider.transaction(&signed.hash)?)?;
+    if known.is_some() {
+        return Ok(hash);
+    }
+    anyhow::ensure!(
+        signed.backend == BackendType::Direct,
+        NotResubmitted(format!("Bundle transaction {} was not included and is not submitted again", hash))
+    );
+    log::info!("Broadcast signed transaction {} again", hash);
+    to_string_result(eth_provider.send_raw_transaction(raw)?)
+}
+
+/// Signs the transaction with the relayer key and broadcasts it via `eth_sendRawTransaction`.
+pub struct DirectBackend;
+
+impl RelayBackend for DirectBackend {
+    fn relay(&self, eth_provider: &EthereumProvider, tx: &RelayTransaction) -> Result<String> {
+        let gas_price = relay_gas_price(eth_provider, tx)?;
+        with_next_nonce(eth_provider, |nonce| {
+            let transaction = signable_transaction(tx, nonce, gas_price);
+            let signed = sign_with_checkpoint(eth_provider, BackendType::Direct, &transaction)?;
+            to_string_result(eth_provider.send_raw_transaction(&signed)?)
+        })
+    }
+
+    fn backend_type(&self) -> BackendType {
+        BackendType::Direct
+    }
+}
+
+/// Relays the transaction via ITX, the gas is paid from the ITX gas tank.
+pub struct ItxBackend;
+
+pub fn gas_tank_balance(eth_provider: &EthereumProvider) -> Result<U256> {
+    let result: ItxBalance = to_typed_result(eth_provider.itx_balance()?)?;
+    Ok(U256::from_dec_str(&result.balance)?)
+}
+
+/// Refuses to relay if the ITX gas tank is below the configured minimum balance.
+fn check_gas_tank(eth_provider: &EthereumProvider) -> Result<()> {
+    let min_balance = eth_provider.chain().itx_min_balance;
+    if min_balance == U256::zero() {
+        return Ok(());
+    }
+    let balance = gas_tank_balance(eth_provider)?;
+    if balance < min_balance {
+        log::warn!("ITX gas tank balance {} is below the minimum of {}", balance, min_balance);
+        anyhow::bail!(Rejection::new(Status::ServiceUnavailable, "Relaying is currently not available"));
+    }
+    Ok(())
+}
+
+impl RelayBackend for ItxBackend {
+    fn relay(&self, eth_provider: &EthereumProvider, tx: &RelayTransaction) -> Result<String> {
+        check_gas_tank(eth_provider)?;
+        let itx_tx_hash = keccak256(&ethabi::encode(&[
+            ethabi::Token::Address(tx.to),
+            ethabi::Token::Bytes(tx.data.0.clone()),
+            ethabi::Token::Uint(tx.gas),
+            ethabi::Token::Uint(U256::from(eth_provider.chain().chain_id))
+        ]));
+        let signature = eth_provider.sign(&itx_tx_hash, KeyType::Itx)?;
+        log::debug!("itx account: {}", eth_provider.itx_account()?);
+        let mut signature_vec = [signature.r, signature.s].concat();
+        signature_vec.push((signature.v + 27) as u8);
+        let itx_tx = ItxTransaction {
+            to: tx.to,
+            data: tx.data.clone(),
+            gas: tx.gas.to_string()
+        };
+        eth_provider.checkpoint(&SignedRelay {
+            backend: BackendType::Itx, hash: H256::from(itx_tx_hash), nonce: None, raw: None
+        })?;
+        to_string_result(eth_provider.itx_relay(&itx_tx, &Bytes(signature_vec))?)
+    }
+
+    fn backend_type(&self) -> BackendType {
+        BackendType::Itx
+    }
+}
+
+/// Signs the transaction with the relayer key and submits it as a bundle via `eth_sendBundle`
+/// to a private builder endpoint, so that it does not show up in the public mempool.
+/// Bundles are only valid for a single block, so the bundle is resubmitted for the following blocks
+/// until the transaction is included or `retry_blocks` blocks have been targeted. A transaction that is not included
+/// is given up, its nonce is used again by the next relay.
+pub struct BundleBackend {
+    pub url: String,
+    pub retry_blocks: u64,
+    pub on_dropped: OnDropped
+}
+
+fn block_number(eth_provider: &EthereumProvider) -> Result<u64> {
+    let block_result = to_string_result(eth_provider.block_number()?)?;
+    Ok(u64::from_str_radix(block_result.trim_start_matches("0x"), 16)?)
+}
+
+fn submit_bundle(eth_provider: &EthereumProvider, url: &str, signer: &PrivateKey, signed: &Bytes, block: u64) -> Result<()> {
+    let bundle = Bundle {
+        txs: vec![signed.clone()],
+        block_number: format!("{:#x}", block)
+    };
+    log::debug!("Submit bundle for block {}", block);
+    to_result_from_output(eth_provider.send_bundle(url, &bundle, signer)?)?;
+    Ok(())
+}
+
+/// Waits until the target block has been mined and returns the current block.
+fn wait_for_block(eth_provider: &EthereumProvider, target: u64, poll_interval: Duration) -> Result<u64> {
+    loop {
+        let current = block_number(eth_provider)?;
+        if current >= target {
+            return Ok(current);
+        }
+        thread::sleep(poll_interval);
+    }
+}
+
+/// Resubmits the bundle for the next block until the transaction is included.
+/// Returns the block of the transaction, or `None` if it was not included in time.
+fn resubmit_until_included(
+    eth_provider: &EthereumProvider,
+    url: &str,
+    signer: &PrivateKey,
+    signed: &Bytes,
+    first_block: u64,
+    retry_blocks: u64,
+    poll_interval: Duration
+) -> Result<Option<U256>> {
+    let tx_hash = H256::from(keccak256(&signed.0));
+    let last_block = first_block + retry_blocks.max(1) - 1;
+    let mut target = first_block;
+    loop {
+        let current = wait_for_block(eth_provider, target, poll_interval)?;
+        let receipt: Option<TransactionReceipt> = to_typed_result(eth_provider.transaction_receipt(&tx_hash)?)?;
+        if let Some(receipt) = receipt.filter(|receipt| receipt.block_number.is_some()) {
+            metrics::observe_receipt(&receipt);
+            return Ok(receipt.block_number);
+        }
+        target = current + 1;
+        if target > last_block {
+            return Ok(None);
+        }
+        submit_bundle(eth_provider, url, signer, signed, target)?;
+    }
+}
+
+impl RelayBackend for BundleBackend {
+    fn relay(&self, eth_provider: &EthereumProvider, tx: &RelayTransaction) -> Result<String> {
+        let signer_key = eth_provider.chain().bundle_signer_key_bytes.as_ref()
+            .ok_or_else(|| anyhow::anyhow!("No bundle signer key configured"))?;
+        let signer = PrivateKey::from_hex_str(signer_key)?;
+        let gas_price = relay_gas_price(eth_provider, tx)?;
+        let (signed, first_block) = with_next_nonce(eth_provider, |nonce| {
+            let transaction = signable_transaction(tx, nonce, gas_price);
+            let signed = sign_with_checkpoint(eth_provider, BackendType::Bundle, &transaction)?;
+            let first_block = block_number(eth_provider)? + 1;
+            submit_bundle(eth_provider, &self.url, &signer, &signed, first_block)?;
+            Ok((signed, first_block))
+        })?;
+
+        let tx_hash = Bytes(keccak256(&signed.0).to_vec()).to_string();
+        let client = eth_provider.client().clone();
+        let chain = eth_provider.chain_config();
+        let url = self.url.clone();
+        let retry_blocks = self.retry_blocks;
+        let watched_hash = tx_hash.clone();
+        let request_id = eth_provider.request_id().map(|id| id.to_string());
+        let on_dropped = self.on_dropped.clone();
+        thread::spawn(move || {
+            logging::set_request_id(request_id.clone());
+            let eth_provider = EthereumProvider::for_chain(&client, chain).with_request_id(request_id);
+            let reason = match resubmit_until_included(
+                &eth_provider, &url, &signer, &signed, first_block, retry_blocks, Duration::from_secs(1)
+            ) {
+                Ok(Some(block)) => {
+                    log::info!("Bundle transaction {} included in block {}", watched_hash, block);
+                    return;
+                },
+                Ok(None) => format!("Bundle transaction was not included within {} blocks", retry_blocks),
+                Err(err) => format!("Resubmitting the bundle transaction failed: {}", err)
+            };
+            log::warn!("Giving up bundle transaction {}: {}", watched_hash, reason);
+            if let Err(err) = reset_nonce(&eth_provider) {
+                log::error!("Could not reset the nonce after giving up {}: {:?}", watched_hash, err);
+            }
+            on_dropped(&watched_hash, &reason);
+        });
+        Ok(tx_hash)
+    }
+
+    fn backend_type(&self) -> BackendType {
+        BackendType::Bundle
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::ethereum::mock::MockRpcServer;
+    use serde_json::{json, Value};
+    use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+    #[test]
+    fn gas_tank_balance_is_decimal() {
+        let server = MockRpcServer::start(|request| match request.method.as_str() {
+            "relay_getBalance" => Ok(json!({ "balance": "1000000000000000000" })),
+            _ => Err(json!({ "code": -32601, "message": "Method not found" }))
+        });
+        let client = reqwest::blocking::Client::new();
+        let eth_provider = EthereumProvider::with_url(&client, server.url.clone());
+
+        assert_eq!(gas_tank_balance(&eth_provider).unwrap(), U256::exp10(18));
+    }
+
+    /// Chain with its own relayer nonce, so that the tests don't share the nonce of the default chain
+    fn chain(chain_id: u64, rpc_url: &str) -> Arc<ChainConfig> {
+        let default_chain = crate::config::test_config().default_chain();
+        Arc::new(ChainConfig { chain_id, rpc_url: rpc_url.to_string(), ..(*default_chain).clone() })
+    }
+
+    fn node_stub(pending_nonce: u64, known: bool) -> MockRpcServer {
+        MockRpcServer::start(move |request| match request.method.as_str() {
+            "eth_getTransactionCount" => Ok(json!(format!("{:#x}", pending_nonce))),
+            // 1 gwei
+            "eth_gasPrice" => Ok(json!("0x3b9aca00")),
+            "eth_sendRawTransaction" => Ok(json!(format!("{:?}", H256::from(keccak256(&Bytes::from(
+                request.params[0].as_str().unwrap().to_string()
+            ).0))))),
+            "eth_getTransactionByHash" if known => Ok(json!({ "hash": request.params[0], "gasPrice": "0x1" })),
+            "eth_getTransactionByHash" => Ok(Value::Null),
+            _ => Err(json!({ "code": -32601, "message": "Method not found" }))
+        })
+    }
+
+    fn relay_transaction() -> RelayTransaction {
+        RelayTransaction {
+            to: Address::from([1u8; 20]),
+            data: Bytes(vec![1, 2, 3]),
+            gas: U256::from(100_000),
+            fee: U256::zero()
+        }
+    }
+
+    fn rejection_status(err: anyhow::Error) -> Status {
+        err.downcast_ref::<Rejection>().unwrap().status
+    }
+
+    #[test]
+    fn relays_use_the_gas_price_of_the_node() {
+        let server = node_stub(3, false);
+        let client = reqwest::blocking::Client::new();
+        let eth_provider = EthereumProvider::for_chain(&client, chain(1008, &server.url));
+        let gas_price = U256::from(1_000_000_000);
+
+        assert_eq!(relay_gas_price(&eth_provider, &relay_transaction()).unwrap(), gas_price);
+        let capped = |max_gas_price: U256| {
+            Arc::new(ChainConfig { max_gas_price: Some(max_gas_price), ..(*chain(1008, &server.url)).clone() })
+        };
+        let below = EthereumProvider::for_chain(&client, capped(gas_price - 1));
+        let err = relay_gas_price(&below, &relay_transaction()).unwrap_err();
+        assert_eq!(rejection_status(err), Status::ServiceUnavailable);
+        let at_cap = EthereumProvider::for_chain(&client, capped(gas_price));
+        assert!(relay_gas_price(&at_cap, &relay_transaction()).is_ok());
+    }
+
+    #[test]
+    fn fees_have_to_cover_the_gas_costs() {
+        let server = node_stub(3, false);
+        let client = reqwest::blocking::Client::new();
+        let eth_provider = EthereumProvider::for_chain(&client, chain(1009, &server.url));
+        // 100k gas at 1 gwei
+        let costs = U256::from(100_000_000_000_000u64);
+
+        let covered = RelayTransaction { fee: costs, ..relay_transaction() };
+        assert!(relay_gas_price(&eth_provider, &covered).is_ok());
+        let too_low = RelayTransaction { fee: costs - 1, ..relay_transaction() };
+        let err = DirectBackend.relay(&eth_provider, &too_low).unwrap_err();
+        assert_eq!(rejection_status(err), Status::UnprocessableEntity);
+        assert!(server.requests_for("eth_getTransactionCount").is_empty());
+        assert!(server.requests_for("eth_sendRawTransaction").is_empty());
+        DirectBackend.relay(&eth_provider, &covered).unwrap();
+    }
+
+    #[test]
+    fn nonces_are_counted_locally_and_resynced_after_errors() {
+        let server = node_stub(5, false);
+        let client = reqwest::blocking::Client::new();
+        let eth_provider = EthereumProvider::for_chain(&client, chain(1001, &server.url));
+
+        assert_eq!(with_next_nonce(&eth_provider, Ok).unwrap(), U256::from(5));
+        assert_eq!(with_next_nonce(&eth_provider, Ok).unwrap(), U256::from(6));
+        assert_eq!(server.requests_for("eth_getTransactionCount").len(), 1);
+
+        assert!(with_next_nonce(&eth_provider, |_| -> Result<()> { anyhow::bail!("Broadcast failed") }).is_err());
+        assert_eq!(with_next_nonce(&eth_provider, Ok).unwrap(), U256::from(5));
+        assert_eq!(server.requests_for("eth_getTransactionCount").len(), 2);
+    }
+
+    #[test]
+    fn concurrent_relays_get_different_nonces() {
+        let server = node_stub(0, false);
+        let chain = chain(1002, &server.url);
+        let workers: Vec<_> = (0..4).map(|_| {
+            let chain = chain.clone();
+            thread::spawn(move || {
+                let client = reqwest::blocking::Client::new();
+                let eth_provider = EthereumProvider::for_chain(&client, chain);
+                (0..5).map(|_| with_next_nonce(&eth_provider, Ok).unwrap().low_u64()).collect::<Vec<u64>>()
+            })
+        }).collect();
+        let mut nonces: Vec<u64> = workers.into_iter().flat_map(|worker| worker.join().unwrap()).collect();
+        nonces.sort_unstable();
+        assert_eq!(nonces, (0..20).collect::<Vec<u64>>());
+    }
+
+    #[test]
+    fn signed_transaction_is_checkpointed_before_broadcast() {
+        let server = node_stub(7, false);
+        let client = reqwest::blocking::Client::new();
+        let checkpoints = Mutex::new(vec![]);
+        let checkpoint = |signed: &SignedRelay| -> Result<()> {
+            checkpoints.lock().unwrap().push(signed.clone());
+            Ok(())
+        };
+        let eth_provider = EthereumProvider::for_chain(&client, chain(1003, &server.url)).with_checkpoint(&checkpoint);
+
+        let hash = DirectBackend.relay(&eth_provider, &relay_transaction()).unwrap();
+
+        let checkpoints = checkpoints.lock().unwrap();
+        assert_eq!(checkpoints.len(), 1);
+        assert_eq!(format!("{:?}", checkpoints[0].hash), hash);
+        assert_eq!(checkpoints[0].nonce, Some(U256::from(7)));
+        assert_eq!(server.requests_for("eth_sendRawTransaction")[0].params[0], json!(checkpoints[0].raw));
+    }
+
+    #[test]
+    fn failed_checkpoint_prevents_broadcast() {
+        let server = node_stub(7, false);
+        let client = reqwest::blocking::Client::new();
+        let checkpoint = |_: &SignedRelay| -> Result<()> { anyhow::bail!("Redis is down") };
+        let eth_provider = EthereumProvider::for_chain(&client, chain(1004, &server.url)).with_checkpoint(&checkpoint);
+
+        assert!(DirectBackend.relay(&eth_provider, &relay_transaction()).is_err());
+        assert!(server.requests_for("eth_sendRawTransaction").is_empty());
+    }
+
+    fn signed(backend: BackendType) -> SignedRelay {
+        let raw = Bytes(vec![0xf8, 0x01]);
+        SignedRelay { backend, hash: H256::from(keccak256(&raw.0)), nonce: Some(U256::one()), raw: Some(raw) }
+    }
+
+    #[test]
+    fn known_transactions_are_resumed_without_broadcast() {
+        let server = node_stub(0, true);
+        let client = reqwest::blocking::Client::new();
+        let eth_provider = EthereumProvider::for_chain(&client, chain(1005, &server.url));
+
+        let hash = resume(&eth_provider, &signed(BackendType::Direct)).unwrap();
+        assert_eq!(hash, format!("{:?}", signed(BackendType::Direct).hash));
+        assert!(resume(&eth_provider, &signed(BackendType::Bundle)).is_ok());
+        assert!(server.requests_for("eth_sendRawTransaction").is_empty());
+        assert!(server.requests_for("eth_getTransactionCount").is_empty());
+    }
+
+    #[test]
+    fn unknown_transactions_are_broadcast_as_signed() {
+        let server = node_stub(0, false);
+        let client = reqwest::blocking::Client::new();
+        let eth_provider = EthereumProvider::for_chain(&client, chain(1006, &server.url));
+
+        let hash = resume(&eth_provider, &signed(BackendType::Direct)).unwrap();
+        assert_eq!(hash, format!("{:?}", signed(BackendType::Direct).hash));
+        assert_eq!(server.requests_for("eth_sendRawTransaction")[0].params[0], json!("0xf801"));
+
+        let bundle = resume(&eth_provider, &signed(BackendType::Bundle)).unwrap_err();
+        assert!(bundle.downcast_ref::<NotResubmitted>().is_some());
+        let itx = SignedRelay { backend: BackendType::Itx, nonce: None, raw: None, ..signed(BackendType::Itx) };
+        assert!(resume(&eth_provider, &itx).unwrap_err().downcast_ref::<NotResubmitted>().is_some());
+        assert_eq!(server.requests_for("eth_sendRawTransaction").len(), 1);
+    }
+
+    #[test]
+    fn parse_backend_type() {
+        assert_eq!("direct".parse::<BackendType>().unwrap(), BackendType::Direct);
+        assert_eq!("ITX".parse::<BackendType>().unwrap(), BackendType::Itx);
+        assert_eq!("bundle".parse::<BackendType>().unwrap(), BackendType::Bundle);
+        assert!("mempool".parse::<BackendType>().is_err());
+    }
+
+    const SIGNER_KEY: &str = "0x4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+
+    /// Stub that plays node and builder, a new block is mined on every `eth_blockNumber` call and
+    /// the transaction is included once `include_after` bundles have been received.
+    fn builder_stub(include_after: Option<usize>) -> MockRpcServer {
+        let block = Arc::new(AtomicU64::new(100));
+        let bundles = Arc::new(AtomicUsize::new(0));
+        MockRpcServer::start(move |request| match request.method.as_str() {
+            "eth_getTransactionCount" => Ok(json!("0x9")),
+            "eth_gasPrice" => Ok(json!("0x3b9aca00")),
+            "eth_sendRawTransaction" => Ok(json!("0x01")),
+            "eth_blockNumber" => Ok(json!(format!("{:#x}", block.fetch_add(1, Ordering::SeqCst)))),
+            "eth_sendBundle" => {
+                bundles.fetch_add(1, Ordering::SeqCst);
+                Ok(json!({ "bundleHash": "0x01" }))
+            },
+            "eth_getTransactionReceipt" => match include_after {
+                Some(count) if bundles.load(Ordering::SeqCst) >= count => Ok(json!({
+                    "transactionHash": request.params[0],
+                    "blockNumber": format!("{:#x}", block.load(Ordering::SeqCst) - 1),
+                    "gasUsed": "0x5208",
+                    "status": "0x1",
+                    "logs": []
+                })),
+                _ => Ok(Value::Null)
+            },
+            _ => Err(json!({ "code": -32601, "message": "Method not found" }))
+        })
+    }
+
+    fn target_blocks(server: &MockRpcServer) -> Vec<String> {
+        server.requests_for("eth_sendBundle").iter()
+            .map(|request| request.params[0]["blockNumber"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn bundle_is_signed() {
+        let server = builder_stub(None);
+        let client = reqwest::blocking::Client::new();
+        let eth_provider = EthereumProvider::with_url(&client, server.url.clone());
+        let signer = PrivateKey::from_hex_str(SIGNER_KEY).unwrap();
+
+        submit_bundle(&eth_provider, &server.url, &signer, &Bytes(vec![1, 2, 3]), 101).unwrap();
+
+        let request = &server.requests_for("eth_sendBundle")[0];
+        assert_eq!(request.params[0], json!({ "txs": ["0x010203"], "blockNumber": "0x65" }));
+        let header = request.header("X-Flashbots-Signature").unwrap();
+        let parts: Vec<&str> = header.split(':').collect();
+        assert_eq!(parts[0], format!("{:?}", signer.public_address()));
+        // 0x prefixed r, s and v
+        assert_eq!(parts[1].len(), 2 + 65 * 2);
+    }
+
+    #[test]
+    fn bundle_is_resubmitted_until_included() {
+        let server = builder_stub(Some(3));
+        let client = reqwest::blocking::Client::new();
+        let eth_provider = EthereumProvider::with_url(&client, server.url.clone());
+        let signer = PrivateKey::from_hex_str(SIGNER_KEY).unwrap();
+        let signed = Bytes(vec![1, 2, 3]);
+
+        submit_bundle(&eth_provider, &server.url, &signer, &signed, 101).unwrap();
+        let included = resubmit_until_included(
+            &eth_provider, &server.url, &signer, &signed, 101, 5, Duration::from_millis(1)
+        ).unwrap();
+
+        assert!(included.is_some());
+        assert_eq!(target_blocks(&server), vec!["0x65", "0x66", "0x67"]);
+    }
+
+    #[test]
+    fn bundle_resubmission_stops_after_retry_blocks() {
+        let server = builder_stub(None);
+        let client = reqwest::blocking::Client::new();
+        let eth_provider = EthereumProvider::with_url(&client, server.url.clone());
+        let signer = PrivateKey::from_hex_str(SIGNER_KEY).unwrap();
+        let signed = Bytes(vec![1, 2, 3]);
+
+        submit_bundle(&eth_provider, &server.url, &signer, &signed, 101).unwrap();
+        let included = resubmit_until_included(
+            &eth_provider, &server.url, &signer, &signed, 101, 3, Duration::from_millis(1)
+        ).unwrap();
+
+        assert_eq!(included, None);
+        assert_eq!(target_blocks(&server), vec!["0x65", "0x66", "0x67"]);
+    }
+
+    #[test]
+    fn nonce_of_dropped_bundle_is_reused() {
+        let server = builder_stub(None);
+        let client = reqwest::blocking::Client::new();
+        let chain = Arc::new(ChainConfig {
+            bundle_signer_key_bytes: Some(SIGNER_KEY.to_string()),
+            ..(*chain(1007, &server.url)).clone()
+        });
+        let nonces = Mutex::new(vec![]);
+        let checkpoint = |signed: &SignedRelay| -> Result<()> {
+            nonces.lock().unwrap().push(signed.nonce.unwrap());
+            Ok(())
+        };
+        let eth_provider = EthereumProvider::for_chain(&client, chain).with_checkpoint(&checkpoint);
+        let dropped = Arc::new(Mutex::new(vec![]));
+        let on_dropped: OnDropped = {
+            let dropped = dropped.clone();
+            Arc::new(move |hash: &str, reason: &str| {
+                dropped.lock().unwrap().push((hash.to_string(), reason.to_string()))
+            })
+        };
+        let backend = BundleBackend { url: server.url.clone(), retry_blocks: 2, on_dropped };
+
+        let hash = backend.relay(&eth_provider, &relay_transaction()).unwrap();
+        for _ in 0..100 {
+            if !dropped.lock().unwrap().is_empty() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        assert_eq!(*dropped.lock().unwrap(), vec![
+            (hash, "Bundle transaction was not included within 2 blocks".to_string())
+        ]);
+
+        DirectBackend.relay(&eth_provider, &relay_transaction()).unwrap();
+        assert_eq!(*nonces.lock().unwrap(), vec![U256::from(9), U256::from(9)]);
+        assert_eq!(server.requests_for("eth_getTransactionCount").len(), 2);
+    }
+}
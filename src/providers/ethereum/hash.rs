@@ -1,6 +1,7 @@
 // https://github.com/gnosis/ethcontract-rs/blob/main/common/src/hash.rs
 //! Keccak256 hash utilities.
 
+use ethereum_types::{Address, H256};
 use tiny_keccak::{Hasher, Keccak};
 
 /// Perform a Keccak256 hash of data and return its 32-byte result.
@@ -34,6 +35,18 @@ where
     selector
 }
 
+/// Calculate the address of a contract deployed with `CREATE2` as per
+/// [EIP-1014](https://eips.ethereum.org/EIPS/eip-1014).
+pub fn create2_address<B>(deployer: Address, salt: H256, init_code: B) -> Address
+where
+    B: AsRef<[u8]>,
+{
+    let hash = keccak256(
+        [&[0xffu8][..], deployer.as_bytes(), salt.as_bytes(), &keccak256(init_code)[..]].concat(),
+    );
+    Address::from_slice(&hash[12..])
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -63,4 +76,18 @@ mod tests {
     fn revert_function_signature() {
         assert_eq!(function_selector("Error(string)"), [0x08, 0xc3, 0x79, 0xa0]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn create2_addresses() {
+        // test vectors retrieved from
+        // https://eips.ethereum.org/EIPS/eip-1014#examples
+        assert_eq!(
+            create2_address(Address::zero(), H256::zero(), [0x00]),
+            "4D1A2e2bB4F88F0250f26Ffff098B0b30B26BF38".parse().unwrap(),
+        );
+        assert_eq!(
+            create2_address("deadbeef00000000000000000000000000000000".parse().unwrap(), H256::zero(), [0x00]),
+            "B928f69Bb1D91Cd65274e3c79d8986362984fDA3".parse().unwrap(),
+        );
+    }
+}
@@ -20,7 +20,7 @@ use zeroize::{DefaultIsZeroes, Zeroizing};
 #[error("invalid private key")]
 pub struct InvalidPrivateKey;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Signature {
     pub r: [u8; 32],
@@ -28,6 +28,13 @@ pub struct Signature {
     pub v: u64
 }
 
+/// Only prints the recovery id, so that signatures do not end up in the logs.
+impl Debug for Signature {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Signature").field("v", &self.v).finish()
+    }
+}
+
 impl From<Secp256k1Error> for InvalidPrivateKey {
     fn from(err: Secp256k1Error) -> Self {
         match err {
@@ -0,0 +1,113 @@
+//! Minimal JSON-RPC server that can be used to test code that talks to a node.
+
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// JSON-RPC request received by the mock server.
+#[derive(Debug, Clone)]
+pub struct MockRequest {
+    pub headers: Vec<(String, String)>,
+    pub method: String,
+    pub params: Value,
+}
+
+impl MockRequest {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Result returned by the handler of the mock server, errors are returned as JSON-RPC errors.
+pub type MockResult = Result<Value, Value>;
+
+pub struct MockRpcServer {
+    pub url: String,
+    requests: Arc<Mutex<Vec<MockRequest>>>,
+}
+
+impl MockRpcServer {
+    /// Starts a server on a random local port that answers every request with the result of the handler.
+    pub fn start<F>(handler: F) -> Self
+    where
+        F: Fn(&MockRequest) -> MockResult + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("could not bind mock server");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(vec![]));
+        let received = requests.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => handle_connection(stream, &handler, &received),
+                    Err(_) => break,
+                }
+            }
+        });
+        MockRpcServer { url, requests }
+    }
+
+    pub fn requests(&self) -> Vec<MockRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    pub fn requests_for(&self, method: &str) -> Vec<MockRequest> {
+        self.requests().into_iter().filter(|request| request.method == method).collect()
+    }
+}
+
+fn handle_connection<F>(stream: TcpStream, handler: &F, requests: &Mutex<Vec<MockRequest>>)
+where
+    F: Fn(&MockRequest) -> MockResult,
+{
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut headers = vec![];
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+            return;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(index) = line.find(':') {
+            headers.push((line[..index].trim().to_string(), line[index + 1..].trim().to_string()));
+        }
+    }
+    let content_length = headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.parse().ok())
+        .unwrap_or(0);
+    let mut body = vec![0u8; content_length];
+    reader.read_exact(&mut body).unwrap();
+    let call: Value = serde_json::from_slice(&body).unwrap();
+
+    let request = MockRequest {
+        headers,
+        method: call["method"].as_str().unwrap_or_default().to_string(),
+        params: call["params"].clone(),
+    };
+    requests.lock().unwrap().push(request.clone());
+    let response = match handler(&request) {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": call["id"], "result": result }),
+        Err(error) => json!({ "jsonrpc": "2.0", "id": call["id"], "error": error }),
+    }
+    .to_string();
+
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.len(),
+        response
+    )
+    .unwrap();
+    stream.flush().unwrap();
+}
@@ -1,18 +1,27 @@
+pub mod backend;
 pub mod hash;
 pub mod key;
+#[cfg(test)]
+pub mod mock;
+pub mod revert;
 pub mod types;
 pub mod transaction;
 
 use std::str;
-use crate::config::{default_key_bytes, itx_key_bytes, base_rpc_url};
+use std::sync::Arc;
+use crate::config::{ChainConfig, DEFAULT_KEY, ITX_KEY};
 use crate::utils::context::Context;
+use crate::utils::logging::REQUEST_ID_HEADER;
+use crate::utils::metrics;
 use types::Bytes;
-use ethereum_types::{Address, U256};
+use ethereum_types::{Address, H256, U256};
 use anyhow::Result;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_json;
 use jsonrpc_core as rpc;
 use transaction::Transaction;
+use backend::SignedRelay;
 
 #[derive(Serialize, Debug)]
 #[serde(rename_all = "camelCase")]
@@ -39,8 +48,83 @@ pub struct ItxTransaction {
     pub gas: String
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Log {
+    pub address: Address,
+    pub topics: Vec<H256>,
+    pub data: Bytes,
+    /// Not set for pending logs
+    #[serde(default)]
+    pub block_number: Option<U256>
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TransactionReceipt {
+    pub transaction_hash: H256,
+    pub block_number: Option<U256>,
+    pub gas_used: U256,
+    /// Not returned by nodes that predate EIP-1559
+    pub effective_gas_price: Option<U256>,
+    pub status: Option<U256>,
+    pub logs: Vec<Log>
+}
+
+impl TransactionReceipt {
+    pub fn is_success(&self) -> bool {
+        self.status == Some(U256::one())
+    }
+}
+
+/// Fields of `eth_getTransactionByHash` used by the relayer
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct TransactionInfo {
+    pub hash: H256,
+    pub gas_price: U256
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ItxBroadcast {
+    pub broadcast_time: Option<String>,
+    pub eth_tx_hash: H256,
+    pub gas_price: Option<String>
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ItxTransactionStatus {
+    pub received_time: Option<String>,
+    #[serde(default)]
+    pub broadcasts: Vec<ItxBroadcast>
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ItxBalance {
+    /// Balance of the gas tank in wei as a decimal string
+    pub balance: String
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Bundle {
+    pub txs: Vec<Bytes>,
+    /// Hex encoded number of the block the bundle should be included in
+    pub block_number: String
+}
+
 pub struct EthereumProvider<'p> {
-    client: &'p reqwest::blocking::Client
+    client: &'p reqwest::blocking::Client,
+    /// Chain the provider relays on, with the contracts, fees and keys used for it
+    chain: Arc<ChainConfig>,
+    rpc_url: String,
+    /// Forwarded to the node as `X-Request-Id`, to correlate the calls with the request that triggered them
+    request_id: Option<String>,
+    /// Called with every transaction the relayer signed, before it is broadcast
+    checkpoint: Option<&'p dyn Fn(&SignedRelay) -> Result<()>>
 }
 
 pub enum KeyType {
@@ -50,24 +134,68 @@ pub enum KeyType {
 
 impl EthereumProvider<'_> {
     pub fn new<'p>(context: &'p Context) -> EthereumProvider<'p> {
+        EthereumProvider::for_chain(context.client(), context.chain())
+            .with_request_id(Some(context.request_id()))
+    }
+
+    pub fn for_chain<'p>(client: &'p reqwest::blocking::Client, chain: Arc<ChainConfig>) -> EthereumProvider<'p> {
         EthereumProvider {
-            client: context.client()
+            client,
+            rpc_url: chain.rpc_url.clone(),
+            chain,
+            request_id: None,
+            checkpoint: None
         }
     }
 
-    fn get_key(&self, key_type: KeyType) -> key::PrivateKey {
-        key::PrivateKey::from_hex_str(match key_type {
-            KeyType::Default => default_key_bytes(),
-            KeyType::Itx => itx_key_bytes()
-        }).unwrap()
+    /// Provider for the default chain of the test configuration that uses a different node
+    #[cfg(test)]
+    pub fn with_url<'p>(client: &'p reqwest::blocking::Client, rpc_url: String) -> EthereumProvider<'p> {
+        let chain = crate::config::test_config().default_chain();
+        EthereumProvider { rpc_url, ..EthereumProvider::for_chain(client, chain) }
+    }
+
+    pub fn chain(&self) -> &ChainConfig {
+        &self.chain
     }
 
-    pub fn account(&self) -> Address {
-        self.get_key(KeyType::Default).public_address()
+    pub fn chain_config(&self) -> Arc<ChainConfig> {
+        self.chain.clone()
+    }
+
+    pub fn with_request_id(self, request_id: Option<String>) -> Self {
+        EthereumProvider { request_id, ..self }
+    }
+
+    pub fn request_id(&self) -> Option<&str> {
+        self.request_id.as_deref()
+    }
+
+    pub fn checkpoint(&self, signed: &SignedRelay) -> Result<()> {
+        match self.checkpoint {
+            Some(checkpoint) => checkpoint(signed),
+            None => Ok(())
+        }
     }
 
-    pub fn itx_account(&self) -> Address {
-        self.get_key(KeyType::Itx).public_address()
+    /// Fails if the key is not configured for the chain, e.g. the ITX key on a chain without ITX.
+    fn get_key(&self, key_type: KeyType) -> Result<key::PrivateKey> {
+        let (name, key_bytes) = match key_type {
+            KeyType::Default => (DEFAULT_KEY, Some(&self.chain.default_key_bytes)),
+            KeyType::Itx => (ITX_KEY, self.chain.itx_key_bytes.as_ref())
+        };
+        let key_bytes = key_bytes
+            .ok_or_else(|| anyhow::anyhow!("{} is not configured for chain {}", name, self.chain.chain_id))?;
+        key::PrivateKey::from_hex_str(key_bytes)
+            .map_err(|_| anyhow::anyhow!("{} of chain {} is not a valid private key", name, self.chain.chain_id))
+    }
+
+    pub fn account(&self) -> Result<Address> {
+        Ok(self.get_key(KeyType::Default)?.public_address())
+    }
+
+    pub fn itx_account(&self) -> Result<Address> {
+        Ok(self.get_key(KeyType::Itx)?.public_address())
     }
 
     pub fn call(
@@ -75,28 +203,45 @@ impl EthereumProvider<'_> {
         transaction: &'_ Call,
         option: &'_ CallOptions
     ) -> Result<rpc::Output> {
-        single_rpc_call(self.client, build_request(
+        self.rpc_call(build_request(
             1, "eth_call", vec![serde_json::to_value(&transaction)?, serde_json::to_value(&option.block)?]
         ))
     }
 
+    /// Calls a view function of the contract at `to` from the relayer account on the latest block.
+    pub fn view(&self, to: Address, data: Vec<u8>) -> Result<Bytes> {
+        let call = Call {
+            to: Some(to),
+            value: None,
+            data: Some(data.into()),
+            gas: None,
+            gas_price: None,
+            from: Some(self.account()?),
+        };
+        let options = CallOptions {
+            block: "latest".to_string(),
+        };
+        Ok(to_string_result(self.call(&call, &options)?)?.into())
+    }
+
     pub fn estimate_gas(
         &self,
         transaction: &'_ Call,
         option: &'_ CallOptions
     ) -> Result<rpc::Output> {
-        single_rpc_call(self.client, build_request(
+        self.rpc_call(build_request(
             1, "eth_estimateGas", vec![serde_json::to_value(&transaction)?, serde_json::to_value(&option.block)?]
         ))
     }
 
-    pub fn execute(
-        &self,
-        transaction: &'_ Transaction
-    ) -> Result<rpc::Output> {
-        let signed = transaction.sign(&self.get_key(KeyType::Default), None);
-        single_rpc_call(self.client, build_request(
-            1, "eth_sendRawTransaction", vec![serde_json::to_value(&signed)?]
+    /// Signs with the chain id of the provider (EIP-155), so that the transaction cannot be replayed on other chains.
+    pub fn sign_transaction(&self, transaction: &'_ Transaction) -> Result<Bytes> {
+        Ok(transaction.sign(&self.get_key(KeyType::Default)?, Some(self.chain.chain_id)))
+    }
+
+    pub fn send_raw_transaction(&self, signed: &Bytes) -> Result<rpc::Output> {
+        self.rpc_call(build_request(
+            1, "eth_sendRawTransaction", vec![serde_json::to_value(signed)?]
         ))
     }
 
@@ -105,33 +250,164 @@ impl EthereumProvider<'_> {
         message: &[u8],
         key_type: KeyType
     ) -> Result<key::Signature> {
-        let encoded_message = [format!("\x19Ethereum Signed Message:\n{}", message.len()).as_bytes(), message].concat();
-        log::debug!("encoded_message: {:?}", encoded_message);
-        let hash = hash::keccak256(&encoded_message);
-        Ok(self.get_key(key_type).sign(&hash))
+        Ok(personal_sign(&self.get_key(key_type)?, message))
+    }
+
+    pub fn code(&self, address: &Address) -> Result<rpc::Output> {
+        self.rpc_call(build_request(
+            1, "eth_getCode", vec![serde_json::to_value(address)?, serde_json::to_value("latest")?]
+        ))
+    }
+
+    pub fn transaction_receipt(&self, hash: &H256) -> Result<rpc::Output> {
+        self.rpc_call(build_request(
+            1, "eth_getTransactionReceipt", vec![serde_json::to_value(hash)?]
+        ))
+    }
+
+    pub fn transaction(&self, hash: &H256) -> Result<rpc::Output> {
+        self.rpc_call(build_request(
+            1, "eth_getTransactionByHash", vec![serde_json::to_value(hash)?]
+        ))
+    }
+
+    /// Logs of the contract in the block range (inclusive), with one of the topics as first topic
+    pub fn logs(&self, address: &Address, topics: &[H256], from_block: u64, to_block: u64) -> Result<rpc::Output> {
+        let filter = serde_json::json!({
+            "address": address,
+            "fromBlock": format!("0x{:x}", from_block),
+            "toBlock": format!("0x{:x}", to_block),
+            "topics": [topics]
+        });
+        self.rpc_call(build_request(1, "eth_getLogs", vec![filter]))
+    }
+
+    pub fn balance(&self, address: &Address) -> Result<rpc::Output> {
+        self.rpc_call(build_request(
+            1, "eth_getBalance", vec![serde_json::to_value(address)?, serde_json::to_value("latest")?]
+        ))
+    }
+
+    pub fn chain_id(&self) -> Result<rpc::Output> {
+        self.rpc_call(build_request(1, "eth_chainId", vec![]))
+    }
+
+    pub fn gas_price(&self) -> Result<rpc::Output> {
+        self.rpc_call(build_request(1, "eth_gasPrice", vec![]))
+    }
+
+    pub fn block_number(&self) -> Result<rpc::Output> {
+        self.rpc_call(build_request(1, "eth_blockNumber", vec![]))
     }
 
     pub fn nonce(&self) -> Result<rpc::Output> {
-        single_rpc_call(self.client, build_request(
-            1, "eth_getTransactionCount", vec![serde_json::to_value(self.account())?, serde_json::to_value("pending")?]
+        self.transaction_count("pending")
+    }
+
+    pub fn transaction_count(&self, block: &str) -> Result<rpc::Output> {
+        self.rpc_call(build_request(
+            1, "eth_getTransactionCount", vec![serde_json::to_value(self.account()?)?, serde_json::to_value(block)?]
         ))
     }
 
     pub fn itx_relay(&self, tx: &ItxTransaction, signature: &Bytes) -> Result<rpc::Output> {
-        single_rpc_call(self.client, build_request(
+        self.rpc_call(build_request(
             1, "relay_sendTransaction", vec![serde_json::to_value(tx)?, serde_json::to_value(signature)?]
         ))
     }
+
+    pub fn itx_transaction_status(&self, relay_hash: &H256) -> Result<rpc::Output> {
+        self.rpc_call(build_request(
+            1, "relay_getTransactionStatus", vec![serde_json::to_value(relay_hash)?]
+        ))
+    }
+
+    pub fn itx_balance(&self) -> Result<rpc::Output> {
+        self.rpc_call(build_request(
+            1, "relay_getBalance", vec![serde_json::to_value(self.itx_account()?)?]
+        ))
+    }
+
+    /// Sends a bundle to a builder, the request is authenticated with the `X-Flashbots-Signature` header.
+    pub fn send_bundle(&self, url: &str, bundle: &Bundle, signer: &key::PrivateKey) -> Result<rpc::Output> {
+        let call = build_request(1, "eth_sendBundle", vec![serde_json::to_value(bundle)?]);
+        let body = serde_json::to_string(&call)?;
+        let body_hash = Bytes(hash::keccak256(body.as_bytes()).to_vec()).to_string();
+        let signature = personal_sign(signer, body_hash.as_bytes());
+        let mut signature_vec = [signature.r, signature.s].concat();
+        signature_vec.push((signature.v + 27) as u8);
+        observe_rpc_call(&call, || {
+            let response = with_request_id(self.client.post(url), self.request_id())
+                .header("Content-Type", "application/json")
+                .header("X-Flashbots-Signature", format!("{:?}:{}", signer.public_address(), Bytes(signature_vec)))
+                .body(body)
+                .send()?.json::<rpc::Response>()?;
+            single_output(response)
+        })
+    }
+
+    pub fn client(&self) -> &reqwest::blocking::Client {
+        self.client
+    }
+
+    fn rpc_call(&self, call: rpc::Call) -> Result<rpc::Output> {
+        single_rpc_call(self.client, &self.rpc_url, self.request_id(), call)
+    }
+}
+
+impl<'p> EthereumProvider<'p> {
+    pub fn with_checkpoint(self, checkpoint: &'p dyn Fn(&SignedRelay) -> Result<()>) -> Self {
+        EthereumProvider { checkpoint: Some(checkpoint), ..self }
+    }
+}
+
+fn with_request_id(builder: reqwest::blocking::RequestBuilder, request_id: Option<&str>) -> reqwest::blocking::RequestBuilder {
+    match request_id {
+        Some(request_id) => builder.header(REQUEST_ID_HEADER, request_id),
+        None => builder
+    }
+}
+
+fn single_rpc_call(
+    client: &'_ reqwest::blocking::Client,
+    url: &str,
+    request_id: Option<&str>,
+    call: rpc::Call
+) -> Result<rpc::Output> {
+    observe_rpc_call(&call, || {
+        let response = with_request_id(client.post(url), request_id).json(&call).send()?.json::<rpc::Response>()?;
+        single_output(response)
+    })
 }
 
-fn single_rpc_call(client: &'_ reqwest::blocking::Client, call: rpc::Call) -> Result<rpc::Output> {
-    let response = client.post(&base_rpc_url()).json(&call).send()?.json::<rpc::Response>()?;
+/// Tracks the latency and failures of a JSON-RPC call by method.
+fn observe_rpc_call<F: FnOnce() -> Result<rpc::Output>>(call: &rpc::Call, perform: F) -> Result<rpc::Output> {
+    let method = match call {
+        rpc::Call::MethodCall(method_call) => method_call.method.as_str(),
+        _ => "unknown"
+    };
+    let timer = metrics::RPC_DURATION.with_label_values(&[method]).start_timer();
+    let result = perform();
+    timer.observe_duration();
+    if result.is_err() {
+        metrics::RPC_ERRORS.with_label_values(&[method]).inc();
+    }
+    result
+}
+
+fn single_output(response: rpc::Response) -> Result<rpc::Output> {
     match response {
         rpc::Response::Single(output) => Ok(output),
         _ => anyhow::bail!("Expected single, got batch."),
     }
 }
 
+/// Signs the message with the `\x19Ethereum Signed Message` prefix, like the `personal_sign` RPC method.
+pub fn personal_sign(key: &key::PrivateKey, message: &[u8]) -> key::Signature {
+    let encoded_message = [format!("\x19Ethereum Signed Message:\n{}", message.len()).as_bytes(), message].concat();
+    key.sign(&hash::keccak256(&encoded_message))
+}
+
 pub fn to_string_result(output: rpc::Output) -> Result<String> {
     let resp = to_result_from_output(output)?;
     match resp {
@@ -140,11 +416,18 @@ pub fn to_string_result(output: rpc::Output) -> Result<String> {
     }
 }
 
+pub fn to_typed_result<T: DeserializeOwned>(output: rpc::Output) -> Result<T> {
+    Ok(serde_json::from_value(to_result_from_output(output)?)?)
+}
+
 /// Parse `rpc::Output` into `Result`.
 pub fn to_result_from_output(output: rpc::Output) -> Result<rpc::Value> {
     match output {
         rpc::Output::Success(success) => Ok(success.result),
-        rpc::Output::Failure(error) => anyhow::bail!("Json RPC call failed! {:?}", error),
+        rpc::Output::Failure(failure) => match revert::revert_from_rpc_error(&failure.error) {
+            Some(revert) => Err(revert.into()),
+            None => anyhow::bail!("Json RPC call failed! {:?}", failure),
+        },
     }
 }
 
@@ -156,4 +439,37 @@ fn build_request(id: usize, method: &str, params: Vec<rpc::Value>) -> rpc::Call
         params: rpc::Params::Array(params),
         id: rpc::Id::Num(id as u64),
     })
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transactions_are_signed_with_the_chain_id() {
+        // Example of https://github.com/ethereum/EIPs/blob/master/EIPS/eip-155.md
+        let chain = ChainConfig {
+            chain_id: 1,
+            default_key_bytes: "0x4646464646464646464646464646464646464646464646464646464646464646".to_string(),
+            ..(*crate::config::test_config().default_chain()).clone()
+        };
+        let client = reqwest::blocking::Client::new();
+        let eth_provider = EthereumProvider::for_chain(&client, Arc::new(chain));
+        let data = Bytes(vec![]);
+        let transaction = Transaction {
+            nonce: U256::from(9),
+            gas_price: U256::from(20_000_000_000u64),
+            gas: U256::from(21_000),
+            to: Some(Address::from([0x35; 20])),
+            value: U256::exp10(18),
+            data: &data
+        };
+
+        assert_eq!(
+            eth_provider.sign_transaction(&transaction).unwrap().to_string(),
+            "0xf86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a76400008025a028ef61340bd\
+             939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276a067cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b\
+             297fb1966a3b6d83"
+        );
+    }
+}
@@ -0,0 +1,207 @@
+//! Decoding of revert data returned by failed `eth_call` and `eth_estimateGas` requests.
+
+use super::hash::{function_selector, H32};
+use super::types::Bytes;
+use ethabi::{ParamType, Token};
+use ethereum_types::U256;
+use jsonrpc_core as rpc;
+use rustc_hex::FromHex;
+use serde::Serialize;
+use std::fmt;
+use thiserror::Error;
+
+/// Known error codes used in the `Error(string)` reasons of the Safe contracts.
+const SAFE_ERROR_CODES: &[(&str, &str)] = &[
+    ("GS000", "Could not finish initialization"),
+    ("GS001", "Threshold needs to be defined"),
+    ("GS010", "Not enough gas to execute Safe transaction"),
+    ("GS011", "Could not pay gas costs with ether"),
+    ("GS012", "Could not pay gas costs with token"),
+    ("GS013", "Safe transaction failed when gasPrice and safeTxGas were 0"),
+    ("GS020", "Signatures data too short"),
+    ("GS021", "Invalid contract signature location: inside static part"),
+    ("GS022", "Invalid contract signature location: length not present"),
+    ("GS023", "Invalid contract signature location: data not complete"),
+    ("GS024", "Invalid contract signature provided"),
+    ("GS025", "Hash has not been approved"),
+    ("GS026", "Invalid owner provided"),
+];
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase", tag = "type")]
+pub enum RevertReason {
+    /// Revert with a reason string (`Error(string)`)
+    Error { message: String },
+    /// Failed assertion or arithmetic error (`Panic(uint256)`)
+    Panic { code: U256 },
+    /// Revert data that could not be decoded
+    Unknown { selector: Option<Bytes> },
+}
+
+impl fmt::Display for RevertReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RevertReason::Error { message } => match safe_error_description(message) {
+                Some(description) => write!(f, "{} ({})", message, description),
+                None => write!(f, "{}", message),
+            },
+            RevertReason::Panic { code } => write!(f, "panic {:#x} ({})", code, panic_description(*code)),
+            RevertReason::Unknown { selector: Some(selector) } => write!(f, "unknown error {}", selector),
+            RevertReason::Unknown { selector: None } => write!(f, "no reason provided"),
+        }
+    }
+}
+
+/// Error returned when a simulated call reverted.
+#[derive(Debug, Error)]
+#[error("Execution reverted: {reason}")]
+pub struct RevertError {
+    pub reason: RevertReason,
+    pub data: Bytes,
+}
+
+impl RevertError {
+    pub fn from_data(data: Bytes) -> Self {
+        RevertError { reason: decode_revert_data(&data.0), data }
+    }
+}
+
+/// Decode the revert data of a failed call.
+pub fn decode_revert_data(data: &[u8]) -> RevertReason {
+    if data.len() < 4 {
+        return RevertReason::Unknown { selector: None };
+    }
+    let selector = &data[0..4];
+    if selector == error_selector() {
+        if let Ok(tokens) = ethabi::decode(&[ParamType::String], &data[4..]) {
+            if let Some(Token::String(message)) = tokens.into_iter().next() {
+                return RevertReason::Error { message };
+            }
+        }
+    } else if selector == panic_selector() {
+        if let Ok(tokens) = ethabi::decode(&[ParamType::Uint(256)], &data[4..]) {
+            if let Some(Token::Uint(code)) = tokens.into_iter().next() {
+                return RevertReason::Panic { code };
+            }
+        }
+    }
+    RevertReason::Unknown { selector: Some(Bytes(selector.to_vec())) }
+}
+
+/// Extract the revert data from a JSON RPC error.
+///
+/// Geth returns the data as a hex string, OpenEthereum and Nethermind prefix it with `Reverted `.
+/// Nodes that do not return any data only report the reason as part of the message.
+pub fn revert_from_rpc_error(error: &rpc::Error) -> Option<RevertError> {
+    if let Some(rpc::Value::String(data)) = &error.data {
+        let hex = data.trim_start_matches("Reverted ");
+        if let Some(Ok(bytes)) = hex.strip_prefix("0x").map(|it| it.from_hex::<Vec<u8>>()) {
+            return Some(RevertError::from_data(Bytes(bytes)));
+        }
+    }
+    let message = error.message.strip_prefix("execution reverted")?;
+    let reason = match message.strip_prefix(": ") {
+        Some(message) => RevertReason::Error { message: message.to_string() },
+        None => RevertReason::Unknown { selector: None },
+    };
+    Some(RevertError { reason, data: Bytes::default() })
+}
+
+fn error_selector() -> H32 {
+    function_selector("Error(string)")
+}
+
+fn panic_selector() -> H32 {
+    function_selector("Panic(uint256)")
+}
+
+fn safe_error_description(message: &str) -> Option<&'static str> {
+    SAFE_ERROR_CODES.iter().find(|(code, _)| *code == message).map(|(_, description)| *description)
+}
+
+// https://docs.soliditylang.org/en/latest/control-structures.html#panic-via-assert-and-error-via-require
+fn panic_description(code: U256) -> &'static str {
+    if code > U256::from(u8::MAX) {
+        return "unknown panic code";
+    }
+    match code.low_u32() {
+        0x01 => "assertion failed",
+        0x11 => "arithmetic overflow or underflow",
+        0x12 => "division or modulo by zero",
+        0x21 => "invalid enum value",
+        0x22 => "invalid storage byte array encoding",
+        0x31 => "pop on empty array",
+        0x32 => "array index out of bounds",
+        0x41 => "out of memory",
+        0x51 => "call to uninitialized function",
+        _ => "unknown panic code",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode_with_selector(selector: H32, tokens: &[Token]) -> Vec<u8> {
+        [&selector[..], &ethabi::encode(tokens)].concat()
+    }
+
+    #[test]
+    fn decode_error_string() {
+        let data = encode_with_selector(error_selector(), &[Token::String("Invalid signatures provided".to_string())]);
+        assert_eq!(
+            decode_revert_data(&data),
+            RevertReason::Error { message: "Invalid signatures provided".to_string() }
+        );
+    }
+
+    #[test]
+    fn decode_panic_code() {
+        let data = encode_with_selector(panic_selector(), &[Token::Uint(U256::from(0x11))]);
+        let reason = decode_revert_data(&data);
+        assert_eq!(reason, RevertReason::Panic { code: U256::from(0x11) });
+        assert_eq!(reason.to_string(), "panic 0x11 (arithmetic overflow or underflow)");
+    }
+
+    #[test]
+    fn decode_unknown_selector() {
+        assert_eq!(
+            decode_revert_data(&[0xde, 0xad, 0xbe, 0xef]),
+            RevertReason::Unknown { selector: Some(Bytes(vec![0xde, 0xad, 0xbe, 0xef])) }
+        );
+        assert_eq!(decode_revert_data(&[]), RevertReason::Unknown { selector: None });
+    }
+
+    #[test]
+    fn describe_safe_error_code() {
+        let reason = RevertReason::Error { message: "GS026".to_string() };
+        assert_eq!(reason.to_string(), "GS026 (Invalid owner provided)");
+    }
+
+    #[test]
+    fn revert_from_geth_and_openethereum_errors() {
+        let data = encode_with_selector(error_selector(), &[Token::String("GS013".to_string())]);
+        let hex = Bytes(data).to_string();
+
+        let mut error = rpc::Error::new(rpc::ErrorCode::ServerError(3));
+        error.message = "execution reverted: GS013".to_string();
+        error.data = Some(rpc::Value::String(hex.clone()));
+        let geth = revert_from_rpc_error(&error).unwrap();
+        assert_eq!(geth.reason, RevertReason::Error { message: "GS013".to_string() });
+
+        error.message = "VM execution error.".to_string();
+        error.data = Some(rpc::Value::String(format!("Reverted {}", hex)));
+        let open_ethereum = revert_from_rpc_error(&error).unwrap();
+        assert_eq!(open_ethereum.reason, geth.reason);
+
+        error.message = "execution reverted: Not enough funds".to_string();
+        error.data = None;
+        assert_eq!(
+            revert_from_rpc_error(&error).unwrap().reason,
+            RevertReason::Error { message: "Not enough funds".to_string() }
+        );
+
+        error.message = "insufficient funds for gas * price + value".to_string();
+        assert!(revert_from_rpc_error(&error).is_none());
+    }
+}
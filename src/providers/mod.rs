@@ -1,2 +1,3 @@
 pub mod accounts;
-pub mod ethereum;
\ No newline at end of file
+pub mod ethereum;
+pub mod store;
@@ -0,0 +1,22 @@
+//! Redis backed storage, features that need persistent state are only available if it is configured.
+
+use crate::config::Config;
+use anyhow::Result;
+
+#[derive(Clone)]
+pub struct Store {
+    client: redis::Client
+}
+
+impl Store {
+    pub fn from_config(config: &Config) -> Result<Option<Store>> {
+        match &config.redis_url {
+            Some(url) => Ok(Some(Store { client: redis::Client::open(url.as_str())? })),
+            None => Ok(None)
+        }
+    }
+
+    pub fn connection(&self) -> Result<redis::Connection> {
+        Ok(self.client.get_connection()?)
+    }
+}
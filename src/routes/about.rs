@@ -1,9 +1,9 @@
 use crate::utils::context::Context;
 use crate::services::about;
 use rocket::response::content::Json;
-use anyhow::Result;
+use crate::utils::errors::ApiResult;
 
 #[get("/about")]
-pub fn info(context: Context) -> Result<Json<String>> {
-    Ok(Json(serde_json::to_string(&about::get_about()?)?))
+pub fn info(context: Context) -> ApiResult<Json<String>> {
+    Ok(Json(serde_json::to_string(&about::get_about(&context)?)?))
 }
\ No newline at end of file
@@ -0,0 +1,80 @@
+use crate::utils::auth::AdminToken;
+use crate::utils::context::Context;
+use crate::services::{accounting, refunder, settings as runtime_settings};
+use crate::models::{Pause, RuntimeSettings, WithdrawPayload};
+use rocket::response::content;
+use rocket_contrib::json::Json;
+use crate::utils::errors::ApiResult;
+use super::parse_address;
+
+#[get("/v1/admin/refunders/<address>")]
+pub fn refunder_info(_token: AdminToken, context: Context, address: String) -> ApiResult<content::Json<String>> {
+    Ok(content::Json(serde_json::to_string(&refunder::info(&context, parse_address(address)?)?)?))
+}
+
+#[post("/v1/admin/refunders/<address>/withdraw", format = "json", data = "<withdrawal>")]
+pub fn withdraw_refunder_tokens(
+    _token: AdminToken,
+    context: Context,
+    address: String,
+    withdrawal: Json<WithdrawPayload>
+) -> ApiResult<String> {
+    Ok(refunder::withdraw(&context, parse_address(address)?, withdrawal.0)?)
+}
+
+#[get("/v1/admin/settings")]
+pub fn settings(_token: AdminToken, context: Context) -> ApiResult<content::Json<String>> {
+    Ok(content::Json(serde_json::to_string(&*context.settings().current())?))
+}
+
+#[put("/v1/admin/settings", format = "json", data = "<settings>")]
+pub fn update_settings(
+    _token: AdminToken,
+    context: Context,
+    settings: Json<RuntimeSettings>
+) -> ApiResult<content::Json<String>> {
+    Ok(content::Json(serde_json::to_string(&runtime_settings::update(&context, settings.0)?)?))
+}
+
+#[get("/v1/admin/settings/audit?<limit>")]
+pub fn settings_audit(_token: AdminToken, context: Context, limit: Option<usize>) -> ApiResult<content::Json<String>> {
+    Ok(content::Json(serde_json::to_string(&context.settings().audit_log(limit.unwrap_or(20))?)?))
+}
+
+#[get("/v1/admin/pauses")]
+pub fn pauses(_token: AdminToken, context: Context) -> ApiResult<content::Json<String>> {
+    let settings = context.settings();
+    Ok(content::Json(serde_json::to_string(&settings.pauses(&settings.current()))?))
+}
+
+#[post("/v1/admin/pauses", format = "json", data = "<pause>")]
+pub fn pause(_token: AdminToken, context: Context, pause: Json<Pause>) -> ApiResult<content::Json<String>> {
+    Ok(content::Json(serde_json::to_string(&runtime_settings::pause(&context, pause.0)?)?))
+}
+
+#[post("/v1/admin/pauses/resume", format = "json", data = "<pause>")]
+pub fn resume(_token: AdminToken, context: Context, pause: Json<Pause>) -> ApiResult<content::Json<String>> {
+    Ok(content::Json(serde_json::to_string(&runtime_settings::resume(&context, pause.0)?)?))
+}
+
+#[get("/v1/admin/accounting?<group_by>&<from>&<to>")]
+pub fn accounting_summary(
+    _token: AdminToken,
+    context: Context,
+    group_by: Option<String>,
+    from: Option<i64>,
+    to: Option<i64>
+) -> ApiResult<content::Json<String>> {
+    let summary = accounting::summary(&context, group_by.as_deref().unwrap_or("day"), from, to)?;
+    Ok(content::Json(serde_json::to_string(&summary)?))
+}
+
+#[get("/v1/admin/accounting/losses?<from>&<to>")]
+pub fn accounting_losses(
+    _token: AdminToken,
+    context: Context,
+    from: Option<i64>,
+    to: Option<i64>
+) -> ApiResult<content::Json<String>> {
+    Ok(content::Json(serde_json::to_string(&accounting::losses(&context, from, to)?)?))
+}
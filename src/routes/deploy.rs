@@ -1,10 +1,38 @@
 use crate::utils::context::Context;
 use crate::services::deployment;
-use crate::models::{DeployPayload};
+use crate::services::jobs::{self, JobRequest, Submission};
+use crate::models::{DeployPayload, PredictPayload, SafeDeployPayload};
+use rocket::response::content;
 use rocket_contrib::json::Json;
-use anyhow::Result;
+use crate::utils::errors::{ApiResult, Rejection};
+use rocket::http::Status;
+
+/// Deployments return the transaction hash as a JSON string
+fn json_hash(submission: Submission) -> ApiResult<Submission> {
+    Ok(match submission {
+        Submission::Sent(hash) => Submission::Sent(serde_json::to_string(&hash)?),
+        queued => queued
+    })
+}
 
 #[post("/v1/deployment/execute", format = "json", data = "<update>")]
-pub fn deploy(context: Context, update: Json<DeployPayload>) -> Result<String> {
-    Ok(serde_json::to_string(&deployment::deploy(&context, update.0)?)?)
-}
\ No newline at end of file
+pub fn deploy(context: Context, update: Json<DeployPayload>) -> ApiResult<Submission> {
+    json_hash(jobs::submit(&context, JobRequest::Deploy(update.0))?)
+}
+
+#[post("/v1/deployment/execute/safe", format = "json", data = "<deployment>")]
+pub fn deploy_safe(context: Context, deployment: Json<SafeDeployPayload>) -> ApiResult<Submission> {
+    json_hash(jobs::submit(&context, JobRequest::DeploySafe(deployment.0))?)
+}
+
+#[post("/v1/deployment/predict", format = "json", data = "<prediction>")]
+pub fn predict(context: Context, prediction: Json<PredictPayload>) -> ApiResult<content::Json<String>> {
+    Ok(content::Json(serde_json::to_string(&deployment::predict(&context, prediction.0)?)?))
+}
+
+#[get("/v1/deployment/<tx_hash>")]
+pub fn status(context: Context, tx_hash: String) -> ApiResult<content::Json<String>> {
+    let tx_hash = serde_json::from_value(serde_json::value::Value::String(tx_hash))
+        .map_err(|_| Rejection::new(Status::BadRequest, "Invalid transaction hash"))?;
+    Ok(content::Json(serde_json::to_string(&deployment::status(&context, tx_hash)?)?))
+}
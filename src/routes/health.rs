@@ -0,0 +1,19 @@
+use crate::utils::context::Context;
+use crate::services::health;
+use rocket::http::Status;
+use rocket::response::content;
+use rocket::response::status;
+use rocket_contrib::json::JsonValue;
+use crate::utils::errors::ApiResult;
+
+#[get("/health/live")]
+pub fn live() -> JsonValue {
+    json!({ "status": "ok" })
+}
+
+#[get("/health/ready")]
+pub fn ready(context: Context) -> ApiResult<status::Custom<content::Json<String>>> {
+    let readiness = health::readiness(&context);
+    let status = if readiness.ready { Status::Ok } else { Status::ServiceUnavailable };
+    Ok(status::Custom(status, content::Json(serde_json::to_string(&readiness)?)))
+}
@@ -0,0 +1,22 @@
+use crate::utils::context::Context;
+use crate::services::jobs::{self, Submission};
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::{self, content, status, Responder};
+use rocket_contrib::json::Json;
+use crate::utils::errors::ApiResult;
+
+/// Relayed requests return the hash as before, queued requests return the job id with `202 Accepted`.
+impl<'r> Responder<'r> for Submission {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        match self {
+            Submission::Sent(hash) => hash.respond_to(request),
+            Submission::Queued(receipt) => status::Custom(Status::Accepted, Json(receipt)).respond_to(request)
+        }
+    }
+}
+
+#[get("/v1/jobs/<job_id>")]
+pub fn job_status(context: Context, job_id: u64) -> ApiResult<content::Json<String>> {
+    Ok(content::Json(serde_json::to_string(&jobs::job_status(&context, job_id)?)?))
+}
@@ -0,0 +1,11 @@
+use crate::services::metrics;
+use crate::utils::auth::MetricsToken;
+use crate::utils::errors::ApiResult;
+use rocket::http::ContentType;
+use rocket::response::content::Content;
+
+#[get("/metrics")]
+pub fn metrics(_token: MetricsToken) -> ApiResult<Content<String>> {
+    let content_type = ContentType::with_params("text", "plain", ("version", "0.0.4"));
+    Ok(Content(content_type, metrics::collect()?))
+}
@@ -1,28 +1,84 @@
 extern crate rocket;
 
+use crate::utils::errors::{ApiResult, Rejection};
+use ethereum_types::Address;
+use rocket::http::Status;
 use rocket::Catcher;
 use rocket::Route;
 use rocket_contrib::json::JsonValue;
 
 pub mod about;
+pub mod admin;
 pub mod deploy;
+pub mod health;
+pub mod jobs;
+pub mod metrics;
 pub mod transactions;
+pub mod wallets;
+pub mod webhooks;
+
+fn parse_address(address: String) -> ApiResult<Address> {
+    Ok(serde_json::from_value(serde_json::value::Value::String(address))
+        .map_err(|_| Rejection::new(Status::BadRequest, "Invalid address"))?)
+}
 
 pub fn active_routes() -> Vec<Route> {
     routes![
         about::info,
+        admin::refunder_info,
+        admin::withdraw_refunder_tokens,
+        admin::settings,
+        admin::update_settings,
+        admin::settings_audit,
+        admin::pauses,
+        admin::pause,
+        admin::resume,
+        admin::accounting_summary,
+        admin::accounting_losses,
         deploy::deploy,
+        deploy::deploy_safe,
+        deploy::predict,
+        deploy::status,
+        health::live,
+        health::ready,
+        jobs::job_status,
+        metrics::metrics,
         transactions::estimate,
         transactions::execute_safe,
+        transactions::execute_safe_batched,
+        transactions::batch_status,
         transactions::execute_vault,
         transactions::update_vault,
         transactions::update_vault_fee,
-        transactions::relay_itx
+        transactions::relay_itx,
+        transactions::relay_itx_status,
+        wallets::transactions,
+        wallets::transactions_csv,
+        webhooks::webhook,
+        webhooks::register,
+        webhooks::unregister,
+        webhooks::reload
     ]
 }
 
 pub fn error_catchers() -> Vec<Catcher> {
-    catchers![not_found, panic]
+    catchers![unauthorized, forbidden, not_found, panic]
+}
+
+#[catch(401)]
+fn unauthorized() -> JsonValue {
+    json!({
+        "status": "error",
+        "reason": "Authorization required."
+    })
+}
+
+#[catch(403)]
+fn forbidden() -> JsonValue {
+    json!({
+        "status": "error",
+        "reason": "Access denied."
+    })
 }
 
 #[catch(404)]
@@ -1,38 +1,58 @@
 use crate::utils::context::Context;
 use crate::services::transactions;
+use crate::services::batch;
 use crate::services::itx;
+use crate::services::jobs::{self, JobRequest, Submission};
 use crate::models::{ExecutePayload, GenericRelayData, PreparePayload};
 use crate::providers::accounts::vault::{VaultPayload, VaultConfigPayload};
 use rocket::response::content;
 use rocket_contrib::json::Json;
-use anyhow::Result;
+use crate::utils::errors::{ApiResult, Rejection};
+use rocket::http::Status;
 
 #[post("/v1/transactions/prepare", format = "json", data = "<update>")]
-pub fn estimate(context: Context, update: Json<PreparePayload>) -> Result<content::Json<String>> {
+pub fn estimate(context: Context, update: Json<PreparePayload>) -> ApiResult<content::Json<String>> {
     Ok(content::Json(serde_json::to_string(&transactions::prepare(&context, update.0)?)?))
 }
 
 #[post("/v1/transactions/execute/safe", format = "json", data = "<transaction>")]
-pub fn execute_safe(context: Context, transaction: Json<ExecutePayload>) -> Result<String> {
-    transactions::execute_safe(&context, transaction.0)
+pub fn execute_safe(context: Context, transaction: Json<ExecutePayload>) -> ApiResult<Submission> {
+    Ok(jobs::submit(&context, JobRequest::ExecuteSafe(transaction.0))?)
+}
+
+#[post("/v1/transactions/execute/safe/batch", format = "json", data = "<transaction>")]
+pub fn execute_safe_batched(context: Context, transaction: Json<ExecutePayload>) -> ApiResult<content::Json<String>> {
+    Ok(content::Json(serde_json::to_string(&batch::execute_safe_batched(&context, transaction.0)?)?))
+}
+
+#[get("/v1/transactions/batch/<batch_id>")]
+pub fn batch_status(context: Context, batch_id: u64) -> ApiResult<content::Json<String>> {
+    Ok(content::Json(serde_json::to_string(&batch::batch_status(&context, batch_id)?)?))
 }
 
 #[post("/v1/transactions/execute/vault", format = "json", data = "<transaction>")]
-pub fn execute_vault(context: Context, transaction: Json<VaultPayload>) -> Result<String> {
-    transactions::execute_vault(&context, transaction.0)
+pub fn execute_vault(context: Context, transaction: Json<VaultPayload>) -> ApiResult<Submission> {
+    Ok(jobs::submit(&context, JobRequest::ExecuteVault(transaction.0))?)
 }
 
 #[post("/v1/transactions/update/vault", format = "json", data = "<update>")]
-pub fn update_vault(context: Context, update: Json<VaultConfigPayload>) -> Result<String> {
-    transactions::update_vault(&context, update.0)
+pub fn update_vault(context: Context, update: Json<VaultConfigPayload>) -> ApiResult<Submission> {
+    Ok(jobs::submit(&context, JobRequest::UpdateVault(update.0))?)
 }
 
 #[get("/v1/transactions/update/vault", format = "json")]
-pub fn update_vault_fee(context: Context) -> Result<content::Json<String>> {
+pub fn update_vault_fee(context: Context) -> ApiResult<content::Json<String>> {
     Ok(content::Json(serde_json::to_string(&transactions::update_vault_hook(&context)?)?))
 }
 
 #[post("/v1/transactions/execute/generic", format = "json", data = "<transaction>")]
-pub fn relay_itx(context: Context, transaction: Json<GenericRelayData>) -> Result<String> {
-    itx::relay_itx(&context, transaction.0)
-}
\ No newline at end of file
+pub fn relay_itx(context: Context, transaction: Json<GenericRelayData>) -> ApiResult<Submission> {
+    Ok(jobs::submit(&context, JobRequest::RelayGeneric(transaction.0))?)
+}
+
+#[get("/v1/transactions/generic/<relay_hash>")]
+pub fn relay_itx_status(context: Context, relay_hash: String) -> ApiResult<content::Json<String>> {
+    let relay_hash = serde_json::from_value(serde_json::value::Value::String(relay_hash))
+        .map_err(|_| Rejection::new(Status::BadRequest, "Invalid relay hash"))?;
+    Ok(content::Json(serde_json::to_string(&itx::relay_status(&context, relay_hash)?)?))
+}
@@ -0,0 +1,33 @@
+use crate::utils::auth::AdminToken;
+use crate::utils::context::Context;
+use crate::services::audit;
+use rocket::http::ContentType;
+use rocket::response::content;
+use crate::utils::errors::ApiResult;
+use super::parse_address;
+
+#[get("/v1/wallets/<address>/transactions?<from>&<to>&<limit>&<offset>")]
+pub fn transactions(
+    admin: Option<AdminToken>,
+    context: Context,
+    address: String,
+    from: Option<i64>,
+    to: Option<i64>,
+    limit: Option<usize>,
+    offset: Option<usize>
+) -> ApiResult<content::Json<String>> {
+    let page = audit::wallet_transactions(&context, admin.is_some(), parse_address(address)?, from, to, limit, offset)?;
+    Ok(content::Json(serde_json::to_string(&page)?))
+}
+
+#[get("/v1/wallets/<address>/transactions/csv?<from>&<to>")]
+pub fn transactions_csv(
+    admin: Option<AdminToken>,
+    context: Context,
+    address: String,
+    from: Option<i64>,
+    to: Option<i64>
+) -> ApiResult<content::Content<String>> {
+    let csv = audit::wallet_transactions_csv(&context, admin.is_some(), parse_address(address)?, from, to)?;
+    Ok(content::Content(ContentType::CSV, csv))
+}
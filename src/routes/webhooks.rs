@@ -0,0 +1,28 @@
+use crate::utils::auth::WebhookToken;
+use crate::utils::context::Context;
+use crate::services::webhooks;
+use crate::models::WebhookRegistration;
+use rocket::response::content;
+use rocket_contrib::json::Json;
+use crate::utils::errors::ApiResult;
+
+#[get("/v1/webhook")]
+pub fn webhook(context: Context) -> ApiResult<content::Json<String>> {
+    Ok(content::Json(serde_json::to_string(&webhooks::webhook(&context)?)?))
+}
+
+#[put("/v1/webhook", format = "json", data = "<registration>")]
+pub fn register(context: Context, registration: Json<WebhookRegistration>) -> ApiResult<content::Json<String>> {
+    Ok(content::Json(serde_json::to_string(&webhooks::register(&context, registration.0)?)?))
+}
+
+#[delete("/v1/webhook")]
+pub fn unregister(context: Context) -> ApiResult<()> {
+    Ok(webhooks::unregister(&context)?)
+}
+
+/// Inbound webhook, reloads the runtime settings persisted in Redis
+#[post("/v1/webhooks/reload")]
+pub fn reload(_token: WebhookToken, context: Context) -> ApiResult<content::Json<String>> {
+    Ok(content::Json(serde_json::to_string(&*context.settings().reload()?)?))
+}
@@ -3,11 +3,30 @@ extern crate reqwest;
 use crate::config::{version, build_number};
 use anyhow::Result;
 use crate::models::About;
+use crate::providers::ethereum::{to_string_result, EthereumProvider};
+use crate::providers::ethereum::backend::gas_tank_balance;
+use crate::utils::context::Context;
+use ethereum_types::U256;
 
-pub fn get_about() -> Result<About> {
+pub fn relayer_balance(eth_provider: &EthereumProvider) -> Result<U256> {
+    let balance = to_string_result(eth_provider.balance(&eth_provider.account()?)?)?;
+    Ok(U256::from_str_radix(balance.trim_start_matches("0x"), 16)?)
+}
+
+pub fn get_about(context: &Context) -> Result<About> {
+    let eth_provider = EthereumProvider::new(context);
     Ok(About {
         name: env!("CARGO_PKG_NAME").to_string(),
         version: version(),
         build_number: build_number(),
+        chain_id: eth_provider.chain().chain_id,
+        relayer_balance: relayer_balance(&eth_provider)
+            .map_err(|err| log::warn!("Could not load relayer balance: {:?}", err)).ok(),
+        itx_balance: if eth_provider.chain().itx_enabled() {
+            gas_tank_balance(&eth_provider)
+                .map_err(|err| log::warn!("Could not load ITX balance: {:?}", err)).ok()
+        } else {
+            None
+        },
     })
 }
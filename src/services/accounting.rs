@@ -0,0 +1,227 @@
+//! Profit and loss of mined relays. The fee income of a relay is compared with the gas costs from its receipt,
+//! relays with a native fee below their gas costs are flagged as losses.
+
+use crate::models::{AccountingSummary, RelayAccount, RelayOutcome, RelayRecord};
+use crate::utils::context::Context;
+use crate::utils::errors::Rejection;
+use crate::utils::metrics;
+use anyhow::Result;
+use ethereum_types::U256;
+use rocket::http::Status;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Grouping {
+    Day,
+    Tenant,
+    Wallet
+}
+
+impl FromStr for Grouping {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "day" => Ok(Grouping::Day),
+            "tenant" => Ok(Grouping::Tenant),
+            "wallet" => Ok(Grouping::Wallet),
+            _ => Err(Rejection::new(Status::BadRequest, format!("Unknown grouping {}", s)).into())
+        }
+    }
+}
+
+/// Gas costs in wei, relays of a batch share the costs of the batch transaction
+pub fn gas_cost(record: &RelayRecord) -> Option<U256> {
+    let gas_cost = record.gas_used? * record.effective_gas_price?;
+    Some(gas_cost / U256::from(record.batch_size.max(1)))
+}
+
+/// Income and costs of a mined relay, `None` if the relay was not mined or the gas price is not known
+pub fn account(record: &RelayRecord) -> Option<RelayAccount> {
+    let income = match record.outcome {
+        RelayOutcome::Mined => record.collected_fee?,
+        // The fee is paid by the transaction itself, so it is not collected if it reverts
+        RelayOutcome::Reverted => U256::zero(),
+        RelayOutcome::Failed | RelayOutcome::Submitted => return None
+    };
+    let gas_cost = gas_cost(record)?;
+    let loss = Some(gas_cost)
+        .filter(|gas_cost| record.fee_token.is_none() && income < *gas_cost)
+        .map(|gas_cost| gas_cost - income);
+    Some(RelayAccount {
+        record_id: record.id,
+        chain_id: record.chain_id,
+        tenant: record.tenant.clone(),
+        wallet: record.wallet,
+        service: record.service.clone(),
+        day: chrono::NaiveDateTime::from_timestamp(record.updated_at, 0).format("%Y-%m-%d").to_string(),
+        fee_token: record.fee_token,
+        income,
+        gas_cost,
+        loss
+    })
+}
+
+/// Tracks the fee and the costs of a relay once it is mined and flags it if it lost money.
+pub fn observe(record: &RelayRecord) {
+    let account = match account(record) {
+        Some(account) => account,
+        None => return
+    };
+    if record.fee_token.is_none() {
+        metrics::FEES.with_label_values(&[&record.service]).inc_by(account.income.low_u128() as f64);
+    }
+    metrics::GAS_COSTS.with_label_values(&[&record.service]).inc_by(account.gas_cost.low_u128() as f64);
+    if let Some(loss) = account.loss {
+        metrics::RELAY_LOSSES.with_label_values(&[&record.service]).inc();
+        log::warn!(
+            "Relay {} on chain {} lost {} wei (fee {}, gas costs {})",
+            record.id, record.chain_id, loss, account.income, account.gas_cost
+        );
+    }
+}
+
+fn summarize(accounts: &[RelayAccount], grouping: Grouping) -> Vec<AccountingSummary> {
+    let mut summaries: BTreeMap<Option<String>, AccountingSummary> = BTreeMap::new();
+    for account in accounts {
+        let key = match grouping {
+            Grouping::Day => Some(account.day.clone()),
+            Grouping::Tenant => account.tenant.clone(),
+            Grouping::Wallet => account.wallet.map(|wallet| format!("{:?}", wallet))
+        };
+        let summary = summaries.entry(key.clone()).or_insert_with(|| AccountingSummary {
+            key,
+            relays: 0,
+            income: U256::zero(),
+            token_income: Default::default(),
+            gas_cost: U256::zero(),
+            losses: 0,
+            loss_amount: U256::zero()
+        });
+        summary.relays += 1;
+        match account.fee_token {
+            Some(token) => *summary.token_income.entry(token).or_insert_with(U256::zero) += account.income,
+            None => summary.income += account.income
+        }
+        summary.gas_cost += account.gas_cost;
+        if let Some(loss) = account.loss {
+            summary.losses += 1;
+            summary.loss_amount += loss;
+        }
+    }
+    summaries.into_iter().map(|(_, summary)| summary).collect()
+}
+
+/// Accounts of the relays on the chain of the request, mined in the time range
+fn accounts(context: &Context, from: Option<i64>, to: Option<i64>) -> Result<Vec<RelayAccount>> {
+    let records = context.audit_log().mined_records(context.chain().chain_id, from, to)?;
+    Ok(records.iter().filter_map(account).collect())
+}
+
+pub fn summary(context: &Context, grouping: &str, from: Option<i64>, to: Option<i64>) -> Result<Vec<AccountingSummary>> {
+    let grouping = Grouping::from_str(grouping)?;
+    Ok(summarize(&accounts(context, from, to)?, grouping))
+}
+
+pub fn losses(context: &Context, from: Option<i64>, to: Option<i64>) -> Result<Vec<RelayAccount>> {
+    Ok(accounts(context, from, to)?.into_iter().filter(|account| account.loss.is_some()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethereum_types::Address;
+
+    fn record(outcome: RelayOutcome, fee: u64, gas_used: u64, batch_size: usize) -> RelayRecord {
+        RelayRecord {
+            id: 1,
+            chain_id: 4,
+            tenant: Some("acme".to_string()),
+            request_id: None,
+            callback_url: None,
+            service: "execute_safe".to_string(),
+            backend: None,
+            wallet: Some(Address::from([1u8; 20])),
+            calls: vec![],
+            fee: U256::from(fee),
+            fee_token: None,
+            collected_fee: Some(U256::from(fee)),
+            batch_size,
+            outcome,
+            hash: None,
+            error: None,
+            transaction_hash: None,
+            block_number: Some(U256::one()),
+            gas_used: Some(U256::from(gas_used)),
+            effective_gas_price: Some(U256::from(2)),
+            created_at: 0,
+            updated_at: 86_400
+        }
+    }
+
+    #[test]
+    fn batch_relays_share_gas_costs() {
+        let account = account(&record(RelayOutcome::Mined, 60, 100, 4)).unwrap();
+        assert_eq!(account.gas_cost, U256::from(50));
+        assert_eq!(account.loss, None);
+        assert_eq!(account.day, "1970-01-02");
+    }
+
+    #[test]
+    fn reverted_relays_lose_the_gas_costs() {
+        let account = account(&record(RelayOutcome::Reverted, 60, 100, 1)).unwrap();
+        assert_eq!(account.income, U256::zero());
+        assert_eq!(account.loss, Some(U256::from(200)));
+        assert!(super::account(&record(RelayOutcome::Submitted, 60, 100, 1)).is_none());
+    }
+
+    #[test]
+    fn failed_safe_transactions_collect_no_fee() {
+        let mut failed = record(RelayOutcome::Mined, 300, 100, 1);
+        failed.collected_fee = Some(U256::zero());
+        let account = account(&failed).unwrap();
+        assert_eq!(account.income, U256::zero());
+        assert_eq!(account.loss, Some(U256::from(200)));
+    }
+
+    #[test]
+    fn collected_fees_are_counted_once_mined() {
+        let service = "collected_fees_test";
+        let fees = || metrics::FEES.with_label_values(&[service]).get();
+        let mined = |outcome, collected_fee: u64| RelayRecord {
+            service: service.to_string(),
+            collected_fee: Some(U256::from(collected_fee)),
+            ..record(outcome, 300, 100, 1)
+        };
+
+        observe(&RelayRecord { outcome: RelayOutcome::Submitted, ..mined(RelayOutcome::Mined, 300) });
+        assert_eq!(fees(), 0.0);
+        observe(&mined(RelayOutcome::Mined, 300));
+        observe(&mined(RelayOutcome::Mined, 0));
+        observe(&mined(RelayOutcome::Reverted, 300));
+        observe(&RelayRecord { fee_token: Some(Address::from([9u8; 20])), ..mined(RelayOutcome::Mined, 50) });
+        assert_eq!(fees(), 300.0);
+    }
+
+    #[test]
+    fn token_fees_are_not_compared_with_gas_costs() {
+        let mut token_relay = record(RelayOutcome::Mined, 10, 100, 1);
+        token_relay.fee_token = Some(Address::from([9u8; 20]));
+        let accounts: Vec<RelayAccount> = vec![
+            token_relay,
+            record(RelayOutcome::Mined, 300, 100, 1),
+            record(RelayOutcome::Mined, 100, 100, 1)
+        ].iter().filter_map(account).collect();
+        assert_eq!(accounts[0].loss, None);
+
+        let summaries = summarize(&accounts, Grouping::Tenant);
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].relays, 3);
+        assert_eq!(summaries[0].income, U256::from(400));
+        assert_eq!(summaries[0].token_income[&Address::from([9u8; 20])], U256::from(10));
+        assert_eq!(summaries[0].gas_cost, U256::from(600));
+        assert_eq!(summaries[0].losses, 1);
+        assert_eq!(summaries[0].loss_amount, U256::from(100));
+    }
+}
@@ -0,0 +1,684 @@
+//! Audit log of all relay attempts. Attempts are recorded with the result of the submission, submitted
+//! transactions are tracked until they are mined to record the gas that was used. Changes of the outcome are
+//! reported to the webhooks.
+
+use crate::config::{ChainConfig, Config};
+use crate::models::{RelayOutcome, RelayRecord, RelayRecordPage, RelayState, RelayedCall, WebhookEventType};
+use crate::providers::accounts::utils::try_decode_multisend_bytes;
+use crate::providers::ethereum::backend::{BackendType, OnDropped};
+use crate::providers::ethereum::types::Bytes;
+use crate::providers::ethereum::{
+    to_typed_result, EthereumProvider, ItxTransactionStatus, TransactionInfo, TransactionReceipt
+};
+use crate::providers::store::Store;
+use crate::services::accounting;
+use crate::services::batch::item_states;
+use crate::services::webhooks::Webhooks;
+use crate::utils::context::Context;
+use crate::utils::errors::Rejection;
+use anyhow::Result;
+use ethereum_types::{Address, H256, U256};
+use redis::Commands;
+use rocket::http::Status;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const NEXT_ID_KEY: &str = "audit:next_id";
+const PENDING_KEY: &str = "audit:pending";
+/// Submitted transactions that are not mined within a day keep the submitted outcome
+const TRACKING_SECS: i64 = 24 * 60 * 60;
+const TRACKING_INTERVAL_SECS: u64 = 15;
+/// Records kept if Redis is not configured
+const MEMORY_RECORDS: usize = 10_000;
+const MAX_PAGE_SIZE: usize = 100;
+
+fn record_key(id: u64) -> String {
+    format!("audit:relay:{}", id)
+}
+
+fn wallet_key(chain_id: u64, wallet: &Address) -> String {
+    format!("audit:wallet:{}:{:?}", chain_id, wallet)
+}
+
+/// Records of the wallet relayed for the tenant, so that pages can be read without loading the other tenants
+fn tenant_wallet_key(chain_id: u64, wallet: &Address, tenant: &str) -> String {
+    format!("audit:wallet:{}:{:?}:{}", chain_id, wallet, tenant)
+}
+
+/// Mined relays of the chain by the time they were mined
+fn mined_key(chain_id: u64) -> String {
+    format!("audit:mined:{}", chain_id)
+}
+
+fn is_mined(record: &RelayRecord) -> bool {
+    record.outcome == RelayOutcome::Mined || record.outcome == RelayOutcome::Reverted
+}
+
+fn now() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+/// Relay that is attempted, recorded together with the result of the submission
+#[derive(Clone, Debug)]
+pub struct RelayAttempt {
+    pub chain_id: u64,
+    pub tenant: Option<String>,
+    pub request_id: Option<String>,
+    pub callback_url: Option<String>,
+    pub service: &'static str,
+    pub wallet: Option<Address>,
+    pub calls: Vec<RelayedCall>,
+    pub fee: U256,
+    pub fee_token: Option<Address>
+}
+
+/// Calls of a wallet transaction, delegate calls to the multiSend contract are decoded into their transactions.
+pub fn relayed_calls(chain: &ChainConfig, operation: u8, to: Address, value: U256, data: &Bytes) -> Vec<RelayedCall> {
+    if to == chain.multisend_address && operation == 1 {
+        if let Ok(transactions) = try_decode_multisend_bytes(&data.0) {
+            return transactions.into_iter()
+                .map(|tx| RelayedCall { operation: tx.operation, to: tx.to, value: tx.value, data: tx.data })
+                .collect();
+        }
+    }
+    vec![RelayedCall { operation, to, value, data: data.clone() }]
+}
+
+/// Fee paid to the relayer by the last call of a relay (the payment transaction), zero if it doesn't pay the relayer.
+pub fn relayer_payment(relayer: Address, calls: &[RelayedCall]) -> U256 {
+    calls.last()
+        .filter(|call| call.to == relayer && call.operation == 0 && call.data.0.is_empty())
+        .map(|call| call.value)
+        .unwrap_or_default()
+}
+
+/// Safe transactions that fail inside execTransaction are still mined, but the payment of the fee is reverted.
+fn collected_fee(record: &RelayRecord, receipt: &TransactionReceipt) -> U256 {
+    let executes_safe = record.service == "execute_safe" || record.service == "execute_safe_batched";
+    match record.wallet {
+        Some(wallet) if executes_safe && item_states(&[wallet], receipt) != vec![RelayState::Success] => U256::zero(),
+        _ if receipt.is_success() => record.fee,
+        _ => U256::zero()
+    }
+}
+
+#[derive(Default)]
+struct Memory {
+    next_id: u64,
+    records: VecDeque<RelayRecord>
+}
+
+/// Stores the relay records in Redis if configured, otherwise the latest records are kept in memory.
+#[derive(Clone)]
+pub struct AuditLog {
+    store: Option<Store>,
+    memory: Arc<Mutex<Memory>>,
+    webhooks: Webhooks
+}
+
+impl AuditLog {
+    pub fn new(store: Option<Store>, webhooks: Webhooks) -> Self {
+        AuditLog { store, memory: Arc::new(Mutex::new(Memory::default())), webhooks }
+    }
+
+    /// Starts the worker that updates submitted transactions once they are mined.
+    pub fn start_tracking(&self, config: Arc<Config>, client: reqwest::blocking::Client) {
+        let audit = self.clone();
+        thread::spawn(move || {
+            loop {
+                thread::sleep(Duration::from_secs(TRACKING_INTERVAL_SECS));
+                if let Err(err) = audit.track(&config, &client) {
+                    log::error!("Tracking relayed transactions failed: {:?}", err);
+                }
+            }
+        });
+    }
+
+    /// Records the attempt, failures are logged as the relay itself should not fail because of the audit log.
+    pub fn record(&self, attempt: RelayAttempt, backend: Option<BackendType>, batch_size: usize, result: &Result<String>) {
+        if let Err(err) = self.try_record(attempt, backend, batch_size, result) {
+            log::error!("Could not record relay attempt: {:?}", err);
+        }
+    }
+
+    fn try_record(
+        &self,
+        attempt: RelayAttempt,
+        backend: Option<BackendType>,
+        batch_size: usize,
+        result: &Result<String>
+    ) -> Result<()> {
+        let now = now();
+        let (outcome, hash, error) = match result {
+            Ok(hash) => (RelayOutcome::Submitted, Some(hash.clone()), None),
+            Err(err) => (RelayOutcome::Failed, None, Some(err.to_string()))
+        };
+        let mut record = RelayRecord {
+            id: 0,
+            chain_id: attempt.chain_id,
+            tenant: attempt.tenant,
+            request_id: attempt.request_id,
+            callback_url: attempt.callback_url,
+            service: attempt.service.to_string(),
+            backend,
+            wallet: attempt.wallet,
+            calls: attempt.calls,
+            fee: attempt.fee,
+            fee_token: attempt.fee_token,
+            collected_fee: None,
+            batch_size,
+            outcome,
+            hash,
+            error,
+            transaction_hash: None,
+            block_number: None,
+            gas_used: None,
+            effective_gas_price: None,
+            created_at: now,
+            updated_at: now
+        };
+        match &self.store {
+            Some(store) => {
+                let mut con = store.connection()?;
+                record.id = con.incr(NEXT_ID_KEY, 1)?;
+                let _: () = con.set(record_key(record.id), serde_json::to_string(&record)?)?;
+                if let Some(wallet) = &record.wallet {
+                    let _: () = con.zadd(wallet_key(record.chain_id, wallet), record.id, record.created_at)?;
+                    if let Some(tenant) = &record.tenant {
+                        let key = tenant_wallet_key(record.chain_id, wallet, tenant);
+                        let _: () = con.zadd(key, record.id, record.created_at)?;
+                    }
+                }
+                if record.outcome == RelayOutcome::Submitted {
+                    let _: () = con.sadd(PENDING_KEY, record.id)?;
+                }
+            },
+            None => {
+                let mut memory = self.memory.lock().unwrap();
+                memory.next_id += 1;
+                record.id = memory.next_id;
+                memory.records.push_back(record.clone());
+                if memory.records.len() > MEMORY_RECORDS {
+                    memory.records.pop_front();
+                }
+            }
+        }
+        if record.outcome == RelayOutcome::Submitted {
+            self.webhooks.notify(WebhookEventType::Broadcast, &record);
+        }
+        Ok(())
+    }
+
+    fn update(&self, record: &RelayRecord) -> Result<()> {
+        match &self.store {
+            Some(store) => {
+                let mut con = store.connection()?;
+                let _: () = con.set(record_key(record.id), serde_json::to_string(record)?)?;
+                if is_mined(record) {
+                    let _: () = con.zadd(mined_key(record.chain_id), record.id, record.updated_at)?;
+                }
+                if record.outcome != RelayOutcome::Submitted {
+                    let _: () = con.srem(PENDING_KEY, record.id)?;
+                }
+            },
+            None => {
+                let mut memory = self.memory.lock().unwrap();
+                if let Some(existing) = memory.records.iter_mut().find(|existing| existing.id == record.id) {
+                    *existing = record.clone();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Hook for the relay backends that marks the relays of a transaction that was given up as failed.
+    pub fn on_dropped(&self, chain_id: u64) -> OnDropped {
+        let audit = self.clone();
+        Arc::new(move |hash: &str, reason: &str| {
+            if let Err(err) = audit.fail_dropped(chain_id, hash, reason) {
+                log::error!("Could not record dropped transaction {}: {:?}", hash, err);
+            }
+        })
+    }
+
+    /// Batched relays share the transaction, so all submitted records with the hash fail.
+    fn fail_dropped(&self, chain_id: u64, hash: &str, reason: &str) -> Result<()> {
+        let dropped = self.pending()?.into_iter()
+            .filter(|record| record.chain_id == chain_id && record.hash.as_deref() == Some(hash));
+        for mut record in dropped {
+            record.outcome = RelayOutcome::Failed;
+            record.error = Some(reason.to_string());
+            record.updated_at = now();
+            self.update(&record)?;
+            self.webhooks.notify(WebhookEventType::Dropped, &record);
+        }
+        Ok(())
+    }
+
+    fn stop_tracking(&self, record: &RelayRecord) -> Result<()> {
+        if let Some(store) = &self.store {
+            let _: () = store.connection()?.srem(PENDING_KEY, record.id)?;
+        }
+        Ok(())
+    }
+
+    fn pending(&self) -> Result<Vec<RelayRecord>> {
+        match &self.store {
+            Some(store) => {
+                let mut con = store.connection()?;
+                let ids: Vec<u64> = con.smembers(PENDING_KEY)?;
+                let mut records = vec![];
+                for id in ids {
+                    let record: Option<String> = con.get(record_key(id))?;
+                    match record {
+                        Some(record) => records.push(serde_json::from_str(&record)?),
+                        None => { let _: () = con.srem(PENDING_KEY, id)?; }
+                    }
+                }
+                Ok(records)
+            },
+            None => {
+                let since = now() - TRACKING_SECS;
+                let memory = self.memory.lock().unwrap();
+                Ok(memory.records.iter()
+                    .filter(|record| record.outcome == RelayOutcome::Submitted && record.created_at > since)
+                    .cloned()
+                    .collect())
+            }
+        }
+    }
+
+    fn track(&self, config: &Config, client: &reqwest::blocking::Client) -> Result<()> {
+        for record in self.pending()? {
+            let id = record.id;
+            if let Err(err) = self.track_record(config, client, record) {
+                log::warn!("Could not check relay {}: {:?}", id, err);
+            }
+        }
+        Ok(())
+    }
+
+    fn track_record(
+        &self,
+        config: &Config,
+        client: &reqwest::blocking::Client,
+        mut record: RelayRecord
+    ) -> Result<()> {
+        let chain = config.chain(record.chain_id).ok_or_else(|| anyhow::anyhow!("Unknown chain {}", record.chain_id))?;
+        let eth_provider = EthereumProvider::for_chain(client, chain).with_request_id(record.request_id.clone());
+        let transaction_hashes = transaction_hashes(&eth_provider, &record)?;
+        // ITX resubmits transactions with a higher gas price, the latest broadcast replaces the previous ones
+        if let Some(latest) = transaction_hashes.first().filter(|latest| record.transaction_hash != Some(**latest)) {
+            let replaced = record.transaction_hash.is_some();
+            record.transaction_hash = Some(*latest);
+            record.updated_at = now();
+            self.update(&record)?;
+            if replaced {
+                self.webhooks.notify(WebhookEventType::Replaced, &record);
+            }
+        }
+        match mined_receipt(&eth_provider, &transaction_hashes)? {
+            Some(receipt) => {
+                let (outcome, event) = if receipt.is_success() {
+                    (RelayOutcome::Mined, WebhookEventType::Mined)
+                } else {
+                    (RelayOutcome::Reverted, WebhookEventType::Reverted)
+                };
+                record.outcome = outcome;
+                record.collected_fee = Some(collected_fee(&record, &receipt));
+                record.transaction_hash = Some(receipt.transaction_hash);
+                record.block_number = receipt.block_number;
+                record.gas_used = Some(receipt.gas_used);
+                record.effective_gas_price = receipt.effective_gas_price;
+                record.updated_at = now();
+                self.update(&record)?;
+                accounting::observe(&record);
+                self.webhooks.notify(event, &record);
+            },
+            None if record.created_at < now() - TRACKING_SECS => {
+                log::warn!("Relay {} was not mined within {} seconds", record.id, TRACKING_SECS);
+                self.stop_tracking(&record)?;
+            },
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Mined relays of the chain, mined in the time range (unix timestamps, inclusive)
+    pub fn mined_records(&self, chain_id: u64, from: Option<i64>, to: Option<i64>) -> Result<Vec<RelayRecord>> {
+        let (from, to) = (from.unwrap_or(0), to.unwrap_or(i64::MAX));
+        match &self.store {
+            Some(store) => {
+                let mut con = store.connection()?;
+                let ids: Vec<u64> = con.zrangebyscore(mined_key(chain_id), from, to)?;
+                let mut records = vec![];
+                for id in ids {
+                    let record: Option<String> = con.get(record_key(id))?;
+                    if let Some(record) = record {
+                        records.push(serde_json::from_str(&record)?);
+                    }
+                }
+                Ok(records)
+            },
+            None => {
+                let memory = self.memory.lock().unwrap();
+                Ok(memory.records.iter()
+                    .filter(|record| record.chain_id == chain_id && is_mined(record))
+                    .filter(|record| record.updated_at >= from && record.updated_at <= to)
+                    .cloned()
+                    .collect())
+            }
+        }
+    }
+
+    /// Records of the wallet created in the time range (unix timestamps, inclusive), latest first. Only the records
+    /// of the tenant are returned if one is set. Returns the number of records in the range and the records of the
+    /// page (offset and limit) if one is set, otherwise all of them.
+    pub fn wallet_records(
+        &self,
+        chain_id: u64,
+        wallet: &Address,
+        tenant: Option<&str>,
+        from: Option<i64>,
+        to: Option<i64>,
+        page: Option<(usize, usize)>
+    ) -> Result<(usize, Vec<RelayRecord>)> {
+        let (from, to) = (from.unwrap_or(0), to.unwrap_or(i64::MAX));
+        match &self.store {
+            Some(store) => {
+                let mut con = store.connection()?;
+                let key = match tenant {
+                    Some(tenant) => tenant_wallet_key(chain_id, wallet, tenant),
+                    None => wallet_key(chain_id, wallet)
+                };
+                let count: usize = con.zcount(&key, from, to)?;
+                let ids: Vec<u64> = match page {
+                    Some((offset, limit)) => con.zrevrangebyscore_limit(&key, to, from, offset as isize, limit as isize)?,
+                    None => con.zrevrangebyscore(&key, to, from)?
+                };
+                let mut records = vec![];
+                for id in ids {
+                    let record: Option<String> = con.get(record_key(id))?;
+                    if let Some(record) = record {
+                        records.push(serde_json::from_str(&record)?);
+                    }
+                }
+                Ok((count, records))
+            },
+            None => {
+                let memory = self.memory.lock().unwrap();
+                let records: Vec<&RelayRecord> = memory.records.iter().rev()
+                    .filter(|record| record.chain_id == chain_id && record.wallet.as_ref() == Some(wallet))
+                    .filter(|record| tenant.map_or(true, |tenant| record.tenant.as_deref() == Some(tenant)))
+                    .filter(|record| record.created_at >= from && record.created_at <= to)
+                    .collect();
+                let (offset, limit) = page.unwrap_or((0, records.len()));
+                Ok((records.len(), records.into_iter().skip(offset).take(limit).cloned().collect()))
+            }
+        }
+    }
+}
+
+/// Hashes of the transactions submitted for the relay, for ITX relays the broadcasts starting with the latest.
+fn transaction_hashes(eth_provider: &EthereumProvider, record: &RelayRecord) -> Result<Vec<H256>> {
+    let hash: H256 = serde_json::from_value(serde_json::Value::String(record.hash.clone().unwrap_or_default()))?;
+    Ok(match record.backend {
+        Some(BackendType::Itx) => {
+            let status: Option<ItxTransactionStatus> = to_typed_result(eth_provider.itx_transaction_status(&hash)?)?;
+            status.map(|status| status.broadcasts.iter().rev().map(|broadcast| broadcast.eth_tx_hash).collect())
+                .unwrap_or_default()
+        },
+        _ => vec![hash]
+    })
+}
+
+/// Receipt of the first of the transactions that was mined
+fn mined_receipt(eth_provider: &EthereumProvider, transaction_hashes: &[H256]) -> Result<Option<TransactionReceipt>> {
+    for transaction_hash in transaction_hashes {
+        let receipt: Option<TransactionReceipt> = to_typed_result(eth_provider.transaction_receipt(transaction_hash)?)?;
+        if let Some(mut receipt) = receipt.filter(|receipt| receipt.block_number.is_some()) {
+            if receipt.effective_gas_price.is_none() {
+                // Nodes that predate EIP-1559 only return the gas price with the transaction
+                let transaction: Option<TransactionInfo> =
+                    to_typed_result(eth_provider.transaction(&receipt.transaction_hash)?)?;
+                receipt.effective_gas_price = transaction.map(|transaction| transaction.gas_price);
+            }
+            return Ok(Some(receipt));
+        }
+    }
+    Ok(None)
+}
+
+/// Tenant whose records are listed, the tenant of the `X-Api-Key`. Records of all tenants are only listed for admins.
+fn listed_tenant(context: &Context, admin: bool) -> Result<Option<String>> {
+    match context.tenant()? {
+        Some(tenant) => Ok(Some(tenant)),
+        None if admin => Ok(None),
+        None => Err(Rejection::new(Status::Unauthorized, "An API key is required").into())
+    }
+}
+
+pub fn wallet_transactions(
+    context: &Context,
+    admin: bool,
+    wallet: Address,
+    from: Option<i64>,
+    to: Option<i64>,
+    limit: Option<usize>,
+    offset: Option<usize>
+) -> Result<RelayRecordPage> {
+    let tenant = listed_tenant(context, admin)?;
+    let limit = limit.unwrap_or(20).min(MAX_PAGE_SIZE);
+    let offset = offset.unwrap_or(0);
+    let (count, results) = context.audit_log()
+        .wallet_records(context.chain().chain_id, &wallet, tenant.as_deref(), from, to, Some((offset, limit)))?;
+    let next_offset = Some(offset + results.len()).filter(|next| *next < count);
+    Ok(RelayRecordPage { count, next_offset, results })
+}
+
+/// All records in the time range as CSV, amounts are in wei
+pub fn wallet_transactions_csv(
+    context: &Context,
+    admin: bool,
+    wallet: Address,
+    from: Option<i64>,
+    to: Option<i64>
+) -> Result<String> {
+    let tenant = listed_tenant(context, admin)?;
+    let (_, records) = context.audit_log()
+        .wallet_records(context.chain().chain_id, &wallet, tenant.as_deref(), from, to, None)?;
+    Ok(to_csv(&records))
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains(&[',', '"', '\n'][..]) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn optional<T: ToString>(value: &Option<T>) -> String {
+    value.as_ref().map(|value| value.to_string()).unwrap_or_default()
+}
+
+fn to_csv(records: &[RelayRecord]) -> String {
+    let mut csv = String::from(
+        "id,createdAt,chainId,tenant,wallet,service,backend,outcome,hash,transactionHash,blockNumber,\
+         fee,feeToken,gasUsed,effectiveGasPrice,gasCost,loss,batchSize,calls,error\n"
+    );
+    for record in records {
+        let account = accounting::account(record);
+        let fields = vec![
+            record.id.to_string(),
+            chrono::NaiveDateTime::from_timestamp(record.created_at, 0).format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+            record.chain_id.to_string(),
+            optional(&record.tenant),
+            record.wallet.map(|wallet| format!("{:?}", wallet)).unwrap_or_default(),
+            record.service.clone(),
+            record.backend.map(|backend| format!("{:?}", backend).to_lowercase()).unwrap_or_default(),
+            format!("{:?}", record.outcome).to_lowercase(),
+            optional(&record.hash),
+            record.transaction_hash.map(|hash| format!("{:?}", hash)).unwrap_or_default(),
+            optional(&record.block_number),
+            record.fee.to_string(),
+            record.fee_token.map(|token| format!("{:?}", token)).unwrap_or_default(),
+            optional(&record.gas_used),
+            optional(&record.effective_gas_price),
+            optional(&account.as_ref().map(|account| account.gas_cost)),
+            optional(&account.and_then(|account| account.loss)),
+            record.batch_size.to_string(),
+            record.calls.len().to_string(),
+            optional(&record.error)
+        ];
+        let fields: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&fields.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+    use crate::providers::ethereum::hash::keccak256;
+    use crate::providers::ethereum::Log;
+    use crate::services::transactions::build_multisend_bytes;
+    use ethabi_contract::use_contract;
+
+    use_contract!(multisend, "./res/multisend.json");
+
+    fn attempt(wallet: Address) -> RelayAttempt {
+        RelayAttempt {
+            chain_id: 4,
+            tenant: Some("acme".to_string()),
+            request_id: None,
+            callback_url: None,
+            service: "execute_safe",
+            wallet: Some(wallet),
+            calls: vec![],
+            fee: U256::from(10),
+            fee_token: None
+        }
+    }
+
+    #[test]
+    fn records_are_listed_by_wallet() {
+        let audit = AuditLog::new(None, Webhooks::new(None, 8));
+        let wallet = Address::from([1u8; 20]);
+        audit.record(attempt(wallet), Some(BackendType::Direct), 1, &Ok("0x01".to_string()));
+        audit.record(attempt(wallet), Some(BackendType::Direct), 1, &Err(anyhow::anyhow!("Reverted")));
+        audit.record(attempt(Address::from([2u8; 20])), None, 1, &Ok("0x02".to_string()));
+
+        let (_, records) = audit.wallet_records(4, &wallet, None, None, None, None).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].outcome, RelayOutcome::Failed);
+        assert_eq!(records[0].error.as_deref(), Some("Reverted"));
+        assert_eq!(records[1].hash.as_deref(), Some("0x01"));
+        assert!(audit.wallet_records(4, &wallet, None, Some(now() + 60), None, None).unwrap().1.is_empty());
+        assert!(audit.wallet_records(1, &wallet, None, None, None, None).unwrap().1.is_empty());
+        assert_eq!(audit.pending().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn records_are_paged_per_tenant() {
+        let audit = AuditLog::new(None, Webhooks::new(None, 8));
+        let wallet = Address::from([1u8; 20]);
+        for hash in &["0x01", "0x02", "0x03"] {
+            audit.record(attempt(wallet), Some(BackendType::Direct), 1, &Ok(hash.to_string()));
+        }
+        audit.record(RelayAttempt { tenant: None, ..attempt(wallet) }, None, 1, &Ok("0x04".to_string()));
+
+        let (count, records) = audit.wallet_records(4, &wallet, Some("acme"), None, None, Some((1, 1))).unwrap();
+        assert_eq!(count, 3);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].hash.as_deref(), Some("0x02"));
+        assert_eq!(audit.wallet_records(4, &wallet, Some("acme"), None, None, Some((2, 5))).unwrap().1.len(), 1);
+        assert_eq!(audit.wallet_records(4, &wallet, Some("other"), None, None, None).unwrap().0, 0);
+        assert_eq!(audit.wallet_records(4, &wallet, None, None, None, None).unwrap().0, 4);
+    }
+
+    #[test]
+    fn dropped_transactions_fail_their_relays() {
+        let audit = AuditLog::new(None, Webhooks::new(None, 8));
+        let wallet = Address::from([1u8; 20]);
+        audit.record(attempt(wallet), Some(BackendType::Bundle), 2, &Ok("0x01".to_string()));
+        audit.record(attempt(Address::from([2u8; 20])), Some(BackendType::Bundle), 2, &Ok("0x01".to_string()));
+        audit.record(attempt(wallet), Some(BackendType::Direct), 1, &Ok("0x02".to_string()));
+
+        audit.on_dropped(4)("0x01", "Bundle transaction was not included within 25 blocks");
+
+        let (_, records) = audit.wallet_records(4, &wallet, None, None, None, None).unwrap();
+        assert_eq!(records[1].outcome, RelayOutcome::Failed);
+        assert_eq!(records[1].error.as_deref(), Some("Bundle transaction was not included within 25 blocks"));
+        assert_eq!(records[0].outcome, RelayOutcome::Submitted);
+        assert_eq!(audit.pending().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn multisend_is_decoded_into_calls() {
+        let chain = test_config().default_chain();
+        let payment = build_multisend_bytes(
+            &[0u8], &[2u8; 20], &ethabi::encode(&[ethabi::Token::Uint(U256::from(10))]), &vec![]
+        );
+        let data: Bytes = multisend::functions::multi_send::encode_input(payment).into();
+
+        let calls = relayed_calls(&chain, 1, chain.multisend_address, U256::zero(), &data);
+        assert_eq!(calls, vec![RelayedCall {
+            operation: 0, to: Address::from([2u8; 20]), value: U256::from(10), data: Bytes(vec![])
+        }]);
+        assert_eq!(relayed_calls(&chain, 0, Address::from([3u8; 20]), U256::zero(), &data).len(), 1);
+    }
+
+    #[test]
+    fn csv_escapes_fields() {
+        let audit = AuditLog::new(None, Webhooks::new(None, 8));
+        let wallet = Address::from([1u8; 20]);
+        audit.record(attempt(wallet), None, 1, &Err(anyhow::anyhow!("Invalid \"data\", rejected")));
+
+        let csv = to_csv(&audit.wallet_records(4, &wallet, None, None, None, None).unwrap().1);
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].ends_with(",execute_safe,,failed,,,,10,,,,,,1,0,\"Invalid \"\"data\"\", rejected\""));
+    }
+
+    #[test]
+    fn fee_is_paid_by_the_last_call() {
+        let relayer = Address::from([2u8; 20]);
+        let payment = RelayedCall { operation: 0, to: relayer, value: U256::from(10), data: Bytes(vec![]) };
+        let call = RelayedCall { operation: 0, to: Address::from([3u8; 20]), value: U256::from(5), data: Bytes(vec![1]) };
+        assert_eq!(relayer_payment(relayer, &[call.clone(), payment.clone()]), U256::from(10));
+        assert_eq!(relayer_payment(relayer, &[payment, call]), U256::zero());
+        assert_eq!(relayer_payment(relayer, &[]), U256::zero());
+    }
+
+    #[test]
+    fn failed_safe_transactions_do_not_collect_the_fee() {
+        let audit = AuditLog::new(None, Webhooks::new(None, 8));
+        let wallet = Address::from([1u8; 20]);
+        audit.record(attempt(wallet), Some(BackendType::Direct), 1, &Ok("0x01".to_string()));
+        let record = audit.wallet_records(4, &wallet, None, None, None, None).unwrap().1.remove(0);
+        let receipt = |event: &str| TransactionReceipt {
+            transaction_hash: H256::zero(),
+            block_number: Some(U256::from(16)),
+            gas_used: U256::from(100_000),
+            effective_gas_price: None,
+            status: Some(U256::one()),
+            logs: vec![Log {
+                address: wallet,
+                topics: vec![H256::from(keccak256(event))],
+                data: Bytes(vec![0; 64]),
+                block_number: None
+            }]
+        };
+
+        assert_eq!(collected_fee(&record, &receipt("ExecutionSuccess(bytes32,uint256)")), U256::from(10));
+        assert_eq!(collected_fee(&record, &receipt("ExecutionFailure(bytes32,uint256)")), U256::zero());
+        let mut reverted = receipt("ExecutionSuccess(bytes32,uint256)");
+        reverted.status = Some(U256::zero());
+        assert_eq!(collected_fee(&record, &reverted), U256::zero());
+    }
+}
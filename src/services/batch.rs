@@ -0,0 +1,792 @@
+use crate::config::Config;
+use crate::models::{
+    BatchItemReceipt, BatchItemStatus, BatchState, BatchStatus, ExecutePayload, RelayState, RelayedCall, RuntimeSettings
+};
+use crate::providers::accounts::safe::SafeAccount;
+use crate::providers::accounts::{check_fee, Account, Estimation};
+use crate::providers::ethereum::backend::{backend_type, relay_backend, BackendType, RouteGroup};
+use crate::providers::ethereum::hash::keccak256;
+use crate::providers::ethereum::types::Bytes;
+use crate::providers::ethereum::{to_typed_result, EthereumProvider, TransactionReceipt};
+use crate::providers::store::Store;
+use crate::services::audit::{relayed_calls, relayer_payment, AuditLog, RelayAttempt};
+use crate::services::settings::{effective_chain, Settings};
+use crate::services::transactions::{build_multisend_bytes, execute_with_estimation};
+use crate::utils::context::Context;
+use crate::utils::errors::Rejection;
+use crate::utils::metrics;
+use anyhow::Result;
+use ethabi_contract::use_contract;
+use ethereum_types::{Address, H256, U256};
+use redis::Commands;
+use rocket::http::Status;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use_contract!(multisend, "./res/multisend.json");
+
+const NEXT_ID_KEY: &str = "batches:next_id";
+/// Batches that were not submitted yet by the time (unix ms) their window closes
+const CLOSING_KEY: &str = "batches:closing";
+/// Submitted batches are kept as long as jobs so that their status can be looked up
+const BATCH_RETENTION_SECS: i64 = 7 * 24 * 60 * 60;
+/// Interval in which the worker looks for batches whose window closed
+const POLL_MS: u64 = 100;
+/// Transactions that conflict with transactions added by other instances at the same time are added again
+const ENQUEUE_ATTEMPTS: usize = 5;
+
+fn batch_key(id: u64) -> String {
+    format!("batches:batch:{}", id)
+}
+
+fn items_key(id: u64) -> String {
+    format!("batches:batch:{}:items", id)
+}
+
+fn wallets_key(id: u64) -> String {
+    format!("batches:batch:{}:wallets", id)
+}
+
+/// Batch of the chain and tenant that accepts transactions
+fn open_key(chain_id: u64, tenant: Option<&str>) -> String {
+    format!("batches:open:{}:{}", chain_id, tenant.unwrap_or_default())
+}
+
+fn now_ms() -> i64 {
+    chrono::Utc::now().timestamp_millis()
+}
+
+/// Safe transaction of a batch, with the parts of its audit record that are known when it is added
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct BatchItem {
+    wallet: Address,
+    data: Bytes,
+    estimate: U256,
+    request_id: Option<String>,
+    callback_url: Option<String>,
+    calls: Vec<RelayedCall>,
+    fee: U256
+}
+
+impl BatchItem {
+    fn estimation(&self) -> Estimation {
+        Estimation { wallet: self.wallet, data: self.data.clone(), estimate: self.estimate }
+    }
+
+    fn attempt(&self, batch: &BatchInfo) -> RelayAttempt {
+        RelayAttempt {
+            chain_id: batch.chain_id,
+            tenant: batch.tenant.clone(),
+            request_id: self.request_id.clone(),
+            callback_url: self.callback_url.clone(),
+            service: "execute_safe_batched",
+            wallet: Some(self.wallet),
+            calls: self.calls.clone(),
+            fee: self.fee,
+            fee_token: None
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct Submission {
+    /// Hash of the multiSend transaction or the error of the submission
+    result: Result<String, String>,
+    /// Positions of the transactions that were left out because they would fail
+    dropped: Vec<usize>,
+    submitted_at: i64
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+struct BatchInfo {
+    chain_id: u64,
+    tenant: Option<String>,
+    /// Set once the batch is taken for submission, later transactions go into a new batch
+    closed: bool,
+    submission: Option<Submission>
+}
+
+struct MemoryBatch {
+    info: BatchInfo,
+    items: Vec<BatchItem>,
+    /// Unix time in ms the window of the batch closes
+    closes_at: i64
+}
+
+/// Batches kept if Redis is not configured
+#[derive(Default)]
+struct Memory {
+    next_id: u64,
+    /// Batches that still accept transactions, by chain and tenant
+    open: HashMap<(u64, Option<String>), u64>,
+    batches: HashMap<u64, MemoryBatch>
+}
+
+fn load_info(con: &mut redis::Connection, id: u64) -> Result<Option<BatchInfo>> {
+    let info: Option<String> = con.get(batch_key(id))?;
+    Ok(match info {
+        Some(info) => Some(serde_json::from_str(&info)?),
+        None => None
+    })
+}
+
+/// Collects Safe transactions and submits them together in a single multiSend call once the window of their batch
+/// closes. Batches are stored in Redis if configured, so that all instances add to and submit the same batches.
+#[derive(Clone)]
+pub struct BatchQueue {
+    config: Arc<Config>,
+    store: Option<Store>,
+    memory: Arc<Mutex<Memory>>
+}
+
+impl BatchQueue {
+    fn new(config: Arc<Config>, store: Option<Store>) -> Self {
+        BatchQueue { config, store, memory: Arc::new(Mutex::new(Memory::default())) }
+    }
+
+    /// Creates the queue and starts the worker that submits the batches when their window closes.
+    pub fn start(
+        config: Arc<Config>,
+        client: reqwest::blocking::Client,
+        store: Option<Store>,
+        settings: Settings,
+        audit: AuditLog
+    ) -> Self {
+        let queue = BatchQueue::new(config, store);
+        let worker = queue.clone();
+        thread::spawn(move || {
+            loop {
+                thread::sleep(Duration::from_millis(POLL_MS));
+                if let Err(err) = worker.submit_due(&client, &settings, &audit) {
+                    log::error!("Submitting batches failed: {:?}", err);
+                }
+            }
+        });
+        queue
+    }
+
+    /// Submits the batches whose window closed. Batches of paused chains keep their transactions until the chain is
+    /// resumed.
+    fn submit_due(&self, client: &reqwest::blocking::Client, settings: &Settings, audit: &AuditLog) -> Result<()> {
+        self.evict_submitted(chrono::Utc::now().timestamp());
+        let runtime = settings.current();
+        for (id, batch) in self.due(now_ms())? {
+            if settings.paused(&runtime, RouteGroup::Batch, batch.chain_id).is_some() {
+                continue;
+            }
+            // Another instance might have taken the batch in the meantime
+            if let Some(items) = self.close(id)? {
+                let submission = self.submit_batch(client, &runtime, audit, id, &batch, &items);
+                self.complete(id, submission)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Simulates the transactions right before the submission and leaves out the ones that would fail,
+    /// so that a single transaction cannot revert the whole batch.
+    fn submit_batch(
+        &self,
+        client: &reqwest::blocking::Client,
+        runtime: &RuntimeSettings,
+        audit: &AuditLog,
+        id: u64,
+        batch: &BatchInfo,
+        items: &[BatchItem]
+    ) -> Submission {
+        let backend = backend_type(&self.config, RouteGroup::Batch, batch.tenant.as_deref()).ok();
+        let mut dropped = vec![];
+        let result = self.config.chain(batch.chain_id)
+            .ok_or_else(|| anyhow::anyhow!("Unknown chain {}", batch.chain_id))
+            .and_then(|chain| {
+                let eth_provider = EthereumProvider::for_chain(client, effective_chain(runtime, chain));
+                let account = SafeAccount { eth_provider: &eth_provider };
+                let mut included = vec![];
+                let mut fee = U256::zero();
+                for (index, item) in items.iter().enumerate() {
+                    match account.failure(&item.estimation())? {
+                        Some(reason) => {
+                            log::warn!("Leaving transaction {} out of batch {}: {}", index, id, reason);
+                            audit.record(item.attempt(batch), backend, 1, &Err(anyhow::anyhow!(reason)));
+                            dropped.push(index);
+                        },
+                        None => {
+                            fee += item.fee;
+                            included.push(item.estimation());
+                        }
+                    }
+                }
+                anyhow::ensure!(!included.is_empty(), "All transactions of the batch would fail");
+                submit(&self.config, &eth_provider, audit, batch.tenant.as_deref(), included, fee)
+            });
+        let batch_size = items.len() - dropped.len();
+        for (index, item) in items.iter().enumerate().filter(|(index, _)| !dropped.contains(index)) {
+            audit.record(item.attempt(batch), backend, batch_size, &result);
+        }
+        Submission {
+            result: result.map_err(|err| {
+                log::error!("Submitting batch {} failed: {:?}", id, err);
+                err.to_string()
+            }),
+            dropped,
+            submitted_at: chrono::Utc::now().timestamp()
+        }
+    }
+
+    /// Adds the transaction to the open batch of the chain and tenant, the window of a new batch starts with its
+    /// first transaction. A Safe can only have one transaction in a batch, as the transactions of a batch are
+    /// estimated and signed for the same Safe nonce.
+    fn enqueue(&self, chain_id: u64, tenant: Option<String>, item: BatchItem) -> Result<BatchItemReceipt> {
+        let conflict = || Rejection::new(Status::Conflict, "The Safe already has a transaction in the current batch");
+        let closes_at = now_ms() + self.config.batch_window_ms as i64;
+        let max_size = self.config.batch_max_size;
+        let store = match &self.store {
+            Some(store) => store,
+            None => {
+                let mut memory = self.memory.lock().unwrap();
+                let Memory { next_id, open, batches } = &mut *memory;
+                let key = (chain_id, tenant.clone());
+                let id = *open.entry(key.clone()).or_insert_with(|| {
+                    *next_id += 1;
+                    batches.insert(*next_id, MemoryBatch {
+                        info: BatchInfo { chain_id, tenant, closed: false, submission: None },
+                        items: vec![],
+                        closes_at
+                    });
+                    *next_id
+                });
+                let batch = batches.get_mut(&id).unwrap();
+                anyhow::ensure!(!batch.items.iter().any(|existing| existing.wallet == item.wallet), conflict());
+                batch.items.push(item);
+                if batch.items.len() >= max_size {
+                    // Full batches are submitted right away, new transactions go into a new batch
+                    open.remove(&key);
+                    batch.closes_at = 0;
+                }
+                return Ok(BatchItemReceipt { batch_id: id, index: batch.items.len() - 1 });
+            }
+        };
+        // Transactions are added with compare and set (`WATCH`/`MULTI`), so that they are neither added to a batch
+        // that was closed nor to the same position by different instances
+        let mut con = store.connection()?;
+        let open_key = open_key(chain_id, tenant.as_deref());
+        for _ in 0..ENQUEUE_ATTEMPTS {
+            let _: () = redis::cmd("WATCH").arg(&open_key).query(&mut con)?;
+            let open: Option<u64> = con.get(&open_key)?;
+            let open = match open {
+                Some(id) => {
+                    let _: () = redis::cmd("WATCH").arg(batch_key(id)).arg(items_key(id)).query(&mut con)?;
+                    load_info(&mut con, id)?.filter(|info| !info.closed).map(|_| id)
+                },
+                None => None
+            };
+            let mut pipe = redis::pipe();
+            pipe.atomic();
+            let (id, index) = match open {
+                Some(id) => {
+                    let taken: bool = con.sismember(wallets_key(id), format!("{:?}", item.wallet))?;
+                    if taken {
+                        let _: () = redis::cmd("UNWATCH").query(&mut con)?;
+                        anyhow::bail!(conflict());
+                    }
+                    let length: usize = con.llen(items_key(id))?;
+                    (id, length)
+                },
+                None => {
+                    let id: u64 = con.incr(NEXT_ID_KEY, 1)?;
+                    let info = BatchInfo { chain_id, tenant: tenant.clone(), closed: false, submission: None };
+                    pipe.set(&open_key, id).ignore()
+                        .set(batch_key(id), serde_json::to_string(&info)?).ignore()
+                        .zadd(CLOSING_KEY, id, closes_at).ignore();
+                    (id, 0)
+                }
+            };
+            pipe.sadd(wallets_key(id), format!("{:?}", item.wallet)).ignore()
+                .rpush(items_key(id), serde_json::to_string(&item)?).ignore();
+            if index + 1 >= max_size {
+                // Full batches are submitted right away, new transactions go into a new batch
+                pipe.del(&open_key).ignore().zadd(CLOSING_KEY, id, 0).ignore();
+            }
+            let committed: Option<()> = pipe.query(&mut con)?;
+            if committed.is_some() {
+                return Ok(BatchItemReceipt { batch_id: id, index });
+            }
+        }
+        anyhow::bail!(Rejection::new(Status::Conflict, "The batch was changed by other requests at the same time"))
+    }
+
+    /// Batches whose window closed before the time (unix ms)
+    fn due(&self, now_ms: i64) -> Result<Vec<(u64, BatchInfo)>> {
+        match &self.store {
+            Some(store) => {
+                let mut con = store.connection()?;
+                let ids: Vec<u64> = con.zrangebyscore(CLOSING_KEY, 0, now_ms)?;
+                let mut due = vec![];
+                for id in ids {
+                    match load_info(&mut con, id)? {
+                        Some(info) => due.push((id, info)),
+                        None => { let _: () = con.zrem(CLOSING_KEY, id)?; }
+                    }
+                }
+                Ok(due)
+            },
+            None => {
+                let memory = self.memory.lock().unwrap();
+                Ok(memory.batches.iter()
+                    .filter(|(_, batch)| !batch.info.closed && batch.closes_at <= now_ms)
+                    .map(|(id, batch)| (*id, batch.info.clone()))
+                    .collect())
+            }
+        }
+    }
+
+    /// Takes the batch for submission and returns its transactions, `None` if it was taken already.
+    fn close(&self, id: u64) -> Result<Option<Vec<BatchItem>>> {
+        match &self.store {
+            Some(store) => {
+                let mut con = store.connection()?;
+                // Transactions added at the same time abort the close, it is tried again with the next poll
+                let _: () = redis::cmd("WATCH").arg(batch_key(id)).arg(items_key(id)).query(&mut con)?;
+                let mut info = match load_info(&mut con, id)?.filter(|info| !info.closed) {
+                    Some(info) => info,
+                    None => {
+                        let _: () = redis::cmd("UNWATCH").query(&mut con)?;
+                        return Ok(None);
+                    }
+                };
+                info.closed = true;
+                let committed: Option<()> = redis::pipe().atomic()
+                    .set(batch_key(id), serde_json::to_string(&info)?).ignore()
+                    .zrem(CLOSING_KEY, id).ignore()
+                    .query(&mut con)?;
+                if committed.is_none() {
+                    return Ok(None);
+                }
+                let items: Vec<String> = con.lrange(items_key(id), 0, -1)?;
+                Ok(Some(items.iter()
+                    .map(|item| -> Result<BatchItem> { Ok(serde_json::from_str(item)?) })
+                    .collect::<Result<_>>()?))
+            },
+            None => {
+                let mut memory = self.memory.lock().unwrap();
+                let Memory { open, batches, .. } = &mut *memory;
+                let batch = match batches.get_mut(&id).filter(|batch| !batch.info.closed) {
+                    Some(batch) => batch,
+                    None => return Ok(None)
+                };
+                batch.info.closed = true;
+                open.retain(|_, open_id| *open_id != id);
+                Ok(Some(batch.items.clone()))
+            }
+        }
+    }
+
+    fn complete(&self, id: u64, submission: Submission) -> Result<()> {
+        match &self.store {
+            Some(store) => {
+                let mut con = store.connection()?;
+                let mut info = load_info(&mut con, id)?.ok_or_else(|| anyhow::anyhow!("Unknown batch {}", id))?;
+                info.submission = Some(submission);
+                let retention = BATCH_RETENTION_SECS as usize;
+                let _: () = redis::pipe().atomic()
+                    .set_ex(batch_key(id), serde_json::to_string(&info)?, retention).ignore()
+                    .expire(items_key(id), retention).ignore()
+                    .expire(wallets_key(id), retention).ignore()
+                    .query(&mut con)?;
+            },
+            None => {
+                if let Some(batch) = self.memory.lock().unwrap().batches.get_mut(&id) {
+                    batch.info.submission = Some(submission);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops the batches that were submitted before the retention window, Redis expires them.
+    fn evict_submitted(&self, now: i64) {
+        self.memory.lock().unwrap().batches.retain(|_, batch| {
+            batch.info.submission.as_ref()
+                .map_or(true, |submission| submission.submitted_at + BATCH_RETENTION_SECS > now)
+        });
+    }
+
+    /// Batch and the Safes of its transactions, in order of the batch
+    fn batch(&self, id: u64) -> Result<Option<(BatchInfo, Vec<Address>)>> {
+        match &self.store {
+            Some(store) => {
+                let mut con = store.connection()?;
+                let info = match load_info(&mut con, id)? {
+                    Some(info) => info,
+                    None => return Ok(None)
+                };
+                let items: Vec<String> = con.lrange(items_key(id), 0, -1)?;
+                let wallets = items.iter()
+                    .map(|item| -> Result<Address> { Ok(serde_json::from_str::<BatchItem>(item)?.wallet) })
+                    .collect::<Result<_>>()?;
+                Ok(Some((info, wallets)))
+            },
+            None => {
+                let memory = self.memory.lock().unwrap();
+                Ok(memory.batches.get(&id).map(|batch| {
+                    (batch.info.clone(), batch.items.iter().map(|item| item.wallet).collect())
+                }))
+            }
+        }
+    }
+}
+
+/// Builds the multiSend call that executes all Safe transactions from the relayer.
+fn build_batch(multisend: Address, items: &[Estimation]) -> Estimation {
+    let transactions: Vec<u8> = items.iter().flat_map(|item| build_multisend_bytes(
+        &[0u8],
+        &item.wallet.to_fixed_bytes(),
+        &ethabi::encode(&[ethabi::Token::Uint(U256::zero())]),
+        &item.data.0,
+    )).collect();
+    Estimation {
+        wallet: multisend,
+        data: multisend::functions::multi_send::encode_input(transactions).into(),
+        // Every item estimate already contains the base costs of a transaction, so this is an upper bound
+        estimate: items.iter().fold(U256::zero(), |sum, item| sum + item.estimate)
+    }
+}
+
+fn submit(
+    config: &Config,
+    eth_provider: &EthereumProvider,
+    audit: &AuditLog,
+    tenant: Option<&str>,
+    items: Vec<Estimation>,
+    fee: U256
+) -> Result<String> {
+    let backend_type = backend_type(config, RouteGroup::Batch, tenant)?;
+    // The status of a batch is looked up via the transaction receipt, which is not known for ITX relays
+    anyhow::ensure!(backend_type != BackendType::Itx, "ITX backend is not supported for batches");
+    let chain = eth_provider.chain();
+    let multisend = chain.batch_multisend_address
+        .ok_or_else(|| anyhow::anyhow!("Batching is not enabled on chain {}", chain.chain_id))?;
+    let batch = build_batch(multisend, &items);
+    log::info!("Submit batch of {} Safe transactions on chain {}", items.len(), chain.chain_id);
+    let backend = relay_backend(config, chain, backend_type, audit.on_dropped(chain.chain_id))?;
+    execute_with_estimation(eth_provider, backend.as_ref(), batch, fee)
+}
+
+/// Validates and estimates the Safe transaction before adding it to the current batch.
+pub fn execute_safe_batched(context: &Context, payload: ExecutePayload) -> Result<BatchItemReceipt> {
+    let tenant = context.tenant()?;
+    let eth_provider = EthereumProvider::new(context);
+    let settings = context.settings();
+    let runtime = context.runtime_settings();
+    anyhow::ensure!(
+        eth_provider.chain().batch_multisend_address.is_some(),
+        Rejection::new(Status::NotFound, "Batching is not enabled on this chain")
+    );
+    settings.check_paused(&runtime, RouteGroup::Batch, eth_provider.chain().chain_id)?;
+    settings.check_relay(&runtime, &tenant, context.client_address(), Some(payload.wallet))?;
+    let callback_url = context.callback_url()?;
+
+    check_fee(
+        &eth_provider,
+        payload.transaction.to,
+        payload.transaction.value,
+        &payload.transaction.data.0,
+        payload.transaction.operation,
+    )?;
+
+    let account = SafeAccount {
+        eth_provider: &eth_provider,
+    };
+    let estimation = account.estimate(&payload)?;
+
+    let chain = eth_provider.chain();
+    let tx = &payload.transaction;
+    let calls = relayed_calls(chain, tx.operation, tx.to, tx.value, &tx.data);
+    let item = BatchItem {
+        wallet: estimation.wallet,
+        data: estimation.data,
+        estimate: estimation.estimate,
+        request_id: Some(context.request_id()),
+        callback_url,
+        fee: relayer_payment(eth_provider.account()?, &calls),
+        calls
+    };
+    context.batch_queue().enqueue(chain.chain_id, tenant, item)
+}
+
+/// Matches the execution events of the Safes, in order of the batch, to the batched transactions.
+pub fn item_states(wallets: &[Address], receipt: &TransactionReceipt) -> Vec<RelayState> {
+    if !receipt.is_success() {
+        // multiSend reverts as a whole
+        return wallets.iter().map(|_| RelayState::Failed).collect();
+    }
+    let success_topic = H256::from(keccak256("ExecutionSuccess(bytes32,uint256)"));
+    let failure_topic = H256::from(keccak256("ExecutionFailure(bytes32,uint256)"));
+    let mut logs = receipt.logs.iter();
+    wallets.iter().map(|wallet| {
+        logs.by_ref()
+            .find(|log| log.address == *wallet && (log.topics.first() == Some(&success_topic) || log.topics.first() == Some(&failure_topic)))
+            .map(|log| if log.topics[0] == success_topic { RelayState::Success } else { RelayState::Failed })
+            .unwrap_or(RelayState::Failed)
+    }).collect()
+}
+
+pub fn batch_status(context: &Context, batch_id: u64) -> Result<BatchStatus> {
+    let (batch, wallets) = context.batch_queue().batch(batch_id)?
+        .filter(|(batch, _)| batch.chain_id == context.chain().chain_id)
+        .ok_or_else(|| Rejection::new(Status::NotFound, "Unknown batch"))?;
+    let dropped = batch.submission.as_ref().map(|submission| submission.dropped.clone()).unwrap_or_default();
+    // Transactions that were left out failed, the states are those of the submitted transactions
+    let items_with = |states: Vec<RelayState>| -> Vec<BatchItemStatus> {
+        let mut states = states.into_iter();
+        wallets.iter().enumerate()
+            .map(|(index, wallet)| {
+                let state = if dropped.contains(&index) {
+                    RelayState::Failed
+                } else {
+                    states.next().unwrap_or(RelayState::Pending)
+                };
+                BatchItemStatus { index, wallet: *wallet, state }
+            })
+            .collect()
+    };
+    let included: Vec<Address> = wallets.iter().enumerate()
+        .filter(|(index, _)| !dropped.contains(index))
+        .map(|(_, wallet)| *wallet)
+        .collect();
+    let transaction_hash: H256 = match batch.submission.map(|submission| submission.result) {
+        None => return Ok(BatchStatus {
+            batch_id,
+            state: BatchState::Queued,
+            transaction_hash: None,
+            block_number: None,
+            error: None,
+            items: items_with(wallets.iter().map(|_| RelayState::Pending).collect())
+        }),
+        Some(Err(error)) => return Ok(BatchStatus {
+            batch_id,
+            state: BatchState::Failed,
+            transaction_hash: None,
+            block_number: None,
+            error: Some(error),
+            items: items_with(included.iter().map(|_| RelayState::Failed).collect())
+        }),
+        Some(Ok(hash)) => serde_json::from_value(serde_json::value::Value::String(hash))?
+    };
+
+    let eth_provider = EthereumProvider::new(context);
+    let receipt: Option<TransactionReceipt> = to_typed_result(eth_provider.transaction_receipt(&transaction_hash)?)?;
+    Ok(match receipt.filter(|receipt| receipt.block_number.is_some()) {
+        Some(receipt) => {
+            metrics::observe_receipt(&receipt);
+            BatchStatus {
+                batch_id,
+                state: if receipt.is_success() { BatchState::Success } else { BatchState::Failed },
+                transaction_hash: Some(transaction_hash),
+                block_number: receipt.block_number,
+                error: None,
+                items: items_with(item_states(&included, &receipt))
+            }
+        },
+        None => BatchStatus {
+            batch_id,
+            state: BatchState::Pending,
+            transaction_hash: Some(transaction_hash),
+            block_number: None,
+            error: None,
+            items: items_with(included.iter().map(|_| RelayState::Pending).collect())
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{test_config, ChainConfig};
+    use crate::models::RelayOutcome;
+    use crate::providers::accounts::utils::try_decode_multisend_bytes;
+    use crate::providers::ethereum::mock::MockRpcServer;
+    use crate::providers::ethereum::Log;
+    use crate::services::webhooks::Webhooks;
+    use serde_json::json;
+
+    fn address(byte: u8) -> Address {
+        Address::from([byte; 20])
+    }
+
+    fn execution_log(wallet: Address, event: &str) -> Log {
+        Log {
+            address: wallet,
+            topics: vec![H256::from(keccak256(event))],
+            data: Bytes(vec![0; 64]),
+            block_number: None
+        }
+    }
+
+    fn item(wallet: Address) -> BatchItem {
+        BatchItem {
+            wallet,
+            data: Bytes(vec![0xaa]),
+            estimate: U256::from(50_000),
+            request_id: None,
+            callback_url: None,
+            calls: vec![],
+            fee: U256::zero()
+        }
+    }
+
+    fn receipt(status: u64, logs: Vec<Log>) -> TransactionReceipt {
+        TransactionReceipt {
+            transaction_hash: H256::zero(),
+            block_number: Some(U256::from(16)),
+            gas_used: U256::from(100_000),
+            effective_gas_price: None,
+            status: Some(U256::from(status)),
+            logs
+        }
+    }
+
+    #[test]
+    fn item_states_follow_execution_events() {
+        let wallets = vec![address(1), address(2), address(1)];
+        let receipt = receipt(1, vec![
+            execution_log(address(1), "ExecutionSuccess(bytes32,uint256)"),
+            // Events emitted by the executed transaction are ignored
+            Log { address: address(9), topics: vec![H256::zero()], data: Bytes(vec![]), block_number: None },
+            execution_log(address(2), "ExecutionFailure(bytes32,uint256)"),
+            execution_log(address(1), "ExecutionSuccess(bytes32,uint256)"),
+        ]);
+
+        assert_eq!(item_states(&wallets, &receipt), vec![RelayState::Success, RelayState::Failed, RelayState::Success]);
+    }
+
+    #[test]
+    fn item_states_of_reverted_batch() {
+        let wallets = vec![address(1), address(2)];
+        let receipt = receipt(0, vec![]);
+
+        assert_eq!(item_states(&wallets, &receipt), vec![RelayState::Failed, RelayState::Failed]);
+    }
+
+    #[test]
+    fn safe_can_only_have_one_transaction_per_batch() {
+        let queue = BatchQueue::new(test_config(), None);
+        let first = queue.enqueue(4, None, item(address(1))).unwrap();
+        let second = queue.enqueue(4, None, item(address(2))).unwrap();
+        assert_eq!((first.batch_id, second.batch_id, second.index), (1, 1, 1));
+
+        let err = queue.enqueue(4, None, item(address(1))).unwrap_err();
+        assert_eq!(err.downcast_ref::<Rejection>().unwrap().status, Status::Conflict);
+        // Other tenants and chains have their own batches
+        assert!(queue.enqueue(4, Some("acme".to_string()), item(address(1))).is_ok());
+        assert!(queue.enqueue(1, None, item(address(1))).is_ok());
+
+        assert_eq!(queue.close(1).unwrap().unwrap().len(), 2);
+        assert!(queue.close(1).unwrap().is_none());
+        let next = queue.enqueue(4, None, item(address(1))).unwrap();
+        assert_eq!((next.batch_id, next.index), (4, 0));
+    }
+
+    #[test]
+    fn batches_are_due_when_their_window_closes() {
+        let mut config = test_config();
+        Arc::get_mut(&mut config).unwrap().batch_max_size = 2;
+        let window = config.batch_window_ms as i64;
+        let queue = BatchQueue::new(config, None);
+        let now = now_ms();
+        queue.enqueue(4, None, item(address(1))).unwrap();
+        queue.enqueue(4, Some("acme".to_string()), item(address(1))).unwrap();
+        queue.enqueue(4, Some("acme".to_string()), item(address(2))).unwrap();
+
+        // Full batches are due right away
+        let due: Vec<u64> = queue.due(now).unwrap().into_iter().map(|(id, _)| id).collect();
+        assert_eq!(due, vec![2]);
+        let mut due: Vec<u64> = queue.due(now_ms() + window).unwrap().into_iter().map(|(id, _)| id).collect();
+        due.sort_unstable();
+        assert_eq!(due, vec![1, 2]);
+        // A new batch of the tenant has its own window
+        assert_eq!(queue.enqueue(4, Some("acme".to_string()), item(address(3))).unwrap().batch_id, 3);
+        queue.close(1).unwrap();
+        assert!(queue.due(now_ms() + window - 1000).unwrap().iter().all(|(id, _)| *id == 2));
+    }
+
+    #[test]
+    fn submitted_batches_are_evicted_after_retention() {
+        let queue = BatchQueue::new(test_config(), None);
+        queue.enqueue(4, None, item(address(1))).unwrap();
+        queue.close(1).unwrap();
+        queue.enqueue(4, None, item(address(2))).unwrap();
+        let submission = Submission { result: Ok("0x01".to_string()), dropped: vec![], submitted_at: 1000 };
+        queue.complete(1, submission).unwrap();
+
+        queue.evict_submitted(1000 + BATCH_RETENTION_SECS - 1);
+        assert!(queue.batch(1).unwrap().is_some());
+        queue.evict_submitted(1000 + BATCH_RETENTION_SECS);
+        assert!(queue.batch(1).unwrap().is_none());
+        // Queued batches are kept
+        assert_eq!(queue.batch(2).unwrap().unwrap().1, vec![address(2)]);
+    }
+
+    #[test]
+    fn transactions_that_would_fail_are_left_out() {
+        let server = MockRpcServer::start(|request| match request.method.as_str() {
+            "eth_call" if request.params[0]["to"] == json!(address(1)) =>
+                Err(json!({ "code": 3, "message": "execution reverted: GS013" })),
+            "eth_call" => Ok(json!(format!("0x{:064x}", 1))),
+            "eth_getTransactionCount" => Ok(json!("0x0")),
+            "eth_gasPrice" => Ok(json!("0x3b9aca00")),
+            "eth_sendRawTransaction" => Ok(json!(format!("{:?}", H256::from([2u8; 32])))),
+            _ => Err(json!({ "code": -32601, "message": "Method not found" }))
+        });
+        let mut config = test_config();
+        let chain = ChainConfig {
+            chain_id: 1010,
+            rpc_url: server.url.clone(),
+            batch_multisend_address: Some(address(9)),
+            ..(*config.default_chain()).clone()
+        };
+        Arc::get_mut(&mut config).unwrap().chains.insert(1010, Arc::new(chain));
+        let queue = BatchQueue::new(config, None);
+        let audit = AuditLog::new(None, Webhooks::new(None, 8));
+        queue.enqueue(1010, None, item(address(1))).unwrap();
+        queue.enqueue(1010, None, item(address(2))).unwrap();
+
+        let (_, batch) = queue.due(now_ms() + 60_000).unwrap().remove(0);
+        let items = queue.close(1).unwrap().unwrap();
+        let client = reqwest::blocking::Client::new();
+        let submission = queue.submit_batch(&client, &RuntimeSettings::default(), &audit, 1, &batch, &items);
+
+        assert_eq!(submission.dropped, vec![0]);
+        assert_eq!(submission.result, Ok(format!("{:?}", H256::from([2u8; 32]))));
+        let (_, dropped) = audit.wallet_records(1010, &address(1), None, None, None, None).unwrap();
+        assert_eq!(dropped[0].outcome, RelayOutcome::Failed);
+        assert!(dropped[0].error.as_deref().unwrap().contains("GS013"));
+        let (_, submitted) = audit.wallet_records(1010, &address(2), None, None, None, None).unwrap();
+        assert_eq!((submitted[0].outcome, submitted[0].batch_size), (RelayOutcome::Submitted, 1));
+    }
+
+    #[test]
+    fn batch_is_multisend_of_calls() {
+        let items = vec![
+            Estimation { wallet: address(1), data: Bytes(vec![0xaa]), estimate: U256::from(50_000) },
+            Estimation { wallet: address(2), data: Bytes(vec![0xbb, 0xcc]), estimate: U256::from(70_000) },
+        ];
+        let batch = build_batch(address(3), &items);
+
+        assert_eq!(batch.wallet, address(3));
+        assert_eq!(batch.estimate, U256::from(120_000));
+        let tokens = ethabi::decode(&[ethabi::ParamType::Bytes], &batch.data.0[4..]).unwrap();
+        let transactions = try_decode_multisend_bytes(&tokens[0].clone().into_bytes().unwrap()).unwrap();
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(transactions[0].to, address(1));
+        assert_eq!(transactions[0].operation, 0);
+        assert_eq!(transactions[0].value, U256::zero());
+        assert_eq!(transactions[1].data, Bytes(vec![0xbb, 0xcc]));
+    }
+}
@@ -1,17 +1,21 @@
-use crate::config::{factory_address};
-use crate::models::{DeployPayload};
-use crate::providers::ethereum::transaction::Transaction;
-use crate::providers::accounts::{check_fee, Estimation};
-use crate::providers::ethereum::{to_string_result, Call, CallOptions, EthereumProvider};
+use crate::models::{DeployPayload, DeploymentPrediction, DeploymentState, DeploymentStatus, PredictPayload, SafeDeployPayload, SafeTransaction};
+use crate::providers::accounts::{check_fee, check_payment_tx, Estimation};
+use crate::providers::ethereum::backend::RelayBackend;
+use crate::providers::ethereum::{to_string_result, to_typed_result, Call, CallOptions, EthereumProvider, TransactionReceipt};
+use crate::providers::ethereum::hash::{create2_address, keccak256};
 use crate::providers::ethereum::types::Bytes;
+use crate::services::transactions::execute_with_estimation;
 use crate::utils::context::Context;
+use crate::utils::errors::Rejection;
+use crate::utils::metrics;
 use anyhow::Result;
 use ethabi_contract::use_contract;
-use ethereum_types::{ U256};
-use serde_json;
+use ethereum_types::{Address, H256, U256};
 
 // https://github.com/openethereum/ethabi/blob/master/tests/src/lib.rs
 use_contract!(factory, "./res/factory.json");
+use_contract!(proxy_factory, "./res/proxy_factory.json");
+use_contract!(safe, "./res/safe.json");
 
 fn estimate(eth_provider: &EthereumProvider, payload: &DeployPayload) -> Result<Estimation> {
     log::debug!("estimate");
@@ -25,15 +29,20 @@ fn estimate(eth_provider: &EthereumProvider, payload: &DeployPayload) -> Result<
         payload.signatures.clone(),
         payload.nonce
     ).into();
-    let factory_address = serde_json::from_value(serde_json::value::Value::String(factory_address()))?;
+    let factory_address = eth_provider.chain().factory_address
+        .ok_or_else(|| Rejection::unprocessable("No factory configured"))?;
     log::debug!("factory: {}", factory_address);
+    estimate_factory_call(eth_provider, factory_address, data)
+}
+
+fn estimate_factory_call(eth_provider: &EthereumProvider, factory_address: Address, data: Bytes) -> Result<Estimation> {
     let call = Call {
         to: Some(factory_address),
         value: None,
         data: Some(data.clone()),
         gas: None,
         gas_price: None,
-        from: Some(eth_provider.account()),
+        from: Some(eth_provider.account()?),
     };
     let options = CallOptions {
         block: "latest".to_string(),
@@ -46,33 +55,220 @@ fn estimate(eth_provider: &EthereumProvider, payload: &DeployPayload) -> Result<
     Ok(Estimation { wallet: factory_address, estimate: U256::from(estimate), data })
 }
 
-fn execute_with_estimation(eth_provider: &EthereumProvider, estimation: Estimation) -> Result<String> {
-    let nonce_result = to_string_result(eth_provider.nonce()?)?;
-    let nonce = u64::from_str_radix(nonce_result.trim_start_matches("0x"), 16)?;
-    let tx = Transaction {
-        to: Some(estimation.wallet),
-        value: U256::zero(),
-        data: &estimation.data,
-        gas: estimation.estimate,
-        gas_price: U256::from(1_000_000_000),
-        nonce: U256::from(nonce),
-    };
-    //TODO check fee > gas * gas_price
-    to_string_result(eth_provider.execute(&tx)?)
-}
-
-pub fn deploy(context: &Context, payload: DeployPayload) -> Result<String> {
-    let eth_provider = EthereumProvider::new(context);
-
+pub fn deploy(
+    eth_provider: &EthereumProvider,
+    backend: &dyn RelayBackend,
+    payload: DeployPayload
+) -> Result<String> {
     check_fee(
-        &eth_provider,
+        eth_provider,
         payload.transaction.to,
         payload.transaction.value,
         &payload.transaction.data.0,
         payload.transaction.operation,
     )?;
 
-    let estimation = estimate(&eth_provider,&payload)?;
+    let estimation = estimate(eth_provider, &payload)?;
+
+    execute_with_estimation(eth_provider, backend, estimation, eth_provider.chain().transaction_fee)
+}
+
+/// Checks that the refund configured in the Safe setup pays exactly the fee to the relayer.
+fn check_setup_payment(eth_provider: &EthereumProvider, payload: &SafeDeployPayload) -> Result<()> {
+    let fee = eth_provider.chain().transaction_fee;
+    if fee != U256::zero() {
+        anyhow::ensure!(
+            payload.payment_token == Address::zero(),
+            Rejection::unprocessable("Payment has to be sent in the native coin")
+        );
+        let payment_tx = SafeTransaction {
+            to: payload.payment_receiver,
+            value: payload.payment,
+            data: Bytes(vec![]),
+            operation: 0,
+            safe_tx_gas: U256::zero()
+        };
+        check_payment_tx(eth_provider.account()?, &payment_tx, fee)?;
+    }
+    Ok(())
+}
+
+pub fn deploy_safe(
+    eth_provider: &EthereumProvider,
+    backend: &dyn RelayBackend,
+    payload: SafeDeployPayload
+) -> Result<String> {
+    check_setup_payment(eth_provider, &payload)?;
+
+    let initializer = safe::functions::setup::encode_input(
+        payload.owners.clone(),
+        payload.threshold,
+        payload.to,
+        payload.data.clone(),
+        payload.fallback_handler,
+        payload.payment_token,
+        payload.payment,
+        payload.payment_receiver
+    );
+    let data: Bytes = proxy_factory::functions::create_proxy_with_nonce::encode_input(
+        payload.singleton,
+        initializer,
+        payload.salt_nonce
+    ).into();
+    let factory_address = eth_provider.chain().safe_proxy_factory_address
+        .ok_or_else(|| Rejection::unprocessable("No Safe proxy factory configured"))?;
+    let estimation = estimate_factory_call(eth_provider, factory_address, data)?;
+
+    execute_with_estimation(eth_provider, backend, estimation, eth_provider.chain().transaction_fee)
+}
+
+/// Calculates the address of the proxy that will be created by the proxy factory used by the factory.
+pub fn predict(context: &Context, payload: PredictPayload) -> Result<DeploymentPrediction> {
+    let eth_provider = EthereumProvider::new(context);
+
+    let factory_address = eth_provider.chain().factory_address
+        .ok_or_else(|| Rejection::unprocessable("No factory configured"))?;
+    let proxy_factory_result = eth_provider.view(factory_address, factory::functions::factory::encode_input())?;
+    let proxy_factory = factory::functions::factory::decode_output(&proxy_factory_result.0)?;
+
+    let creation_code_result = eth_provider.view(proxy_factory, proxy_factory::functions::proxy_creation_code::encode_input())?;
+    let creation_code = proxy_factory::functions::proxy_creation_code::decode_output(&creation_code_result.0)?;
+
+    let salt = keccak256([
+        &keccak256(&payload.initializer.0)[..],
+        &ethabi::encode(&[ethabi::Token::Uint(payload.nonce)])
+    ].concat());
+    let init_code = [creation_code, ethabi::encode(&[ethabi::Token::Address(payload.implementation)])].concat();
+    let address = create2_address(proxy_factory, H256::from(salt), init_code);
+
+    let code: Bytes = to_string_result(eth_provider.code(&address)?)?.into();
+    Ok(DeploymentPrediction {
+        address,
+        proxy_factory,
+        deployed: !code.0.is_empty()
+    })
+}
+
+fn find_created_proxy(receipt: &TransactionReceipt) -> Option<Address> {
+    receipt.logs.iter().find_map(|log| {
+        proxy_factory::events::proxy_creation::parse_log(ethabi::RawLog {
+            topics: log.topics.clone(),
+            data: log.data.0.clone(),
+        }).ok().map(|event| event.proxy)
+    })
+}
+
+/// Checks the receipt of a deployment transaction and extracts the created wallet from the proxy creation logs.
+/// Deployments that are not mined yet are pending, clients poll until they are mined.
+pub fn status(context: &Context, tx_hash: H256) -> Result<DeploymentStatus> {
+    deployment_status(&EthereumProvider::new(context), tx_hash)
+}
+
+fn deployment_status(eth_provider: &EthereumProvider, tx_hash: H256) -> Result<DeploymentStatus> {
+    let receipt: Option<TransactionReceipt> = to_typed_result(eth_provider.transaction_receipt(&tx_hash)?)?;
+    let receipt = match receipt {
+        Some(receipt) if receipt.block_number.is_some() => receipt,
+        _ => return Ok(DeploymentStatus {
+            transaction_hash: tx_hash,
+            state: DeploymentState::Pending,
+            wallet: None,
+            block_number: None,
+            initialized: false
+        })
+    };
+    metrics::observe_receipt(&receipt);
+    let wallet = find_created_proxy(&receipt);
+    log::debug!("deployment {:?} created {:?}", tx_hash, wallet);
+    Ok(DeploymentStatus {
+        transaction_hash: tx_hash,
+        state: if receipt.is_success() { DeploymentState::Success } else { DeploymentState::Failed },
+        // The factory reverts if the initializer fails, so a created proxy means that it was initialized
+        initialized: receipt.is_success() && wallet.is_some(),
+        wallet,
+        block_number: receipt.block_number
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::ethereum::mock::MockRpcServer;
+    use serde_json::{json, Value};
+
+    const TX_HASH: &str = "0x4444444444444444444444444444444444444444444444444444444444444444";
+    const PROXY: &str = "0x5555555555555555555555555555555555555555";
+
+    /// Receipt of a Safe deployment, the Safe emits its setup event before the factory emits `ProxyCreation`
+    fn deployment_receipt(status: &str) -> Value {
+        json!({
+            "transactionHash": TX_HASH,
+            "blockNumber": "0x10",
+            "gasUsed": "0x3d090",
+            "status": status,
+            "logs": [
+                {
+                    "address": PROXY,
+                    "topics": ["0x141df868a6331af528e38c83b7aa03edc19be66e37ae67f9285bf4f8e3c6a1a8"],
+                    "data": "0x"
+                },
+                {
+                    "address": "0x76e2cfc1f5fa8f6a5b3fc4c8f4788f0116861f9b",
+                    "topics": ["0xa38789425dbeee0239e16ff2d2567e31720127fbc6430758c1a4efc6aef29f80"],
+                    "data": "0x0000000000000000000000005555555555555555555555555555555555555555"
+                }
+            ]
+        })
+    }
+
+    fn hash() -> H256 {
+        serde_json::from_value(Value::String(TX_HASH.to_string())).unwrap()
+    }
+
+    fn proxy() -> Address {
+        serde_json::from_value(Value::String(PROXY.to_string())).unwrap()
+    }
+
+    #[test]
+    fn proxy_is_found_in_creation_log() {
+        let receipt: TransactionReceipt = serde_json::from_value(deployment_receipt("0x1")).unwrap();
+        assert_eq!(
+            receipt.logs[1].topics[0],
+            H256::from(keccak256("ProxyCreation(address)"))
+        );
+        assert_eq!(find_created_proxy(&receipt), Some(proxy()));
+
+        let without_creation = TransactionReceipt { logs: vec![receipt.logs[0].clone()], ..receipt };
+        assert_eq!(find_created_proxy(&without_creation), None);
+    }
+
+    #[test]
+    fn unmined_deployments_are_pending() {
+        let server = MockRpcServer::start(|request| match request.method.as_str() {
+            "eth_getTransactionReceipt" => Ok(Value::Null),
+            _ => Err(json!({ "code": -32601, "message": "Method not found" }))
+        });
+        let client = reqwest::blocking::Client::new();
+        let eth_provider = EthereumProvider::with_url(&client, server.url.clone());
+
+        let status = deployment_status(&eth_provider, hash()).unwrap();
+        assert_eq!(status.state, DeploymentState::Pending);
+        assert_eq!(status.wallet, None);
+        assert_eq!(server.requests_for("eth_getTransactionReceipt").len(), 1);
+    }
+
+    #[test]
+    fn mined_deployments_report_the_created_wallet() {
+        let server = MockRpcServer::start(|request| match request.method.as_str() {
+            "eth_getTransactionReceipt" => Ok(deployment_receipt("0x1")),
+            _ => Err(json!({ "code": -32601, "message": "Method not found" }))
+        });
+        let client = reqwest::blocking::Client::new();
+        let eth_provider = EthereumProvider::with_url(&client, server.url.clone());
 
-    Ok(execute_with_estimation(&eth_provider, estimation)?)
+        let status = deployment_status(&eth_provider, hash()).unwrap();
+        assert_eq!(status.state, DeploymentState::Success);
+        assert_eq!(status.wallet, Some(proxy()));
+        assert!(status.initialized);
+        assert_eq!(status.block_number, Some(U256::from(16)));
+    }
 }
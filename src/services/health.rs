@@ -0,0 +1,142 @@
+use crate::config::{ChainConfig, BUNDLE_SIGNER_KEY, DEFAULT_KEY, ITX_KEY};
+use crate::models::{HealthCheck, Readiness};
+use crate::providers::ethereum::key::PrivateKey;
+use crate::providers::ethereum::{to_string_result, EthereumProvider};
+use crate::providers::store::Store;
+use crate::services::about::relayer_balance;
+use crate::utils::context::Context;
+use anyhow::Result;
+use ethereum_types::U256;
+
+fn parse_quantity(value: String) -> Result<U256> {
+    Ok(U256::from_str_radix(value.trim_start_matches("0x"), 16)?)
+}
+
+/// Number of relayer transactions that have been sent but are not mined yet.
+pub fn pending_nonce_gap(eth_provider: &EthereumProvider) -> Result<U256> {
+    let pending = parse_quantity(to_string_result(eth_provider.transaction_count("pending")?)?)?;
+    let latest = parse_quantity(to_string_result(eth_provider.transaction_count("latest")?)?)?;
+    Ok(pending.saturating_sub(latest))
+}
+
+fn check(name: &str, result: Result<()>) -> HealthCheck {
+    HealthCheck {
+        name: name.to_string(),
+        chain_id: None,
+        healthy: result.is_ok(),
+        details: result.err().map(|err| err.to_string())
+    }
+}
+
+fn chain_check(chain_id: u64, name: &str, result: Result<()>) -> HealthCheck {
+    HealthCheck { chain_id: Some(chain_id), ..check(name, result) }
+}
+
+fn check_rpc(eth_provider: &EthereumProvider, expected_chain_id: u64) -> Result<()> {
+    let chain_id = parse_quantity(to_string_result(eth_provider.chain_id()?)?)?;
+    anyhow::ensure!(
+        chain_id == U256::from(expected_chain_id),
+        "Node is on chain {}, expected {}", chain_id, expected_chain_id
+    );
+    Ok(())
+}
+
+fn check_keys(chain: &ChainConfig) -> Result<()> {
+    let mut keys = vec![DEFAULT_KEY];
+    if chain.itx_enabled() {
+        keys.push(ITX_KEY);
+    }
+    if chain.bundle_rpc_url.is_some() {
+        keys.push(BUNDLE_SIGNER_KEY);
+    }
+    for key in keys {
+        let key_bytes = chain.key_bytes(key).ok_or_else(|| anyhow::anyhow!("{} is not configured", key))?;
+        PrivateKey::from_hex_str(key_bytes).map_err(|_| anyhow::anyhow!("{} is not a valid private key", key))?;
+    }
+    Ok(())
+}
+
+fn check_balance(eth_provider: &EthereumProvider) -> Result<()> {
+    let min_balance = eth_provider.chain().min_relayer_balance;
+    let balance = relayer_balance(eth_provider)?;
+    anyhow::ensure!(balance >= min_balance, "Balance {} is below {}", balance, min_balance);
+    Ok(())
+}
+
+fn check_redis(store: &Store) -> Result<()> {
+    let _: String = redis::cmd("PING").query(&mut store.connection()?)?;
+    Ok(())
+}
+
+fn check_nonce_backlog(eth_provider: &EthereumProvider, max_backlog: usize) -> Result<()> {
+    let backlog = pending_nonce_gap(eth_provider)?;
+    anyhow::ensure!(
+        backlog <= U256::from(max_backlog),
+        "{} transactions are pending, limit is {}", backlog, max_backlog
+    );
+    Ok(())
+}
+
+/// Runs all readiness checks for every chain, checks that depend on the relayer key are skipped
+/// if the keys of the chain are invalid.
+pub fn readiness(context: &Context) -> Readiness {
+    let config = context.config();
+    let mut chain_ids: Vec<&u64> = config.chains.keys().collect();
+    chain_ids.sort();
+    let mut checks = Vec::new();
+    for chain_id in chain_ids {
+        let chain = config.chains[chain_id].clone();
+        let eth_provider = EthereumProvider::for_chain(context.client(), chain.clone())
+            .with_request_id(Some(context.request_id()));
+        checks.push(chain_check(chain.chain_id, "rpc", check_rpc(&eth_provider, chain.chain_id)));
+        let keys = chain_check(chain.chain_id, "keys", check_keys(&chain));
+        let keys_valid = keys.healthy;
+        checks.push(keys);
+        if keys_valid {
+            checks.push(chain_check(chain.chain_id, "balance", check_balance(&eth_provider)));
+            let backlog = check_nonce_backlog(&eth_provider, config.max_nonce_backlog);
+            checks.push(chain_check(chain.chain_id, "nonceBacklog", backlog));
+        }
+    }
+    if let Some(store) = context.store() {
+        checks.push(check("redis", check_redis(store)));
+    }
+    Readiness {
+        ready: checks.iter().all(|check| check.healthy),
+        checks
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::ethereum::mock::MockRpcServer;
+    use serde_json::json;
+
+    fn chain_server() -> MockRpcServer {
+        MockRpcServer::start(|request| match request.method.as_str() {
+            "eth_chainId" => Ok(json!("0x4")),
+            _ => Err(json!({ "code": -32601, "message": "Method not found" }))
+        })
+    }
+
+    #[test]
+    fn rpc_check_compares_chain_id() {
+        let server = chain_server();
+        let client = reqwest::blocking::Client::new();
+        let eth_provider = EthereumProvider::with_url(&client, server.url.clone());
+
+        assert!(check_rpc(&eth_provider, 4).is_ok());
+        let mismatch = check("rpc", check_rpc(&eth_provider, 1));
+        assert!(!mismatch.healthy);
+        assert_eq!(mismatch.details, Some("Node is on chain 4, expected 1".to_string()));
+    }
+
+    #[test]
+    fn rpc_check_fails_for_unreachable_node() {
+        let client = reqwest::blocking::Client::new();
+        let eth_provider = EthereumProvider::with_url(&client, "http://127.0.0.1:1".to_string());
+
+        assert!(check_rpc(&eth_provider, 4).is_err());
+    }
+}
@@ -0,0 +1,271 @@
+//! Deduplication of relay requests. Requests are identified by the `Idempotency-Key` header and, for Safe
+//! transactions, by the hash of the Safe transaction and its signatures. Duplicates within the configured window get the original result.
+
+use crate::models::JobReceipt;
+use crate::providers::accounts::safe::payload_hash;
+use crate::providers::ethereum::hash::keccak256;
+use crate::providers::ethereum::types::Bytes;
+use crate::providers::ethereum::EthereumProvider;
+use crate::providers::store::Store;
+use crate::services::jobs::{JobRequest, Submission};
+use crate::utils::errors::Rejection;
+use anyhow::Result;
+use redis::Commands;
+use rocket::http::Status;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "camelCase")]
+enum Outcome {
+    Pending,
+    Sent { result: String },
+    Queued { job_id: u64 }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Entry {
+    request_hash: String,
+    outcome: Outcome
+}
+
+pub struct IdempotencyKey {
+    key: String,
+    /// A key supplied by the client has to be used for the same request, the payload key of a Safe transaction
+    /// is already derived from the request
+    match_request: bool
+}
+
+/// Keys of a relay request, empty if the request cannot be deduplicated.
+pub fn idempotency_keys(
+    eth_provider: &EthereumProvider,
+    tenant: &Option<String>,
+    header: Option<String>,
+    request: &JobRequest
+) -> Result<Vec<IdempotencyKey>> {
+    let mut keys = vec![];
+    if let Some(header) = header {
+        keys.push(IdempotencyKey {
+            key: format!("idempotency:key:{}:{}", tenant.as_deref().unwrap_or(""), header),
+            match_request: true
+        });
+    }
+    if let JobRequest::ExecuteSafe(payload) = request {
+        keys.push(IdempotencyKey {
+            key: format!(
+                "idempotency:safe:{}:{:?}:{:?}", eth_provider.chain().chain_id, payload.wallet, payload_hash(payload)
+            ),
+            match_request: false
+        });
+    }
+    Ok(keys)
+}
+
+/// Remembers the outcome of relay requests, in Redis if configured and otherwise in memory.
+pub struct Idempotency {
+    store: Option<Store>,
+    /// Time in seconds a key is remembered
+    window_secs: usize,
+    memory: Mutex<HashMap<String, (String, Instant)>>
+}
+
+impl Idempotency {
+    pub fn new(store: Option<Store>, window_secs: usize) -> Self {
+        Idempotency { store, window_secs, memory: Mutex::new(HashMap::new()) }
+    }
+
+    /// Stores the value if the key is not set yet, otherwise returns the current value.
+    fn set_if_absent(&self, key: &str, value: &str) -> Result<Option<String>> {
+        let window = self.window_secs;
+        match &self.store {
+            Some(store) => {
+                let mut con = store.connection()?;
+                let claimed: Option<String> = redis::cmd("SET").arg(key).arg(value)
+                    .arg("NX").arg("EX").arg(window)
+                    .query(&mut con)?;
+                Ok(match claimed {
+                    Some(_) => None,
+                    None => con.get(key)?
+                })
+            },
+            None => {
+                let mut memory = self.memory.lock().unwrap();
+                let now = Instant::now();
+                memory.retain(|_, (_, expires)| *expires > now);
+                if let Some((existing, _)) = memory.get(key) {
+                    return Ok(Some(existing.clone()));
+                }
+                memory.insert(key.to_string(), (value.to_string(), now + Duration::from_secs(window as u64)));
+                Ok(None)
+            }
+        }
+    }
+
+    fn set(&self, key: &str, value: &str) -> Result<()> {
+        let window = self.window_secs;
+        match &self.store {
+            Some(store) => {
+                let _: () = store.connection()?.set_ex(key, value, window)?;
+            },
+            None => {
+                let expires = Instant::now() + Duration::from_secs(window as u64);
+                self.memory.lock().unwrap().insert(key.to_string(), (value.to_string(), expires));
+            }
+        }
+        Ok(())
+    }
+
+    fn remove(&self, key: &str) -> Result<()> {
+        match &self.store {
+            Some(store) => {
+                let _: () = store.connection()?.del(key)?;
+            },
+            None => {
+                self.memory.lock().unwrap().remove(key);
+            }
+        }
+        Ok(())
+    }
+
+    /// Claims all keys for the request. If a key was already used the original outcome is returned.
+    pub fn claim(&self, keys: &[IdempotencyKey], request_hash: &str) -> Result<Option<Submission>> {
+        let pending = serde_json::to_string(&Entry { request_hash: request_hash.to_string(), outcome: Outcome::Pending })?;
+        for (index, key) in keys.iter().enumerate() {
+            let existing = match self.set_if_absent(&key.key, &pending)? {
+                Some(existing) => existing,
+                None => continue
+            };
+            self.release(&keys[..index]);
+            let entry: Entry = serde_json::from_str(&existing)?;
+            anyhow::ensure!(
+                !key.match_request || entry.request_hash == request_hash,
+                Rejection::unprocessable("Idempotency key was used for a different request")
+            );
+            return match entry.outcome {
+                Outcome::Pending => Err(Rejection::new(Status::Conflict, "Request is already being processed").into()),
+                Outcome::Sent { result } => Ok(Some(Submission::Sent(result))),
+                Outcome::Queued { job_id } => Ok(Some(Submission::Queued(JobReceipt { job_id })))
+            };
+        }
+        Ok(None)
+    }
+
+    /// Stores the outcome of a claimed request.
+    pub fn complete(&self, keys: &[IdempotencyKey], request_hash: &str, submission: &Submission) -> Result<()> {
+        let outcome = match submission {
+            Submission::Sent(result) => Outcome::Sent { result: result.clone() },
+            Submission::Queued(receipt) => Outcome::Queued { job_id: receipt.job_id }
+        };
+        let entry = serde_json::to_string(&Entry { request_hash: request_hash.to_string(), outcome })?;
+        for key in keys {
+            self.set(&key.key, &entry)?;
+        }
+        Ok(())
+    }
+
+    /// Releases the keys of a failed request, so that it can be retried.
+    pub fn release(&self, keys: &[IdempotencyKey]) {
+        for key in keys {
+            if let Err(err) = self.remove(&key.key) {
+                log::error!("Could not release idempotency key {}: {:?}", key.key, err);
+            }
+        }
+    }
+}
+
+pub fn request_hash(request: &JobRequest) -> Result<String> {
+    Ok(Bytes(keccak256(serde_json::to_vec(request)?).to_vec()).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{ExecutePayload, SafeTransaction};
+    use ethereum_types::{Address, U256};
+
+    fn key(key: &str, match_request: bool) -> IdempotencyKey {
+        IdempotencyKey { key: key.to_string(), match_request }
+    }
+
+    #[test]
+    fn duplicate_returns_original_result() {
+        let idempotency = Idempotency::new(None, 60);
+        let keys = vec![key("header", true), key("safe", false)];
+
+        assert!(idempotency.claim(&keys, "0x01").unwrap().is_none());
+        idempotency.complete(&keys, "0x01", &Submission::Sent("0xabcd".to_string())).unwrap();
+
+        match idempotency.claim(&[key("safe", false)], "0x02").unwrap() {
+            Some(Submission::Sent(result)) => assert_eq!(result, "0xabcd"),
+            _ => panic!("expected original result")
+        }
+    }
+
+    #[test]
+    fn pending_request_is_a_conflict() {
+        let idempotency = Idempotency::new(None, 60);
+        let keys = vec![key("header", true)];
+
+        assert!(idempotency.claim(&keys, "0x01").unwrap().is_none());
+        let err = idempotency.claim(&keys, "0x01").err().unwrap();
+        assert_eq!(err.downcast_ref::<Rejection>().unwrap().status, Status::Conflict);
+    }
+
+    #[test]
+    fn header_key_requires_same_request() {
+        let idempotency = Idempotency::new(None, 60);
+        let keys = vec![key("header", true)];
+
+        idempotency.claim(&keys, "0x01").unwrap();
+        idempotency.complete(&keys, "0x01", &Submission::Queued(JobReceipt { job_id: 1 })).unwrap();
+        let err = idempotency.claim(&keys, "0x02").err().unwrap();
+        assert_eq!(err.downcast_ref::<Rejection>().unwrap().status, Status::UnprocessableEntity);
+    }
+
+    #[test]
+    fn released_keys_can_be_claimed_again() {
+        let idempotency = Idempotency::new(None, 60);
+        let keys = vec![key("header", true), key("safe", false)];
+
+        idempotency.claim(&keys, "0x01").unwrap();
+        idempotency.release(&keys);
+        assert!(idempotency.claim(&keys, "0x01").unwrap().is_none());
+    }
+
+    #[test]
+    fn partial_claim_is_released() {
+        let idempotency = Idempotency::new(None, 60);
+        idempotency.claim(&[key("safe", false)], "0x01").unwrap();
+
+        // The header key is released again, because the Safe transaction is already being processed
+        assert!(idempotency.claim(&[key("header", true), key("safe", false)], "0x02").is_err());
+        assert!(idempotency.claim(&[key("header", true)], "0x02").unwrap().is_none());
+    }
+
+    #[test]
+    fn safe_payload_hash_covers_transaction_and_signatures() {
+        let payload = ExecutePayload {
+            wallet: Address::from_low_u64_be(1),
+            signatures: Bytes(vec![1; 65]),
+            transaction: SafeTransaction {
+                to: Address::from_low_u64_be(2),
+                value: U256::from(3),
+                data: Bytes(vec![4]),
+                operation: 0,
+                safe_tx_gas: U256::zero()
+            }
+        };
+        assert_eq!(payload_hash(&payload), payload_hash(&payload.clone()));
+
+        let mut resigned = payload.clone();
+        resigned.signatures = Bytes(vec![2; 65]);
+        assert_ne!(payload_hash(&payload), payload_hash(&resigned));
+
+        let mut delegate_call = payload.clone();
+        delegate_call.transaction.operation = 1;
+        assert_ne!(payload_hash(&payload), payload_hash(&delegate_call));
+    }
+}
@@ -1,18 +1,123 @@
-use crate::models::{GenericRelayData};
-use crate::config::{exec_tx_refunder_address, chain_id};
-use crate::providers::ethereum::{to_string_result, Call, CallOptions, EthereumProvider, ItxTransaction, KeyType};
+use crate::models::{GenericRelayData, RelayState, RelayStatus};
+use crate::config::{ChainConfig, RelayMethodConfig, ValidationRule};
+use crate::providers::ethereum::{
+    to_string_result, to_typed_result, Call, CallOptions, EthereumProvider, ItxTransactionStatus, TransactionReceipt
+};
+use crate::providers::ethereum::backend::{BackendType, RelayBackend, RelayTransaction};
 use crate::providers::ethereum::types::Bytes;
-use crate::providers::ethereum::hash::{keccak256};
+use crate::providers::ethereum::hash::H32;
 use crate::utils::context::Context;
+use crate::utils::errors::Rejection;
+use crate::utils::metrics;
 use anyhow::Result;
 use ethabi_contract::use_contract;
-use ethereum_types::{Address, U256};
+use ethabi::{ParamType, Token};
+use ethereum_types::{Address, H256, U256};
+use rocket::http::Status;
 use ethabi;
-use serde_json;
 
 use_contract!(refunder, "./res/refunder.json");
 
-fn estimate_gas(
+/// Method that can be relayed via ITX. The call is wrapped in the refunder contract that pays the fee.
+pub struct RelayMethod {
+    pub name: &'static str,
+    pub selector: H32,
+    pub refunder: Address,
+    params: Vec<ParamType>,
+    validate: fn(&[Token]) -> Result<()>
+}
+
+impl RelayMethod {
+    /// Method of a configured entry, the validation rule defines the parameters and the checks of the call data.
+    fn new(config: &RelayMethodConfig) -> Self {
+        let (name, params, validate): (_, _, fn(&[Token]) -> Result<()>) = match config.rule {
+            ValidationRule::SafeExecTransaction => (
+                "Safe execTransaction",
+                vec![
+                    ParamType::Address, ParamType::Uint(256), ParamType::Bytes, ParamType::Uint(8),
+                    ParamType::Uint(256), ParamType::Uint(256), ParamType::Uint(256), ParamType::Address,
+                    ParamType::Address, ParamType::Bytes
+                ],
+                validate_safe_exec_transaction
+            ),
+            ValidationRule::VaultExecTransaction => (
+                "Vault execTransaction",
+                vec![
+                    ParamType::Address, ParamType::Uint(256), ParamType::Bytes, ParamType::Uint(8),
+                    ParamType::Uint(256), ParamType::Uint(256), ParamType::FixedBytes(32), ParamType::Bytes,
+                    ParamType::Bool
+                ],
+                validate_vault_exec_transaction
+            ),
+            ValidationRule::VaultUpdateConfig => (
+                "Vault updateConfig",
+                vec![
+                    ParamType::Address, ParamType::Array(Box::new(ParamType::Address)), ParamType::Uint(256),
+                    ParamType::Address, ParamType::Address, ParamType::Address, ParamType::Bytes,
+                    ParamType::Uint(256), ParamType::FixedBytes(32), ParamType::Bytes
+                ],
+                validate_vault_update_config
+            ),
+        };
+        RelayMethod { name, selector: config.selector, refunder: config.refunder, params, validate }
+    }
+
+    pub fn method_id(&self) -> String {
+        Bytes(self.selector.to_vec()).to_string()
+    }
+
+    /// Checks that the method data is a valid call of this method.
+    pub fn check(&self, method_data: &[u8]) -> Result<()> {
+        anyhow::ensure!(
+            method_data.len() >= 4 && method_data[0..4] == self.selector,
+            "Method data does not match {}", self.name
+        );
+        let tokens = ethabi::decode(&self.params, &method_data[4..])?;
+        (self.validate)(&tokens)
+    }
+}
+
+fn check_operation(operation: &Token) -> Result<()> {
+    match operation {
+        Token::Uint(operation) if *operation <= U256::one() => Ok(()),
+        _ => anyhow::bail!("Invalid operation")
+    }
+}
+
+fn validate_safe_exec_transaction(tokens: &[Token]) -> Result<()> {
+    check_operation(&tokens[3])?;
+    // The refunder pays the relayer, so the Safe should not refund anything
+    anyhow::ensure!(tokens[6] == Token::Uint(U256::zero()), "Safe refunds are not supported, gas price has to be 0");
+    Ok(())
+}
+
+fn validate_vault_exec_transaction(tokens: &[Token]) -> Result<()> {
+    check_operation(&tokens[3])
+}
+
+fn validate_vault_update_config(_: &[Token]) -> Result<()> {
+    Ok(())
+}
+
+/// Methods configured in `RELAY_METHODS`, other methods are not relayed.
+pub fn relay_methods(chain: &ChainConfig) -> Vec<RelayMethod> {
+    chain.relay_methods.iter().map(RelayMethod::new).collect()
+}
+
+fn relay_method(chain: &ChainConfig, method_id: &str) -> Result<RelayMethod> {
+    relay_methods(chain).into_iter()
+        .find(|method| method.method_id().eq_ignore_ascii_case(method_id))
+        .ok_or_else(|| Rejection::unprocessable(format!("Method {} is not supported", method_id)).into())
+}
+
+/// Fee the relayer receives from the refunder of the relayed method, `None` as token for native fees.
+pub fn relay_fee(eth_provider: &EthereumProvider, payload: &GenericRelayData) -> Result<(U256, Option<Address>)> {
+    let method = relay_method(eth_provider.chain(), &payload.method)?;
+    let (fee, token) = crate::services::refunder::fee(eth_provider, method.refunder)?;
+    Ok((fee, Some(token).filter(|token| !token.is_zero())))
+}
+
+pub fn estimate_gas(
     eth_provider: &EthereumProvider,
     target: &Address,
     data: &Bytes
@@ -23,7 +128,7 @@ fn estimate_gas(
         data: Some(data.clone()),
         gas: None,
         gas_price: None,
-        from: Some(eth_provider.account()),
+        from: Some(eth_provider.account()?),
     };
     let options = CallOptions {
         block: "latest".to_string(),
@@ -34,29 +139,258 @@ fn estimate_gas(
     Ok(estimate)
 }
 
-pub fn relay_itx(context: &Context, payload: GenericRelayData) -> Result<String> {
-    let eth_provider = EthereumProvider::new(context);
-
-    if payload.method != "0x6a761202" { anyhow::bail!("Invalid method"); }
+pub fn relay_itx(
+    eth_provider: &EthereumProvider,
+    backend: &dyn RelayBackend,
+    payload: GenericRelayData
+) -> Result<String> {
+    let method = relay_method(eth_provider.chain(), &payload.method)?;
+    method.check(&payload.method_data.0)
+        .map_err(|err| Rejection::unprocessable(format!("Invalid {} data: {}", method.name, err)))?;
 
-    let target: Address = serde_json::from_value(serde_json::value::Value::String(exec_tx_refunder_address()))?;
+    let target = method.refunder;
     let data: Bytes = refunder::functions::execute::encode_input(payload.to, payload.method_data).into();
-    let estimation = estimate_gas(&eth_provider, &target, &data)?;
-
-    let itx_tx_hash = keccak256(&ethabi::encode(&[
-        ethabi::Token::Address(target),
-        ethabi::Token::Bytes(data.0.clone()),
-        ethabi::Token::Uint(U256::from(estimation)),
-        ethabi::Token::Uint(U256::from(chain_id()))
-    ]));
-    let signature = eth_provider.sign(&itx_tx_hash, KeyType::Itx)?;
-    log::debug!("itx account: {}", eth_provider.itx_account());
-    let mut signature_vec = [signature.r, signature.s].concat();
-    signature_vec.push((signature.v + 27) as u8);
-    let itx_tx = ItxTransaction {
-        to: target,
-        data: data,
-        gas: estimation.to_string()
+    let estimation = estimate_gas(eth_provider, &target, &data)?;
+    // The gas of ITX relays is paid from the gas tank, other backends check the gas costs against native fees
+    let fee = match backend.backend_type() {
+        BackendType::Itx => U256::zero(),
+        _ => match crate::services::refunder::fee(eth_provider, target)? {
+            (fee, token) if token.is_zero() => fee,
+            _ => U256::zero()
+        }
     };
-    to_string_result(eth_provider.itx_relay(&itx_tx, &Bytes(signature_vec))?)
-}
\ No newline at end of file
+
+    backend.relay(eth_provider, &RelayTransaction { to: target, data, gas: U256::from(estimation), fee })
+}
+
+/// Checks the broadcasts of a relayed transaction, starting with the latest, for a mined transaction.
+fn transaction_status(eth_provider: &EthereumProvider, relay_hash: H256) -> Result<RelayStatus> {
+    let status: Option<ItxTransactionStatus> = to_typed_result(eth_provider.itx_transaction_status(&relay_hash)?)?;
+    let status = status.ok_or_else(|| Rejection::new(Status::NotFound, "Unknown relay transaction"))?;
+    let broadcasts: Vec<H256> = status.broadcasts.iter().map(|broadcast| broadcast.eth_tx_hash).collect();
+    for tx_hash in broadcasts.iter().rev() {
+        let receipt: Option<TransactionReceipt> = to_typed_result(eth_provider.transaction_receipt(tx_hash)?)?;
+        if let Some(receipt) = receipt.filter(|receipt| receipt.block_number.is_some()) {
+            metrics::observe_receipt(&receipt);
+            return Ok(RelayStatus {
+                relay_hash,
+                state: if receipt.is_success() { RelayState::Success } else { RelayState::Failed },
+                broadcasts,
+                mined_transaction: Some(receipt.transaction_hash),
+                block_number: receipt.block_number
+            });
+        }
+    }
+    Ok(RelayStatus {
+        relay_hash,
+        state: RelayState::Pending,
+        broadcasts,
+        mined_transaction: None,
+        block_number: None
+    })
+}
+
+pub fn relay_status(context: &Context, relay_hash: H256) -> Result<RelayStatus> {
+    let eth_provider = EthereumProvider::new(context);
+    transaction_status(&eth_provider, relay_hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::ethereum::hash::function_selector;
+    use crate::providers::ethereum::mock::MockRpcServer;
+    use serde_json::{json, Value};
+
+    const RELAY_HASH: &str = "0x1111111111111111111111111111111111111111111111111111111111111111";
+    const FIRST_BROADCAST: &str = "0x2222222222222222222222222222222222222222222222222222222222222222";
+    const SECOND_BROADCAST: &str = "0x3333333333333333333333333333333333333333333333333333333333333333";
+
+    fn hash(value: &str) -> H256 {
+        serde_json::from_value(Value::String(value.to_string())).unwrap()
+    }
+
+    fn receipt(tx_hash: &str, status: &str) -> Value {
+        json!({
+            "transactionHash": tx_hash,
+            "blockNumber": "0x10",
+            "gasUsed": "0x5208",
+            "status": status,
+            "logs": []
+        })
+    }
+
+    fn itx_server(mined: Option<(&'static str, &'static str)>) -> MockRpcServer {
+        MockRpcServer::start(move |request| match request.method.as_str() {
+            "relay_getTransactionStatus" if request.params[0] == RELAY_HASH => Ok(json!({
+                "receivedTime": "2021-02-12T10:00:00.000Z",
+                "broadcasts": [
+                    { "broadcastTime": "2021-02-12T10:00:01.000Z", "ethTxHash": FIRST_BROADCAST, "gasPrice": "1000000000" },
+                    { "broadcastTime": "2021-02-12T10:01:01.000Z", "ethTxHash": SECOND_BROADCAST, "gasPrice": "1200000000" }
+                ]
+            })),
+            "relay_getTransactionStatus" => Ok(Value::Null),
+            "eth_getTransactionReceipt" => match mined {
+                Some((tx_hash, status)) if request.params[0] == tx_hash => Ok(receipt(tx_hash, status)),
+                _ => Ok(Value::Null)
+            },
+            _ => Err(json!({ "code": -32601, "message": "Method not found" }))
+        })
+    }
+
+    #[test]
+    fn relay_status_pending() {
+        let server = itx_server(None);
+        let client = reqwest::blocking::Client::new();
+        let eth_provider = EthereumProvider::with_url(&client, server.url.clone());
+
+        let status = transaction_status(&eth_provider, hash(RELAY_HASH)).unwrap();
+        assert_eq!(status.state, RelayState::Pending);
+        assert_eq!(status.broadcasts, vec![hash(FIRST_BROADCAST), hash(SECOND_BROADCAST)]);
+        assert_eq!(status.mined_transaction, None);
+        assert_eq!(server.requests_for("eth_getTransactionReceipt").len(), 2);
+    }
+
+    #[test]
+    fn relay_status_mined() {
+        let server = itx_server(Some((FIRST_BROADCAST, "0x1")));
+        let client = reqwest::blocking::Client::new();
+        let eth_provider = EthereumProvider::with_url(&client, server.url.clone());
+
+        let status = transaction_status(&eth_provider, hash(RELAY_HASH)).unwrap();
+        assert_eq!(status.state, RelayState::Success);
+        assert_eq!(status.mined_transaction, Some(hash(FIRST_BROADCAST)));
+        assert_eq!(status.block_number, Some(U256::from(0x10)));
+    }
+
+    #[test]
+    fn relay_status_reverted() {
+        let server = itx_server(Some((SECOND_BROADCAST, "0x0")));
+        let client = reqwest::blocking::Client::new();
+        let eth_provider = EthereumProvider::with_url(&client, server.url.clone());
+
+        let status = transaction_status(&eth_provider, hash(RELAY_HASH)).unwrap();
+        assert_eq!(status.state, RelayState::Failed);
+        assert_eq!(status.mined_transaction, Some(hash(SECOND_BROADCAST)));
+    }
+
+    #[test]
+    fn relay_status_unknown() {
+        let server = itx_server(None);
+        let client = reqwest::blocking::Client::new();
+        let eth_provider = EthereumProvider::with_url(&client, server.url.clone());
+
+        let unknown = hash("0x4444444444444444444444444444444444444444444444444444444444444444");
+        let err = transaction_status(&eth_provider, unknown).unwrap_err();
+        assert_eq!(err.downcast_ref::<Rejection>().unwrap().status, Status::NotFound);
+    }
+
+    use_contract!(vault, "./res/vault.json");
+
+    fn refunder(byte: u8) -> Address {
+        Address::from([byte; 20])
+    }
+
+    fn chain() -> ChainConfig {
+        let mut chain = (*crate::config::test_config().default_chain()).clone();
+        chain.relay_methods = vec![
+            RelayMethodConfig {
+                selector: function_selector(
+                    "execTransaction(address,uint256,bytes,uint8,uint256,uint256,uint256,address,address,bytes)"
+                ),
+                refunder: refunder(1),
+                rule: ValidationRule::SafeExecTransaction
+            },
+            RelayMethodConfig {
+                selector: function_selector(
+                    "execTransaction(address,uint256,bytes,uint8,uint256,uint256,bytes32,bytes,bool)"
+                ),
+                refunder: refunder(2),
+                rule: ValidationRule::VaultExecTransaction
+            },
+            RelayMethodConfig {
+                selector: function_selector(
+                    "updateConfig(address,address[],uint256,address,address,address,bytes,uint256,bytes32,bytes)"
+                ),
+                refunder: refunder(3),
+                rule: ValidationRule::VaultUpdateConfig
+            },
+        ];
+        chain
+    }
+
+    fn method(chain: &ChainConfig, name: &str) -> RelayMethod {
+        relay_methods(chain).into_iter().find(|method| method.name == name).unwrap()
+    }
+
+    fn safe_exec_transaction_data(operation: u8, gas_price: u64) -> Vec<u8> {
+        let params = ethabi::encode(&[
+            Token::Address(Address::zero()), Token::Uint(U256::zero()), Token::Bytes(vec![]),
+            Token::Uint(U256::from(operation)), Token::Uint(U256::zero()), Token::Uint(U256::zero()),
+            Token::Uint(U256::from(gas_price)), Token::Address(Address::zero()), Token::Address(Address::zero()),
+            Token::Bytes(vec![])
+        ]);
+        [&method(&chain(), "Safe execTransaction").selector[..], &params].concat()
+    }
+
+    fn vault_exec_transaction_data(operation: u8) -> Vec<u8> {
+        vault::functions::exec_transaction::encode_input(
+            Address::zero(), U256::zero(), vec![0xaa], operation, U256::zero(), U256::one(), H256::zero(), vec![0xbb], true
+        )
+    }
+
+    fn vault_update_config_data() -> Vec<u8> {
+        vault::functions::update_config::encode_input(
+            Address::zero(), vec![refunder(9)], U256::one(), Address::zero(), Address::zero(), Address::zero(),
+            vec![], U256::one(), H256::zero(), vec![0xbb]
+        )
+    }
+
+    #[test]
+    fn only_configured_methods_are_relayed() {
+        assert!(relay_methods(&crate::config::test_config().default_chain()).is_empty());
+
+        let refunders: Vec<Address> = relay_methods(&chain()).iter().map(|method| method.refunder).collect();
+        assert_eq!(refunders, vec![refunder(1), refunder(2), refunder(3)]);
+
+        let mut without_vault_exec = chain();
+        without_vault_exec.relay_methods.retain(|method| method.rule != ValidationRule::VaultExecTransaction);
+        let names: Vec<&str> = relay_methods(&without_vault_exec).iter().map(|method| method.name).collect();
+        assert_eq!(names, vec!["Safe execTransaction", "Vault updateConfig"]);
+        let vault_exec = method(&chain(), "Vault execTransaction").method_id();
+        assert!(relay_method(&without_vault_exec, &vault_exec).is_err());
+    }
+
+    #[test]
+    fn safe_exec_transaction_method_id() {
+        assert_eq!(method(&chain(), "Safe execTransaction").method_id(), "0x6a761202");
+        assert!(relay_method(&chain(), "0x6A761202").is_ok());
+    }
+
+    #[test]
+    fn check_safe_exec_transaction() {
+        let method = method(&chain(), "Safe execTransaction");
+        assert!(method.check(&safe_exec_transaction_data(0, 0)).is_ok());
+        assert!(method.check(&safe_exec_transaction_data(2, 0)).is_err());
+        assert!(method.check(&safe_exec_transaction_data(0, 1)).is_err());
+        assert!(method.check(&safe_exec_transaction_data(0, 0)[4..]).is_err());
+    }
+
+    #[test]
+    fn check_vault_exec_transaction() {
+        let method = method(&chain(), "Vault execTransaction");
+        assert!(method.check(&vault_exec_transaction_data(0)).is_ok());
+        assert!(method.check(&vault_exec_transaction_data(1)).is_ok());
+        assert!(method.check(&vault_exec_transaction_data(2)).is_err());
+        assert!(method.check(&vault_update_config_data()).is_err());
+        assert!(method.check(&safe_exec_transaction_data(0, 0)).is_err());
+    }
+
+    #[test]
+    fn check_vault_update_config() {
+        let method = method(&chain(), "Vault updateConfig");
+        assert!(method.check(&vault_update_config_data()).is_ok());
+        assert!(method.check(&vault_update_config_data()[..36]).is_err());
+        assert!(method.check(&vault_exec_transaction_data(0)).is_err());
+    }
+}
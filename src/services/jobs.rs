@@ -0,0 +1,627 @@
+//! Durable queue between the relay routes and the services. If Redis is configured relay requests
+//! are stored as jobs and processed by a pool of workers, otherwise they are relayed synchronously.
+
+use crate::config::Config;
+use crate::models::{
+    DeployPayload, ExecutePayload, GenericRelayData, JobReceipt, JobState, JobStatus, RelayedCall, SafeDeployPayload
+};
+use crate::providers::accounts::vault::{VaultConfigPayload, VaultHook, VaultPayload};
+use crate::providers::ethereum::backend::{
+    backend_type, relay_backend, resume, NotResubmitted, OnDropped, RelayBackend, RouteGroup, SignedRelay
+};
+use crate::providers::ethereum::revert::RevertError;
+use crate::providers::ethereum::EthereumProvider;
+use crate::providers::store::Store;
+use crate::services::audit::{relayed_calls, relayer_payment, AuditLog, RelayAttempt};
+use crate::services::idempotency::{idempotency_keys, request_hash};
+use crate::services::settings::{effective_chain, Settings};
+use crate::services::{deployment, itx, transactions};
+use crate::utils::context::Context;
+use crate::utils::errors::Rejection;
+use crate::utils::logging;
+use crate::utils::metrics;
+use anyhow::Result;
+use ethereum_types::{Address, U256};
+use redis::Commands;
+use rocket::http::Status;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+const QUEUE_KEY: &str = "jobs:queue";
+const DELAYED_KEY: &str = "jobs:delayed";
+const NEXT_ID_KEY: &str = "jobs:next_id";
+const NEXT_INSTANCE_KEY: &str = "jobs:next_instance";
+/// Processing lists of the workers by the time their lease was last renewed
+const LEASES_KEY: &str = "jobs:leases";
+/// Workers whose lease was not renewed for this long belong to a stopped instance, their jobs are queued again
+const LEASE_SECS: i64 = 60;
+const LEASE_RENEWAL_SECS: u64 = 15;
+const JOB_RETENTION_SECS: usize = 7 * 24 * 60 * 60;
+/// Delay before a job of a paused route group or chain is checked again
+const PAUSE_RETRY_SECS: i64 = 30;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", content = "payload", rename_all = "camelCase")]
+pub enum JobRequest {
+    ExecuteSafe(ExecutePayload),
+    ExecuteVault(VaultPayload),
+    UpdateVault(VaultConfigPayload),
+    Deploy(DeployPayload),
+    DeploySafe(SafeDeployPayload),
+    RelayGeneric(GenericRelayData)
+}
+
+impl JobRequest {
+    pub fn group(&self) -> RouteGroup {
+        match self {
+            JobRequest::ExecuteSafe(_) => RouteGroup::Safe,
+            JobRequest::ExecuteVault(_) | JobRequest::UpdateVault(_) => RouteGroup::Vault,
+            JobRequest::Deploy(_) | JobRequest::DeploySafe(_) => RouteGroup::Deployment,
+            JobRequest::RelayGeneric(_) => RouteGroup::Generic
+        }
+    }
+
+    /// Wallet the request relays a transaction for, deployments create a new wallet
+    pub fn wallet(&self) -> Option<Address> {
+        match self {
+            JobRequest::ExecuteSafe(payload) => Some(payload.wallet),
+            JobRequest::ExecuteVault(payload) => Some(payload.wallet),
+            JobRequest::UpdateVault(payload) => Some(payload.wallet),
+            JobRequest::Deploy(_) | JobRequest::DeploySafe(_) => None,
+            JobRequest::RelayGeneric(payload) => Some(payload.to)
+        }
+    }
+
+    fn service(&self) -> &'static str {
+        match self {
+            JobRequest::ExecuteSafe(_) => "execute_safe",
+            JobRequest::ExecuteVault(_) => "execute_vault",
+            JobRequest::UpdateVault(_) => "update_vault",
+            JobRequest::Deploy(_) => "deploy",
+            JobRequest::DeploySafe(_) => "deploy_safe",
+            JobRequest::RelayGeneric(_) => "relay_generic"
+        }
+    }
+
+    /// Calls performed for the wallet, deployments only record the calls of the deployed wallet
+    fn calls(&self, eth_provider: &EthereumProvider) -> Vec<RelayedCall> {
+        let chain = eth_provider.chain();
+        match self {
+            JobRequest::ExecuteSafe(ExecutePayload { transaction: tx, .. })
+            | JobRequest::Deploy(DeployPayload { transaction: tx, .. }) =>
+                relayed_calls(chain, tx.operation, tx.to, tx.value, &tx.data),
+            JobRequest::ExecuteVault(payload) => {
+                let tx = &payload.transaction;
+                relayed_calls(chain, tx.operation, tx.to, tx.value, &tx.data)
+            },
+            JobRequest::UpdateVault(payload) => VaultHook::decode(&payload.hook.0).into_iter()
+                .map(|hook| RelayedCall { operation: hook.operation, to: hook.to, value: hook.value, data: hook.data })
+                .collect(),
+            JobRequest::DeploySafe(_) => vec![],
+            JobRequest::RelayGeneric(payload) => vec![RelayedCall {
+                operation: 0, to: payload.to, value: U256::zero(), data: payload.method_data.clone()
+            }]
+        }
+    }
+
+    /// Fee the relay pays to the relayer and the token it is paid in, `None` for the native currency
+    fn fee(&self, eth_provider: &EthereumProvider, calls: &[RelayedCall]) -> Result<(U256, Option<Address>)> {
+        let relayer = eth_provider.account()?;
+        Ok(match self {
+            JobRequest::RelayGeneric(payload) => itx::relay_fee(eth_provider, payload).unwrap_or_else(|err| {
+                log::warn!("Could not load the refunder fee: {:?}", err);
+                (U256::zero(), None)
+            }),
+            // The Safe pays the relayer during the setup
+            JobRequest::DeploySafe(payload) if payload.payment_receiver == relayer =>
+                (payload.payment, Some(payload.payment_token).filter(|token| !token.is_zero())),
+            JobRequest::DeploySafe(_) => (U256::zero(), None),
+            _ => (relayer_payment(relayer, calls), None)
+        })
+    }
+
+    fn run(
+        self,
+        eth_provider: &EthereumProvider,
+        backend: &dyn RelayBackend,
+        audit: &AuditLog,
+        tenant: Option<&str>,
+        callback_url: Option<&str>,
+        signed: Option<&SignedRelay>
+    ) -> Result<String> {
+        let service = self.service();
+        let calls = self.calls(eth_provider);
+        let (fee, fee_token) = self.fee(eth_provider, &calls)?;
+        let attempt = RelayAttempt {
+            chain_id: eth_provider.chain().chain_id,
+            tenant: tenant.map(|tenant| tenant.to_string()),
+            request_id: eth_provider.request_id().map(|id| id.to_string()),
+            callback_url: callback_url.map(|url| url.to_string()),
+            service,
+            wallet: self.wallet(),
+            calls,
+            fee,
+            fee_token
+        };
+        let result = match signed {
+            Some(signed) => resume(eth_provider, signed),
+            None => self.relay(eth_provider, backend)
+        };
+        metrics::observe_relay(service, &result);
+        audit.record(attempt, Some(backend.backend_type()), 1, &result);
+        result
+    }
+
+    fn relay(self, eth_provider: &EthereumProvider, backend: &dyn RelayBackend) -> Result<String> {
+        match self {
+            JobRequest::ExecuteSafe(payload) => transactions::execute_safe(eth_provider, backend, payload),
+            JobRequest::ExecuteVault(payload) => transactions::execute_vault(eth_provider, backend, payload),
+            JobRequest::UpdateVault(payload) => transactions::update_vault(eth_provider, backend, payload),
+            JobRequest::Deploy(payload) => deployment::deploy(eth_provider, backend, payload),
+            JobRequest::DeploySafe(payload) => deployment::deploy_safe(eth_provider, backend, payload),
+            JobRequest::RelayGeneric(payload) => itx::relay_itx(eth_provider, backend, payload)
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Job {
+    id: u64,
+    /// Chain the job is relayed on, jobs without a chain were queued for the default chain
+    #[serde(default)]
+    chain_id: Option<u64>,
+    tenant: Option<String>,
+    /// Id of the HTTP request that created the job, used for the logs and RPC calls of the job
+    #[serde(default)]
+    request_id: Option<String>,
+    /// Notified about the outcome of the relay instead of the tenant webhook
+    #[serde(default)]
+    callback_url: Option<String>,
+    /// Transaction signed for the job, saved before it is broadcast. Retries resume it instead of relaying again.
+    #[serde(default)]
+    signed: Option<SignedRelay>,
+    request: JobRequest,
+    state: JobState,
+    attempts: usize,
+    result: Option<String>,
+    error: Option<String>,
+    created_at: i64,
+    updated_at: i64
+}
+
+impl Job {
+    fn status(&self) -> JobStatus {
+        JobStatus {
+            job_id: self.id,
+            state: self.state,
+            attempts: self.attempts,
+            result: self.result.clone(),
+            error: self.error.clone(),
+            created_at: self.created_at,
+            updated_at: self.updated_at
+        }
+    }
+}
+
+/// Result of a relay request, either relayed right away or queued as a job.
+pub enum Submission {
+    Sent(String),
+    Queued(JobReceipt)
+}
+
+fn job_key(id: u64) -> String {
+    format!("jobs:job:{}", id)
+}
+
+fn now() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+fn load_job(con: &mut redis::Connection, id: u64) -> Result<Option<Job>> {
+    let job: Option<String> = con.get(job_key(id))?;
+    Ok(match job {
+        Some(job) => Some(serde_json::from_str(&job)?),
+        None => None
+    })
+}
+
+fn save_job(con: &mut redis::Connection, job: &mut Job) -> Result<()> {
+    job.updated_at = now();
+    let _: () = con.set_ex(job_key(job.id), serde_json::to_string(job)?, JOB_RETENTION_SECS)?;
+    Ok(())
+}
+
+/// Marks the relay and the job as failed if the backend gives up the transaction of a job that succeeded.
+fn on_dropped(store: &Store, audit: &AuditLog, chain_id: u64, id: u64) -> OnDropped {
+    let store = store.clone();
+    let audit_dropped = audit.on_dropped(chain_id);
+    Arc::new(move |hash: &str, reason: &str| {
+        audit_dropped(hash, reason);
+        if let Err(err) = fail_dropped(&store, id, reason) {
+            log::error!("Could not mark job {} as failed: {:?}", id, err);
+        }
+    })
+}
+
+fn fail_dropped(store: &Store, id: u64, reason: &str) -> Result<()> {
+    let mut con = store.connection()?;
+    if let Some(mut job) = load_job(&mut con, id)? {
+        log::warn!("Job {} failed, its transaction was dropped: {}", id, reason);
+        job.state = JobState::Failed;
+        job.error = Some(reason.to_string());
+        save_job(&mut con, &mut job)?;
+    }
+    Ok(())
+}
+
+/// Client errors and reverts will fail again, so they are not retried. Neither are signed relays that cannot be
+/// resumed without signing them again.
+fn is_permanent(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<Rejection>().is_some()
+        || err.downcast_ref::<RevertError>().is_some()
+        || err.downcast_ref::<NotResubmitted>().is_some()
+}
+
+/// Saves the signed transaction on the job before it is broadcast
+fn save_signed(store: &Store, id: u64, signed: &SignedRelay) -> Result<()> {
+    let mut con = store.connection()?;
+    let mut job = load_job(&mut con, id)?.ok_or_else(|| anyhow::anyhow!("Unknown job {}", id))?;
+    job.signed = Some(signed.clone());
+    save_job(&mut con, &mut job)
+}
+
+/// Jobs taken from the queue by a worker, until they are processed
+fn processing_key(instance: u64, worker: usize) -> String {
+    format!("jobs:processing:{}:{}", instance, worker)
+}
+
+fn retry_delay_secs(attempts: usize) -> i64 {
+    2i64.pow(attempts.min(8) as u32)
+}
+
+pub struct JobQueue {
+    store: Store
+}
+
+impl JobQueue {
+    /// Starts the workers of this instance. Every worker has its own processing list with a lease that is renewed
+    /// while the instance is running, jobs of leases that expired are queued again by the other instances.
+    pub fn start(
+        config: Arc<Config>,
+        client: reqwest::blocking::Client,
+        store: Store,
+        settings: Settings,
+        audit: AuditLog
+    ) -> Result<Self> {
+        let mut con = store.connection()?;
+        let instance: u64 = con.incr(NEXT_INSTANCE_KEY, 1)?;
+        let leases: Vec<String> = (0..config.job_workers).map(|worker| processing_key(instance, worker)).collect();
+        renew_leases(&mut con, &leases)?;
+        requeue_expired(&mut con)?;
+        let lease_store = store.clone();
+        let renewed = leases.clone();
+        thread::spawn(move || {
+            loop {
+                thread::sleep(Duration::from_secs(LEASE_RENEWAL_SECS));
+                let result = lease_store.connection().and_then(|mut con| {
+                    renew_leases(&mut con, &renewed)?;
+                    requeue_expired(&mut con)
+                });
+                if let Err(err) = result {
+                    log::error!("Renewing the job leases failed: {:?}", err);
+                }
+            }
+        });
+        for (worker, processing) in leases.into_iter().enumerate() {
+            let config = config.clone();
+            let client = client.clone();
+            let store = store.clone();
+            let settings = settings.clone();
+            let audit = audit.clone();
+            thread::spawn(move || {
+                loop {
+                    if let Err(err) = work(&config, &store, &client, &settings, &audit, &processing) {
+                        log::error!("Job worker {} failed: {:?}", worker, err);
+                        thread::sleep(Duration::from_secs(1));
+                    }
+                }
+            });
+        }
+        Ok(JobQueue { store })
+    }
+
+    fn enqueue(
+        &self,
+        chain_id: u64,
+        tenant: Option<String>,
+        request_id: Option<String>,
+        callback_url: Option<String>,
+        request: JobRequest
+    ) -> Result<JobReceipt> {
+        let mut con = self.store.connection()?;
+        let id: u64 = con.incr(NEXT_ID_KEY, 1)?;
+        let now = now();
+        let mut job = Job {
+            id, chain_id: Some(chain_id), tenant, request_id, callback_url, signed: None, request, state: JobState::Queued,
+            attempts: 0, result: None, error: None, created_at: now, updated_at: now
+        };
+        save_job(&mut con, &mut job)?;
+        let _: () = con.lpush(QUEUE_KEY, id)?;
+        log::debug!("Queued job {}", id);
+        Ok(JobReceipt { job_id: id })
+    }
+
+    fn status(&self, tenant: &Option<String>, id: u64) -> Result<JobStatus> {
+        let mut con = self.store.connection()?;
+        load_job(&mut con, id)?
+            .filter(|job| &job.tenant == tenant)
+            .map(|job| job.status())
+            .ok_or_else(|| Rejection::new(Status::NotFound, "Unknown job").into())
+    }
+}
+
+fn renew_leases(con: &mut redis::Connection, leases: &[String]) -> Result<()> {
+    let now = now();
+    for processing in leases {
+        let _: () = con.zadd(LEASES_KEY, processing, now)?;
+    }
+    Ok(())
+}
+
+/// Moves the jobs of a processing list back to the queue.
+fn requeue(con: &mut redis::Connection, processing: &str) -> Result<()> {
+    loop {
+        let id: Option<u64> = con.rpoplpush(processing, QUEUE_KEY)?;
+        match id {
+            Some(id) => log::warn!("Requeue interrupted job {}", id),
+            None => return Ok(())
+        }
+    }
+}
+
+/// Requeues the jobs of workers whose instance stopped while processing them.
+fn requeue_expired(con: &mut redis::Connection) -> Result<()> {
+    let expired: Vec<String> = con.zrangebyscore(LEASES_KEY, 0, now() - LEASE_SECS)?;
+    for processing in expired {
+        requeue(con, &processing)?;
+        let _: () = con.zrem(LEASES_KEY, &processing)?;
+    }
+    Ok(())
+}
+
+/// Moves retries that are due back to the queue.
+fn promote_due_retries(con: &mut redis::Connection) -> Result<()> {
+    let due: Vec<u64> = con.zrangebyscore(DELAYED_KEY, 0, now())?;
+    for id in due {
+        // Only the worker that removes the entry requeues it
+        let removed: usize = con.zrem(DELAYED_KEY, id)?;
+        if removed > 0 {
+            let _: () = con.lpush(QUEUE_KEY, id)?;
+        }
+    }
+    Ok(())
+}
+
+fn work(
+    config: &Config,
+    store: &Store,
+    client: &reqwest::blocking::Client,
+    settings: &Settings,
+    audit: &AuditLog,
+    processing: &str
+) -> Result<()> {
+    let mut con = store.connection()?;
+    // Jobs this worker was processing when it failed before
+    requeue(&mut con, processing)?;
+    loop {
+        promote_due_retries(&mut con)?;
+        let id: Option<u64> = con.brpoplpush(QUEUE_KEY, processing, 1)?;
+        if let Some(id) = id {
+            // A job that was requeued after its retry was scheduled is only processed once
+            let _: () = con.zrem(DELAYED_KEY, id)?;
+            let result = process(config, &mut con, store, client, settings, audit, id);
+            logging::set_request_id(None);
+            if let Err(err) = result {
+                retry_or_fail(config, &mut con, id, &err)?;
+            }
+            let _: () = con.lrem(processing, 1, id)?;
+        }
+    }
+}
+
+/// Jobs that could not be processed because of an error of the queue itself (e.g. Redis) are retried like relays
+/// that failed with a temporary error.
+fn retry_or_fail(config: &Config, con: &mut redis::Connection, id: u64, err: &anyhow::Error) -> Result<()> {
+    let mut job = match load_job(con, id) {
+        Ok(Some(job)) if job.state != JobState::Succeeded && job.state != JobState::Failed => job,
+        Ok(_) => return Ok(()),
+        Err(load_err) => {
+            log::error!("Dropping job {} that cannot be loaded: {:?}", id, load_err);
+            return Ok(());
+        }
+    };
+    job.error = Some(err.to_string());
+    if job.attempts < config.job_max_attempts {
+        log::warn!("Job {} could not be processed on attempt {}, retrying: {:?}", id, job.attempts, err);
+        job.state = JobState::Retrying;
+        let _: () = con.zadd(DELAYED_KEY, id, now() + retry_delay_secs(job.attempts))?;
+    } else {
+        log::warn!("Job {} could not be processed: {:?}", id, err);
+        job.state = JobState::Failed;
+    }
+    save_job(con, &mut job)
+}
+
+fn process(
+    config: &Config,
+    con: &mut redis::Connection,
+    store: &Store,
+    client: &reqwest::blocking::Client,
+    settings: &Settings,
+    audit: &AuditLog,
+    id: u64
+) -> Result<()> {
+    let mut job = match load_job(con, id)? {
+        Some(job) if job.state != JobState::Succeeded && job.state != JobState::Failed => job,
+        _ => return Ok(())
+    };
+    logging::set_request_id(job.request_id.clone());
+    let chain = match job.chain_id {
+        Some(chain_id) => config.chain(chain_id).ok_or_else(|| anyhow::anyhow!("Unknown chain {}", chain_id)),
+        None => Ok(config.default_chain())
+    };
+    let runtime = settings.current();
+    if let Ok(chain) = &chain {
+        if settings.paused(&runtime, job.request.group(), chain.chain_id).is_some() {
+            log::info!("Job {} is paused, checking again in {} seconds", id, PAUSE_RETRY_SECS);
+            let _: () = con.zadd(DELAYED_KEY, id, now() + PAUSE_RETRY_SECS)?;
+            return Ok(());
+        }
+    }
+    job.state = JobState::Running;
+    job.attempts += 1;
+    save_job(con, &mut job)?;
+
+    let request = job.request.clone();
+    let checkpoint = |signed: &SignedRelay| save_signed(store, id, signed);
+    let result = chain.and_then(|chain| {
+        let chain = effective_chain(&runtime, chain);
+        let eth_provider = EthereumProvider::for_chain(client, chain)
+            .with_request_id(job.request_id.clone())
+            .with_checkpoint(&checkpoint);
+        if let Some(signed) = &job.signed {
+            log::info!("Job {} was signed as {:?} before, resuming it", id, signed.hash);
+        }
+        job.signed.as_ref().map(|signed| Ok(signed.backend))
+            .unwrap_or_else(|| backend_type(config, request.group(), job.tenant.as_deref()))
+            .and_then(|backend_type| {
+                let on_dropped = on_dropped(store, audit, eth_provider.chain().chain_id, id);
+                relay_backend(config, eth_provider.chain(), backend_type, on_dropped)
+            })
+            .and_then(|backend| {
+                let (tenant, callback_url) = (job.tenant.as_deref(), job.callback_url.as_deref());
+                request.run(&eth_provider, backend.as_ref(), audit, tenant, callback_url, job.signed.as_ref())
+            })
+    });
+    if let Some(saved) = load_job(con, id)? {
+        job.signed = saved.signed;
+    }
+    match result {
+        Ok(hash) => {
+            log::info!("Job {} relayed as {}", id, hash);
+            job.state = JobState::Succeeded;
+            job.result = Some(hash);
+            job.error = None;
+        },
+        Err(err) if !is_permanent(&err) && job.attempts < config.job_max_attempts => {
+            log::warn!("Job {} failed on attempt {}, retrying: {:?}", id, job.attempts, err);
+            job.state = JobState::Retrying;
+            job.error = Some(err.to_string());
+            let _: () = con.zadd(DELAYED_KEY, id, now() + retry_delay_secs(job.attempts))?;
+        },
+        Err(err) => {
+            log::warn!("Job {} failed: {:?}", id, err);
+            job.state = JobState::Failed;
+            job.error = Some(err.to_string());
+        }
+    }
+    save_job(con, &mut job)
+}
+
+/// Queues the request if a job queue is configured, otherwise the request is relayed right away.
+/// Duplicates of a previous request get the original result.
+pub fn submit(context: &Context, request: JobRequest) -> Result<Submission> {
+    let tenant = context.tenant()?;
+    let eth_provider = EthereumProvider::new(context);
+    let settings = context.settings();
+    let runtime = context.runtime_settings();
+    settings.check_paused(&runtime, request.group(), eth_provider.chain().chain_id)?;
+    settings.check_relay(&runtime, &tenant, context.client_address(), request.wallet())?;
+    let callback_url = context.callback_url()?;
+    let keys = idempotency_keys(&eth_provider, &tenant, context.idempotency_key(), &request)?;
+    let request_hash = request_hash(&request)?;
+    let idempotency = context.idempotency();
+    if let Some(original) = idempotency.claim(&keys, &request_hash)? {
+        log::info!("Duplicate relay request, returning the original result");
+        return Ok(original);
+    }
+
+    let submission = match context.job_queue() {
+        Some(queue) => queue.enqueue(eth_provider.chain().chain_id, tenant, Some(context.request_id()), callback_url, request)
+            .map(Submission::Queued),
+        None => context.relay_backend(request.group())
+            .and_then(|backend| {
+                let audit = context.audit_log();
+                request.run(&eth_provider, backend.as_ref(), audit, tenant.as_deref(), callback_url.as_deref(), None)
+            })
+            .map(Submission::Sent)
+    };
+    match &submission {
+        Ok(submission) => idempotency.complete(&keys, &request_hash, submission)?,
+        Err(_) => idempotency.release(&keys)
+    }
+    submission
+}
+
+pub fn job_status(context: &Context, id: u64) -> Result<JobStatus> {
+    let queue = context.job_queue()
+        .ok_or_else(|| Rejection::new(Status::NotFound, "Job queue is not enabled"))?;
+    queue.status(&context.tenant()?, id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::ethereum::types::Bytes;
+
+    #[test]
+    fn job_request_round_trip() {
+        let request = JobRequest::RelayGeneric(GenericRelayData {
+            to: Address::from([1u8; 20]),
+            method: "0x6a761202".to_string(),
+            method_data: Bytes(vec![0x6a, 0x76, 0x12, 0x02])
+        });
+        let json = serde_json::to_value(&request).unwrap();
+        assert_eq!(json["type"], "relayGeneric");
+        let decoded: JobRequest = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&decoded).unwrap(), json);
+    }
+
+    #[test]
+    fn permanent_errors_are_not_retried() {
+        assert!(is_permanent(&Rejection::unprocessable("Invalid").into()));
+        assert!(is_permanent(&NotResubmitted("Relay was submitted before".to_string()).into()));
+        assert!(!is_permanent(&anyhow::anyhow!("Connection refused")));
+    }
+
+    #[test]
+    fn jobs_queued_before_checkpoints_are_loaded() {
+        let request = serde_json::to_value(JobRequest::RelayGeneric(GenericRelayData {
+            to: Address::from([1u8; 20]),
+            method: "0x6a761202".to_string(),
+            method_data: Bytes(vec![0x6a, 0x76, 0x12, 0x02])
+        })).unwrap();
+        let job: Job = serde_json::from_value(serde_json::json!({
+            "id": 1,
+            "tenant": null,
+            "request": request,
+            "state": "queued",
+            "attempts": 0,
+            "result": null,
+            "error": null,
+            "createdAt": 0,
+            "updatedAt": 0
+        })).unwrap();
+        assert!(job.signed.is_none());
+    }
+
+    #[test]
+    fn retry_delay_backs_off() {
+        assert_eq!(retry_delay_secs(1), 2);
+        assert_eq!(retry_delay_secs(3), 8);
+        assert_eq!(retry_delay_secs(20), 256);
+    }
+}
@@ -0,0 +1,104 @@
+use crate::config::Config;
+use crate::providers::ethereum::EthereumProvider;
+use crate::services::about::relayer_balance;
+use crate::services::health::pending_nonce_gap;
+use crate::utils::metrics;
+use anyhow::Result;
+use ethereum_types::U256;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Interval in which the gauges of the relayer accounts are refreshed, scrapes only read the last values
+const REFRESH_INTERVAL_SECS: u64 = 30;
+
+/// Updates the gauges of the relayer account.
+fn update_account_metrics(eth_provider: &EthereumProvider) -> Result<()> {
+    let chain = eth_provider.chain().chain_id.to_string();
+    let balance = relayer_balance(eth_provider)?;
+    metrics::RELAYER_BALANCE.with_label_values(&[&chain]).set((balance / U256::exp10(9)).low_u64() as i64);
+    metrics::NONCE_GAP.with_label_values(&[&chain]).set(pending_nonce_gap(eth_provider)?.low_u64() as i64);
+    Ok(())
+}
+
+fn refresh(config: &Config, client: &reqwest::blocking::Client) {
+    for chain in config.chains.values() {
+        let eth_provider = EthereumProvider::for_chain(client, chain.clone());
+        if let Err(err) = update_account_metrics(&eth_provider) {
+            log::warn!("Could not update relayer account metrics for chain {}: {:?}", chain.chain_id, err);
+        }
+    }
+}
+
+/// Starts the worker that refreshes the gauges of the relayer accounts, so that scrapes don't call the nodes.
+pub fn start_refresh(config: Arc<Config>, client: reqwest::blocking::Client) {
+    thread::spawn(move || {
+        loop {
+            refresh(&config, &client);
+            thread::sleep(Duration::from_secs(REFRESH_INTERVAL_SECS));
+        }
+    });
+}
+
+pub fn collect() -> Result<String> {
+    metrics::encode()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{test_config, ChainConfig};
+    use crate::providers::ethereum::mock::MockRpcServer;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    #[test]
+    fn account_metrics_are_updated_from_the_node() {
+        let server = MockRpcServer::start(|request| match request.method.as_str() {
+            // 3 gwei
+            "eth_getBalance" => Ok(json!("0xb2d05e00")),
+            "eth_getTransactionCount" if request.params[1] == "pending" => Ok(json!("0xc")),
+            "eth_getTransactionCount" => Ok(json!("0x9")),
+            _ => Err(json!({ "code": -32601, "message": "Method not found" }))
+        });
+        let client = reqwest::blocking::Client::new();
+        let mut chain = (*test_config().default_chain()).clone();
+        chain.chain_id = 9_001;
+        chain.rpc_url = server.url.clone();
+        let eth_provider = EthereumProvider::for_chain(&client, Arc::new(chain));
+
+        update_account_metrics(&eth_provider).unwrap();
+        assert_eq!(metrics::RELAYER_BALANCE.with_label_values(&["9001"]).get(), 3);
+        assert_eq!(metrics::NONCE_GAP.with_label_values(&["9001"]).get(), 3);
+        let encoded = metrics::encode().unwrap();
+        assert!(encoded.contains("relayer_balance_gwei{chain=\"9001\"} 3"));
+        assert!(encoded.contains("relayer_pending_nonce_gap{chain=\"9001\"} 3"));
+    }
+
+    #[test]
+    fn account_metrics_fail_without_a_node() {
+        let server = MockRpcServer::start(|_| Err(json!({ "code": -32000, "message": "Unavailable" })));
+        let client = reqwest::blocking::Client::new();
+        let eth_provider = EthereumProvider::with_url(&client, server.url.clone());
+
+        assert!(update_account_metrics(&eth_provider).is_err());
+    }
+
+    #[test]
+    fn scrapes_do_not_call_the_node() {
+        let server = MockRpcServer::start(|_| Err(json!({ "code": -32000, "message": "Unavailable" })));
+        let mut config = test_config();
+        let chain = (*config.default_chain()).clone();
+        let config_mut = Arc::get_mut(&mut config).unwrap();
+        config_mut.chains.clear();
+        let chain = ChainConfig { chain_id: 9_002, rpc_url: server.url.clone(), ..chain };
+        config_mut.chains.insert(chain.chain_id, Arc::new(chain));
+        let client = reqwest::blocking::Client::new();
+
+        refresh(&config, &client);
+        let refresh_requests = server.requests().len();
+        assert!(refresh_requests > 0);
+        collect().unwrap();
+        assert_eq!(server.requests().len(), refresh_requests);
+    }
+}
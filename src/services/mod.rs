@@ -1,4 +1,14 @@
 pub mod about;
+pub mod accounting;
+pub mod audit;
+pub mod batch;
 pub mod deployment;
+pub mod health;
+pub mod idempotency;
 pub mod itx;
-pub mod transactions;
\ No newline at end of file
+pub mod jobs;
+pub mod metrics;
+pub mod refunder;
+pub mod settings;
+pub mod transactions;
+pub mod webhooks;
\ No newline at end of file
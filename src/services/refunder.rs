@@ -0,0 +1,147 @@
+use crate::models::{RefunderInfo, WithdrawPayload};
+use crate::providers::accounts::Estimation;
+use crate::providers::ethereum::EthereumProvider;
+use crate::providers::ethereum::backend::DirectBackend;
+use crate::providers::ethereum::types::Bytes;
+use crate::services::itx::{estimate_gas, relay_methods};
+use crate::services::transactions::execute_with_estimation;
+use crate::utils::context::Context;
+use crate::utils::errors::Rejection;
+use anyhow::Result;
+use ethabi_contract::use_contract;
+use ethereum_types::{Address, U256};
+use rocket::http::Status;
+
+use_contract!(refunder, "./res/refunder.json");
+
+/// Only refunders that are used for relaying can be administrated.
+fn check_known_refunder(eth_provider: &EthereumProvider, address: Address) -> Result<()> {
+    let known = relay_methods(eth_provider.chain()).iter().any(|method| method.refunder == address);
+    anyhow::ensure!(known, Rejection::new(Status::NotFound, "Unknown refunder"));
+    Ok(())
+}
+
+/// Fee the refunder pays per relay and the token it is paid in
+pub fn fee(eth_provider: &EthereumProvider, address: Address) -> Result<(U256, Address)> {
+    let fee = refunder::functions::fee::decode_output(
+        &eth_provider.view(address, refunder::functions::fee::encode_input())?.0
+    )?;
+    let token = refunder::functions::token::decode_output(
+        &eth_provider.view(address, refunder::functions::token::encode_input())?.0
+    )?;
+    Ok((fee, token))
+}
+
+fn load_info(eth_provider: &EthereumProvider, address: Address) -> Result<RefunderInfo> {
+    let (fee, token) = fee(eth_provider, address)?;
+    let owner = refunder::functions::owner::decode_output(
+        &eth_provider.view(address, refunder::functions::owner::encode_input())?.0
+    )?;
+    Ok(RefunderInfo { address, fee, token, owner })
+}
+
+pub fn info(context: &Context, address: Address) -> Result<RefunderInfo> {
+    let eth_provider = EthereumProvider::new(context);
+    check_known_refunder(&eth_provider, address)?;
+    load_info(&eth_provider, address)
+}
+
+/// Sweeps the collected tokens of a refunder owned by the relayer.
+pub fn withdraw(context: &Context, address: Address, payload: WithdrawPayload) -> Result<String> {
+    withdraw_from(&EthereumProvider::new(context), address, payload)
+}
+
+fn withdraw_from(eth_provider: &EthereumProvider, address: Address, payload: WithdrawPayload) -> Result<String> {
+    check_known_refunder(eth_provider, address)?;
+
+    let info = load_info(eth_provider, address)?;
+    anyhow::ensure!(
+        info.owner == eth_provider.account()?,
+        Rejection::unprocessable("Refunder is not owned by the relayer")
+    );
+    let target = match payload.target {
+        Some(target) => target,
+        None => match eth_provider.chain().treasury_address {
+            Some(treasury) => treasury,
+            None => anyhow::bail!(Rejection::unprocessable("No target provided and no treasury configured"))
+        }
+    };
+    log::info!("Withdraw {:?} from refunder {:?} to {:?}", payload.token, address, target);
+
+    let data: Bytes = refunder::functions::withdraw_tokens_to::encode_input(payload.token, target).into();
+    let estimate = estimate_gas(eth_provider, &address, &data)?;
+    let estimation = Estimation { wallet: address, estimate: U256::from(estimate), data };
+    // Only the owner can withdraw, so this always has to be sent from the relayer account. The relayer pays the gas.
+    execute_with_estimation(eth_provider, &DirectBackend, estimation, U256::zero())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{test_config, RelayMethodConfig, ValidationRule};
+    use crate::providers::ethereum::mock::MockRpcServer;
+    use rustc_hex::ToHex;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    const REFUNDER: [u8; 20] = [7u8; 20];
+
+    /// Node with a refunder that is owned by `owner`, all other views of the refunder return zero.
+    fn refunder_server(owner: Address) -> MockRpcServer {
+        let owner_selector: String = refunder::functions::owner::encode_input().to_hex();
+        MockRpcServer::start(move |request| match request.method.as_str() {
+            "eth_call" => {
+                let data = request.params[0]["data"].as_str().unwrap_or_default().trim_start_matches("0x");
+                let result = if data.starts_with(&owner_selector) {
+                    ethabi::Token::Address(owner)
+                } else {
+                    ethabi::Token::Uint(U256::zero())
+                };
+                let encoded: String = ethabi::encode(&[result]).to_hex();
+                Ok(json!(format!("0x{}", encoded)))
+            },
+            _ => Err(json!({ "code": -32601, "message": "Method not found" }))
+        })
+    }
+
+    fn provider<'p>(client: &'p reqwest::blocking::Client, server: &MockRpcServer) -> EthereumProvider<'p> {
+        let mut chain = (*test_config().default_chain()).clone();
+        chain.rpc_url = server.url.clone();
+        chain.relay_methods = vec![RelayMethodConfig {
+            selector: [0x6a, 0x76, 0x12, 0x02],
+            refunder: Address::from(REFUNDER),
+            rule: ValidationRule::SafeExecTransaction
+        }];
+        EthereumProvider::for_chain(client, Arc::new(chain))
+    }
+
+    fn payload() -> WithdrawPayload {
+        WithdrawPayload { token: Address::from([8u8; 20]), target: Some(Address::from([9u8; 20])) }
+    }
+
+    fn status(result: Result<String>) -> Status {
+        result.unwrap_err().downcast_ref::<Rejection>().unwrap().status
+    }
+
+    #[test]
+    fn unknown_refunders_can_not_be_withdrawn_from() {
+        let server = refunder_server(Address::zero());
+        let client = reqwest::blocking::Client::new();
+        let eth_provider = provider(&client, &server);
+
+        assert_eq!(status(withdraw_from(&eth_provider, Address::from([6u8; 20]), payload())), Status::NotFound);
+        assert!(server.requests().is_empty());
+    }
+
+    #[test]
+    fn refunders_of_other_owners_can_not_be_withdrawn_from() {
+        let server = refunder_server(Address::from([5u8; 20]));
+        let client = reqwest::blocking::Client::new();
+        let eth_provider = provider(&client, &server);
+
+        let info = load_info(&eth_provider, Address::from(REFUNDER)).unwrap();
+        assert_eq!(info.owner, Address::from([5u8; 20]));
+        assert_eq!(status(withdraw_from(&eth_provider, Address::from(REFUNDER), payload())), Status::UnprocessableEntity);
+        assert!(server.requests_for("eth_sendRawTransaction").is_empty());
+    }
+}
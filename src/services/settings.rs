@@ -0,0 +1,444 @@
+//! Settings that can be changed at runtime via the admin routes. Changes are persisted in Redis if configured
+//! and every change is recorded in an audit log. Requests work on a snapshot of the settings taken when they
+//! are first needed, so a change never applies to only a part of a request.
+
+use crate::config::{ChainConfig, Config};
+use crate::models::{FeeSettings, Pause, RuntimeSettings, SettingsChange};
+use crate::providers::ethereum::backend::RouteGroup;
+use crate::providers::store::Store;
+use crate::utils::context::Context;
+use crate::utils::errors::Rejection;
+use anyhow::Result;
+use ethereum_types::Address;
+use redis::Commands;
+use rocket::http::Status;
+use std::collections::HashMap;
+use std::fs;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::Duration;
+use thiserror::Error;
+
+const SETTINGS_KEY: &str = "settings:runtime";
+/// Interval in which changes of other instances are loaded from Redis
+const REFRESH_INTERVAL_SECS: u64 = 5;
+const AUDIT_KEY: &str = "settings:audit";
+const AUDIT_LENGTH: usize = 1000;
+
+/// Relay request for a paused route group or chain, reported as 503 with the pause as details.
+#[derive(Debug, Error)]
+#[error("Relaying is paused")]
+pub struct Paused(pub Pause);
+
+/// Current runtime settings, shared by the routes and the workers. With Redis the settings are only replaced if they
+/// were not changed by another instance in the meantime, changes of other instances are refreshed periodically.
+#[derive(Clone)]
+pub struct Settings {
+    store: Option<Store>,
+    /// Relaying is paused while this file exists
+    pause_file: Option<String>,
+    current: Arc<RwLock<Arc<RuntimeSettings>>>,
+    /// Audit log if Redis is not configured, also serializes the updates
+    changes: Arc<Mutex<Vec<SettingsChange>>>,
+    /// Requests in the current rate limit window if Redis is not configured, by client
+    requests: Arc<Mutex<HashMap<String, (u64, u64)>>>
+}
+
+impl Settings {
+    pub fn load(store: Option<Store>, pause_file: Option<String>) -> Result<Self> {
+        let current = match &store {
+            Some(store) => stored(&mut store.connection()?)?.unwrap_or_default(),
+            None => RuntimeSettings::default()
+        };
+        Ok(Settings {
+            store,
+            pause_file,
+            current: Arc::new(RwLock::new(Arc::new(current))),
+            changes: Arc::new(Mutex::new(vec![])),
+            requests: Arc::new(Mutex::new(HashMap::new()))
+        })
+    }
+
+    pub fn current(&self) -> Arc<RuntimeSettings> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Starts the worker that picks up the changes of other instances, only needed with Redis.
+    pub fn start_refresh(&self) {
+        if self.store.is_none() {
+            return;
+        }
+        let settings = self.clone();
+        thread::spawn(move || {
+            loop {
+                thread::sleep(Duration::from_secs(REFRESH_INTERVAL_SECS));
+                if let Err(err) = settings.reload() {
+                    log::warn!("Could not refresh the runtime settings: {:?}", err);
+                }
+            }
+        });
+    }
+
+    /// Reloads the settings persisted in Redis, e.g. after another instance changed them.
+    /// Without Redis the current settings are kept.
+    pub fn reload(&self) -> Result<Arc<RuntimeSettings>> {
+        let store = match &self.store {
+            Some(store) => store,
+            None => return Ok(self.current())
+        };
+        let _changes = self.changes.lock().unwrap();
+        if let Some(settings) = stored(&mut store.connection()?)? {
+            if settings.version != self.current().version {
+                log::info!("Runtime settings reloaded at version {}", settings.version);
+                *self.current.write().unwrap() = Arc::new(settings);
+            }
+        }
+        Ok(self.current())
+    }
+
+    /// Replaces the settings, if they are based on the current version.
+    pub fn update(&self, config: &Config, request_id: &str, settings: RuntimeSettings) -> Result<SettingsChange> {
+        self.change(config, request_id, |current| {
+            anyhow::ensure!(
+                settings.version == current.version,
+                Rejection::new(
+                    Status::Conflict,
+                    format!("Settings were changed in the meantime, current version is {}", current.version)
+                )
+            );
+            Ok(settings)
+        })
+    }
+
+    /// Pauses the route group and chain of the pause, replaces an existing pause of the same routes.
+    pub fn pause(&self, config: &Config, request_id: &str, pause: Pause) -> Result<SettingsChange> {
+        self.change(config, request_id, |current| {
+            let mut settings = current.clone();
+            settings.pauses.retain(|it| !same_routes(it, &pause));
+            settings.pauses.push(pause);
+            Ok(settings)
+        })
+    }
+
+    /// Removes the pause of the same route group and chain, other pauses stay active.
+    pub fn resume(&self, config: &Config, request_id: &str, pause: Pause) -> Result<SettingsChange> {
+        self.change(config, request_id, |current| {
+            anyhow::ensure!(
+                current.pauses.iter().any(|it| same_routes(it, &pause)),
+                Rejection::new(Status::NotFound, "No pause for these routes")
+            );
+            let mut settings = current.clone();
+            settings.pauses.retain(|it| !same_routes(it, &pause));
+            Ok(settings)
+        })
+    }
+
+    /// Applies a change to the current settings, persists it and records it in the audit log.
+    /// With Redis the change is based on the stored settings and only saved if no other instance changed them
+    /// in the meantime (compare and set via `WATCH`/`MULTI`), otherwise it is rejected with a conflict.
+    fn change<F>(&self, config: &Config, request_id: &str, change: F) -> Result<SettingsChange>
+    where
+        F: FnOnce(&RuntimeSettings) -> Result<RuntimeSettings>
+    {
+        let mut changes = self.changes.lock().unwrap();
+        let change = match &self.store {
+            Some(store) => {
+                let mut con = store.connection()?;
+                let _: () = redis::cmd("WATCH").arg(SETTINGS_KEY).query(&mut con)?;
+                let previous = stored(&mut con)?.unwrap_or_default();
+                let change = prepare(config, request_id, previous, change)?;
+                let committed: Option<()> = redis::pipe().atomic()
+                    .set(SETTINGS_KEY, serde_json::to_string(&change.settings)?).ignore()
+                    .lpush(AUDIT_KEY, serde_json::to_string(&change)?).ignore()
+                    .ltrim(AUDIT_KEY, 0, AUDIT_LENGTH as isize - 1).ignore()
+                    .query(&mut con)?;
+                anyhow::ensure!(
+                    committed.is_some(),
+                    Rejection::new(Status::Conflict, "Settings were changed by another instance at the same time")
+                );
+                change
+            },
+            None => {
+                let change = prepare(config, request_id, (*self.current()).clone(), change)?;
+                changes.insert(0, change.clone());
+                changes.truncate(AUDIT_LENGTH);
+                change
+            }
+        };
+        log::info!(
+            "Runtime settings changed to version {}: {}", change.version, serde_json::to_string(&change.settings)?
+        );
+        *self.current.write().unwrap() = Arc::new(change.settings.clone());
+        Ok(change)
+    }
+
+    /// Latest changes first
+    pub fn audit_log(&self, limit: usize) -> Result<Vec<SettingsChange>> {
+        match &self.store {
+            Some(store) => {
+                let changes: Vec<String> = store.connection()?.lrange(AUDIT_KEY, 0, limit as isize - 1)?;
+                changes.iter()
+                    .map(|change| -> Result<SettingsChange> { Ok(serde_json::from_str(change)?) })
+                    .collect()
+            },
+            None => Ok(self.changes.lock().unwrap().iter().take(limit).cloned().collect())
+        }
+    }
+
+    /// Active pauses, including the pause of the signal file
+    pub fn pauses(&self, settings: &RuntimeSettings) -> Vec<Pause> {
+        self.file_pause().into_iter().chain(settings.pauses.iter().cloned()).collect()
+    }
+
+    fn file_pause(&self) -> Option<Pause> {
+        let content = fs::read_to_string(self.pause_file.as_ref()?).ok()?;
+        let reason = match content.trim() {
+            "" => "Paused by signal file".to_string(),
+            reason => reason.to_string()
+        };
+        Some(Pause { group: None, chain_id: None, reason: Some(reason) })
+    }
+
+    /// Pause that applies to the route group on the chain
+    pub fn paused(&self, settings: &RuntimeSettings, group: RouteGroup, chain_id: u64) -> Option<Pause> {
+        self.pauses(settings).into_iter().find(|pause| covers(pause, group, chain_id))
+    }
+
+    pub fn check_paused(&self, settings: &RuntimeSettings, group: RouteGroup, chain_id: u64) -> Result<()> {
+        match self.paused(settings, group, chain_id) {
+            Some(pause) => Err(Paused(pause).into()),
+            None => Ok(())
+        }
+    }
+
+    /// Checks whether the client is allowed to relay a transaction for the wallet.
+    pub fn check_relay(
+        &self,
+        settings: &RuntimeSettings,
+        tenant: &Option<String>,
+        client: Option<String>,
+        wallet: Option<Address>
+    ) -> Result<()> {
+        if let Some(tenants) = &settings.allowlists.tenants {
+            anyhow::ensure!(
+                tenant.as_ref().map_or(false, |tenant| tenants.contains(tenant)),
+                Rejection::new(Status::Forbidden, "Tenant is not allowed to relay")
+            );
+        }
+        if let (Some(wallets), Some(wallet)) = (&settings.allowlists.wallets, wallet) {
+            anyhow::ensure!(
+                wallets.contains(&wallet),
+                Rejection::new(Status::Forbidden, "Wallet is not allowed to relay")
+            );
+        }
+        if let Some(rate_limit) = settings.rate_limit {
+            let client = tenant.clone().or(client).unwrap_or_default();
+            let window = chrono::Utc::now().timestamp() as u64 / rate_limit.window_secs;
+            anyhow::ensure!(
+                self.count_request(&client, window, rate_limit.window_secs)? <= rate_limit.requests,
+                Rejection::new(Status::TooManyRequests, "Rate limit exceeded")
+            );
+        }
+        Ok(())
+    }
+
+    /// Counts the request in the window and returns the number of requests of the client in the window.
+    fn count_request(&self, client: &str, window: u64, window_secs: u64) -> Result<u64> {
+        match &self.store {
+            Some(store) => {
+                let key = format!("ratelimit:{}:{}", client, window);
+                let mut con = store.connection()?;
+                let count: u64 = con.incr(&key, 1)?;
+                if count == 1 {
+                    let _: () = con.expire(&key, window_secs as usize)?;
+                }
+                Ok(count)
+            },
+            None => {
+                let mut requests = self.requests.lock().unwrap();
+                requests.retain(|_, (request_window, _)| *request_window == window);
+                let (_, count) = requests.entry(client.to_string()).or_insert((window, 0));
+                *count += 1;
+                Ok(*count)
+            }
+        }
+    }
+}
+
+/// Applies the change to the previous settings and builds the audit entry of the next version.
+fn prepare<F>(config: &Config, request_id: &str, previous: RuntimeSettings, change: F) -> Result<SettingsChange>
+where
+    F: FnOnce(&RuntimeSettings) -> Result<RuntimeSettings>
+{
+    let settings = change(&previous)?;
+    validate(config, &settings)?;
+    let settings = RuntimeSettings { version: previous.version + 1, ..settings };
+    Ok(SettingsChange {
+        version: settings.version,
+        changed_at: chrono::Utc::now().timestamp(),
+        request_id: request_id.to_string(),
+        previous,
+        settings
+    })
+}
+
+fn stored(con: &mut redis::Connection) -> Result<Option<RuntimeSettings>> {
+    let settings: Option<String> = con.get(SETTINGS_KEY)?;
+    Ok(match settings {
+        Some(settings) => Some(serde_json::from_str(&settings)?),
+        None => None
+    })
+}
+
+fn same_routes(pause: &Pause, other: &Pause) -> bool {
+    pause.group == other.group && pause.chain_id == other.chain_id
+}
+
+fn covers(pause: &Pause, group: RouteGroup, chain_id: u64) -> bool {
+    let group_paused = pause.group.map_or(true, |paused| {
+        paused == group || (paused == RouteGroup::Safe && group == RouteGroup::Batch)
+    });
+    group_paused && pause.chain_id.map_or(true, |paused| paused == chain_id)
+}
+
+fn validate(config: &Config, settings: &RuntimeSettings) -> Result<()> {
+    let chain_ids = settings.fees.keys().chain(settings.pauses.iter().filter_map(|pause| pause.chain_id.as_ref()));
+    for chain_id in chain_ids {
+        anyhow::ensure!(
+            config.chains.contains_key(chain_id),
+            Rejection::unprocessable(format!("Chain {} is not configured", chain_id))
+        );
+    }
+    if let Some(rate_limit) = settings.rate_limit {
+        anyhow::ensure!(
+            rate_limit.requests > 0 && rate_limit.window_secs > 0,
+            Rejection::unprocessable("Rate limit requires requests and a window")
+        );
+    }
+    Ok(())
+}
+
+/// Chain configuration with the fee overrides of the settings applied
+pub fn effective_chain(settings: &RuntimeSettings, chain: Arc<ChainConfig>) -> Arc<ChainConfig> {
+    let FeeSettings { transaction_fee, multisend_address } = match settings.fees.get(&chain.chain_id) {
+        Some(fees) => fees.clone(),
+        None => return chain
+    };
+    let mut chain = (*chain).clone();
+    if let Some(transaction_fee) = transaction_fee {
+        chain.transaction_fee = transaction_fee;
+    }
+    if let Some(multisend_address) = multisend_address {
+        chain.multisend_address = multisend_address;
+    }
+    Arc::new(chain)
+}
+
+pub fn update(context: &Context, settings: RuntimeSettings) -> Result<SettingsChange> {
+    context.settings().update(&context.config(), &context.request_id(), settings)
+}
+
+pub fn pause(context: &Context, pause: Pause) -> Result<SettingsChange> {
+    log::warn!("Pausing {:?} on chain {:?}: {:?}", pause.group, pause.chain_id, pause.reason);
+    context.settings().pause(&context.config(), &context.request_id(), pause)
+}
+
+pub fn resume(context: &Context, pause: Pause) -> Result<SettingsChange> {
+    log::warn!("Resuming {:?} on chain {:?}", pause.group, pause.chain_id);
+    context.settings().resume(&context.config(), &context.request_id(), pause)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+    use crate::models::RateLimit;
+    use ethereum_types::U256;
+
+    fn status(result: Result<()>) -> Status {
+        result.err().unwrap().downcast_ref::<Rejection>().unwrap().status
+    }
+
+    #[test]
+    fn updates_require_current_version() {
+        let settings = Settings::load(None, None).unwrap();
+        let update = RuntimeSettings { rate_limit: Some(RateLimit { requests: 10, window_secs: 60 }), ..Default::default() };
+        let change = settings.update(&test_config(), "1", update).unwrap();
+        assert_eq!(change.version, 1);
+        assert_eq!(settings.current().rate_limit.unwrap().requests, 10);
+
+        let err = settings.update(&test_config(), "2", RuntimeSettings::default()).err().unwrap();
+        assert_eq!(err.downcast_ref::<Rejection>().unwrap().status, Status::Conflict);
+        assert_eq!(settings.audit_log(10).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn fees_of_unknown_chains_are_rejected() {
+        let settings = Settings::load(None, None).unwrap();
+        let mut update = RuntimeSettings::default();
+        update.fees.insert(1234, FeeSettings::default());
+        let err = settings.update(&test_config(), "1", update).err().unwrap();
+        assert_eq!(err.downcast_ref::<Rejection>().unwrap().status, Status::UnprocessableEntity);
+    }
+
+    #[test]
+    fn fee_overrides_apply_to_their_chain() {
+        let chain = test_config().default_chain();
+        let mut settings = RuntimeSettings::default();
+        settings.fees.insert(chain.chain_id, FeeSettings { transaction_fee: Some(U256::from(7)), multisend_address: None });
+
+        let effective = effective_chain(&settings, chain.clone());
+        assert_eq!(effective.transaction_fee, U256::from(7));
+        assert_eq!(effective.multisend_address, chain.multisend_address);
+    }
+
+    #[test]
+    fn relay_checks() {
+        let settings = Settings::load(None, None).unwrap();
+        let tenant = Some("acme".to_string());
+        let mut runtime = RuntimeSettings::default();
+        runtime.allowlists.tenants = Some(vec!["acme".to_string()]);
+        runtime.allowlists.wallets = Some(vec![Address::from([1u8; 20])]);
+        runtime.rate_limit = Some(RateLimit { requests: 1, window_secs: 3600 });
+
+        assert_eq!(status(settings.check_relay(&runtime, &None, None, None)), Status::Forbidden);
+        assert_eq!(status(settings.check_relay(&runtime, &tenant, None, Some(Address::zero()))), Status::Forbidden);
+        assert!(settings.check_relay(&runtime, &tenant, None, Some(Address::from([1u8; 20]))).is_ok());
+        assert_eq!(status(settings.check_relay(&runtime, &tenant, None, None)), Status::TooManyRequests);
+    }
+
+    fn pause(group: Option<RouteGroup>, chain_id: Option<u64>) -> Pause {
+        Pause { group, chain_id, reason: None }
+    }
+
+    #[test]
+    fn pauses_apply_to_their_group_and_chain() {
+        let settings = Settings::load(None, None).unwrap();
+        let chain_id = test_config().default_chain_id;
+        settings.pause(&test_config(), "1", pause(Some(RouteGroup::Safe), Some(chain_id))).unwrap();
+        let runtime = settings.current();
+
+        assert!(settings.paused(&runtime, RouteGroup::Safe, chain_id).is_some());
+        assert!(settings.paused(&runtime, RouteGroup::Batch, chain_id).is_some());
+        assert!(settings.paused(&runtime, RouteGroup::Vault, chain_id).is_none());
+        assert!(settings.paused(&runtime, RouteGroup::Safe, chain_id + 1).is_none());
+        let err = settings.check_paused(&runtime, RouteGroup::Safe, chain_id).err().unwrap();
+        assert!(err.downcast_ref::<Paused>().is_some());
+
+        settings.resume(&test_config(), "2", pause(Some(RouteGroup::Safe), Some(chain_id))).unwrap();
+        assert!(settings.paused(&settings.current(), RouteGroup::Safe, chain_id).is_none());
+        assert!(settings.resume(&test_config(), "3", pause(None, None)).is_err());
+    }
+
+    #[test]
+    fn signal_file_pauses_everything() {
+        let path = std::env::temp_dir().join(format!("relayer-pause-{}", std::process::id()));
+        let settings = Settings::load(None, Some(path.to_string_lossy().to_string())).unwrap();
+        assert!(settings.pauses(&settings.current()).is_empty());
+
+        fs::write(&path, "Incident 42\n").unwrap();
+        let pause = settings.paused(&settings.current(), RouteGroup::Deployment, 1).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(pause.reason.as_deref(), Some("Incident 42"));
+    }
+}
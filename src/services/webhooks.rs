@@ -0,0 +1,488 @@
+//! Outbound webhooks for the outcome of relays. Tenants register a webhook and can set a callback URL per request
+//! (`X-Callback-Url`), they receive a POST signed with their secret when their transaction is broadcast, mined,
+//! reverted or replaced. Webhooks are never delivered to loopback, private or link-local addresses.
+//! Deliveries are queued in Redis if configured, otherwise in memory, and retried with an exponential backoff.
+
+use crate::models::{RelayRecord, Webhook, WebhookEvent, WebhookEventType, WebhookRegistration};
+use crate::providers::store::Store;
+use crate::utils::context::Context;
+use crate::utils::errors::Rejection;
+use crate::utils::metrics;
+use anyhow::Result;
+use hmac::{Hmac, Mac};
+use redis::Commands;
+use rocket::http::Status;
+use rustc_hex::ToHex;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const DELIVERIES_KEY: &str = "webhooks:deliveries";
+const DELIVERY_INTERVAL_SECS: u64 = 1;
+const DELIVERY_TIMEOUT_SECS: u64 = 10;
+const MIN_SECRET_LENGTH: usize = 16;
+
+fn registration_key(tenant: &str) -> String {
+    format!("webhooks:tenant:{}", tenant)
+}
+
+fn now() -> i64 {
+    chrono::Utc::now().timestamp()
+}
+
+fn retry_delay_secs(attempts: usize) -> i64 {
+    2i64.pow(attempts.min(10) as u32)
+}
+
+fn event_name(event: WebhookEventType) -> String {
+    format!("{:?}", event).to_lowercase()
+}
+
+/// Loopback, private, link-local (e.g. cloud metadata endpoints) and other addresses that are not public
+fn is_internal(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let octets = ip.octets();
+            ip.is_loopback() || ip.is_private() || ip.is_link_local() || ip.is_unspecified() || ip.is_broadcast()
+                || octets[0] == 0
+                // Shared address space (RFC 6598)
+                || (octets[0] == 100 && octets[1] & 0xc0 == 64)
+        },
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+            ip.is_loopback() || ip.is_unspecified()
+                // Unique local and link-local addresses
+                || first & 0xfe00 == 0xfc00 || first & 0xffc0 == 0xfe80
+                || ip.to_ipv4().map_or(false, |ip| is_internal(IpAddr::V4(ip)))
+        }
+    }
+}
+
+/// Host of the URL without the brackets of IPv6 addresses
+fn host(url: &reqwest::Url) -> Option<&str> {
+    url.host_str().map(|host| host.trim_start_matches('[').trim_end_matches(']'))
+}
+
+/// Only http(s) URLs are accepted as webhooks, hosts that are internal addresses are refused right away.
+/// Names are checked again when the webhook is delivered.
+fn check_url(url: &str) -> Result<()> {
+    let invalid = || Rejection::new(Status::UnprocessableEntity, format!("Invalid webhook url {}", url));
+    let parsed = reqwest::Url::parse(url).map_err(|_| invalid())?;
+    anyhow::ensure!(parsed.scheme() == "http" || parsed.scheme() == "https", invalid());
+    let host = host(&parsed).ok_or_else(invalid)?;
+    let internal = match host.parse::<IpAddr>() {
+        Ok(ip) => is_internal(ip),
+        Err(_) => host.eq_ignore_ascii_case("localhost") || host.to_lowercase().ends_with(".localhost")
+    };
+    anyhow::ensure!(
+        !internal,
+        Rejection::new(Status::UnprocessableEntity, format!("Webhook url {} is not a public address", url))
+    );
+    Ok(())
+}
+
+/// Resolves the host of the URL before a delivery, so that names of internal addresses are refused as well.
+fn check_target(url: &str) -> Result<()> {
+    let parsed = reqwest::Url::parse(url)?;
+    let host = host(&parsed).ok_or_else(|| anyhow::anyhow!("Webhook url {} has no host", url))?;
+    let port = parsed.port_or_known_default().unwrap_or(443);
+    let addresses: Vec<IpAddr> = (host, port).to_socket_addrs()?.map(|address| address.ip()).collect();
+    anyhow::ensure!(
+        !addresses.is_empty() && !addresses.iter().any(|ip| is_internal(*ip)),
+        "Webhook url {} resolves to an internal address", url
+    );
+    Ok(())
+}
+
+/// HMAC-SHA256 of the body, sent as `X-Relayer-Signature: sha256=<hex>`
+fn sign(secret: &str, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_varkey(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.input(body.as_bytes());
+    let signature: String = mac.result().code().as_slice().to_hex();
+    format!("sha256={}", signature)
+}
+
+fn event(event: WebhookEventType, record: &RelayRecord) -> WebhookEvent {
+    WebhookEvent {
+        event,
+        relay_id: record.id,
+        chain_id: record.chain_id,
+        request_id: record.request_id.clone(),
+        wallet: record.wallet,
+        hash: record.hash.clone(),
+        transaction_hash: record.transaction_hash,
+        block_number: record.block_number,
+        timestamp: now()
+    }
+}
+
+/// Signed event that is delivered to a webhook, the signature is computed once so the secret is not queued.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct Delivery {
+    url: String,
+    event: WebhookEventType,
+    body: String,
+    signature: String,
+    attempts: usize
+}
+
+#[derive(Default)]
+struct Memory {
+    registrations: HashMap<String, WebhookRegistration>,
+    /// Deliveries with the time they are due
+    deliveries: Vec<(i64, Delivery)>
+}
+
+#[derive(Clone)]
+pub struct Webhooks {
+    store: Option<Store>,
+    max_attempts: usize,
+    memory: Arc<Mutex<Memory>>
+}
+
+impl Webhooks {
+    pub fn new(store: Option<Store>, max_attempts: usize) -> Self {
+        Webhooks { store, max_attempts, memory: Arc::new(Mutex::new(Memory::default())) }
+    }
+
+    /// Starts the worker that delivers the queued events. Redirects are not followed, as their targets are not checked.
+    pub fn start(&self) -> Result<()> {
+        let client = reqwest::blocking::Client::builder().redirect(reqwest::redirect::Policy::none()).build()?;
+        let webhooks = self.clone();
+        thread::spawn(move || {
+            loop {
+                thread::sleep(Duration::from_secs(DELIVERY_INTERVAL_SECS));
+                if let Err(err) = webhooks.deliver_due(&client) {
+                    log::error!("Delivering webhooks failed: {:?}", err);
+                }
+            }
+        });
+        Ok(())
+    }
+
+    pub fn register(&self, tenant: &str, registration: WebhookRegistration) -> Result<()> {
+        check_url(&registration.url)?;
+        anyhow::ensure!(
+            registration.secret.len() >= MIN_SECRET_LENGTH,
+            Rejection::new(
+                Status::UnprocessableEntity,
+                format!("Webhook secret needs at least {} characters", MIN_SECRET_LENGTH)
+            )
+        );
+        match &self.store {
+            Some(store) => {
+                let _: () = store.connection()?.set(registration_key(tenant), serde_json::to_string(&registration)?)?;
+            },
+            None => {
+                self.memory.lock().unwrap().registrations.insert(tenant.to_string(), registration);
+            }
+        }
+        log::info!("Webhook of tenant {} registered", tenant);
+        Ok(())
+    }
+
+    pub fn registration(&self, tenant: &str) -> Result<Option<WebhookRegistration>> {
+        match &self.store {
+            Some(store) => {
+                let registration: Option<String> = store.connection()?.get(registration_key(tenant))?;
+                Ok(match registration {
+                    Some(registration) => Some(serde_json::from_str(&registration)?),
+                    None => None
+                })
+            },
+            None => Ok(self.memory.lock().unwrap().registrations.get(tenant).cloned())
+        }
+    }
+
+    pub fn unregister(&self, tenant: &str) -> Result<()> {
+        let removed = match &self.store {
+            Some(store) => {
+                let removed: usize = store.connection()?.del(registration_key(tenant))?;
+                removed > 0
+            },
+            None => self.memory.lock().unwrap().registrations.remove(tenant).is_some()
+        };
+        anyhow::ensure!(removed, Rejection::new(Status::NotFound, "No webhook registered"));
+        log::info!("Webhook of tenant {} removed", tenant);
+        Ok(())
+    }
+
+    /// Callback URL of a relay request. Deliveries are signed with the secret of the tenant webhook, so only
+    /// tenants with a registered webhook can set one.
+    pub fn callback_url(&self, tenant: &Option<String>, url: Option<&str>) -> Result<Option<String>> {
+        let url = match url {
+            Some(url) => url,
+            None => return Ok(None)
+        };
+        let tenant = tenant.as_ref()
+            .ok_or_else(|| Rejection::new(Status::Unauthorized, "Callback urls require an API key"))?;
+        check_url(url)?;
+        anyhow::ensure!(
+            self.registration(tenant)?.is_some(),
+            Rejection::new(Status::UnprocessableEntity, "Callback urls require a registered webhook")
+        );
+        Ok(Some(url.to_string()))
+    }
+
+    /// Queues the event for the callback URL of the relay or the webhook of its tenant. Failures are logged
+    /// as the relay itself should not fail because of a webhook.
+    pub fn notify(&self, event: WebhookEventType, record: &RelayRecord) {
+        if let Err(err) = self.try_notify(event, record) {
+            log::error!("Could not queue {} webhook of relay {}: {:?}", event_name(event), record.id, err);
+        }
+    }
+
+    fn try_notify(&self, event_type: WebhookEventType, record: &RelayRecord) -> Result<()> {
+        let registration = match &record.tenant {
+            Some(tenant) => self.registration(tenant)?,
+            None => None
+        };
+        // Without a webhook of the tenant there is no secret to sign a callback with
+        let registration = match registration {
+            Some(registration) => registration,
+            None => return Ok(())
+        };
+        let url = record.callback_url.clone().unwrap_or(registration.url);
+        let secret = registration.secret;
+        let body = serde_json::to_string(&event(event_type, record))?;
+        let signature = sign(&secret, &body);
+        self.schedule(now(), &Delivery { url, event: event_type, body, signature, attempts: 0 })
+    }
+
+    fn schedule(&self, due: i64, delivery: &Delivery) -> Result<()> {
+        match &self.store {
+            Some(store) => {
+                let _: () = store.connection()?.zadd(DELIVERIES_KEY, serde_json::to_string(delivery)?, due)?;
+            },
+            None => self.memory.lock().unwrap().deliveries.push((due, delivery.clone()))
+        }
+        Ok(())
+    }
+
+    fn take_due(&self) -> Result<Vec<Delivery>> {
+        let now = now();
+        match &self.store {
+            Some(store) => {
+                let mut con = store.connection()?;
+                let due: Vec<String> = con.zrangebyscore(DELIVERIES_KEY, 0, now)?;
+                let mut deliveries = vec![];
+                for delivery in due {
+                    // Only the instance that removes the entry delivers it
+                    let removed: usize = con.zrem(DELIVERIES_KEY, &delivery)?;
+                    if removed > 0 {
+                        deliveries.push(serde_json::from_str(&delivery)?);
+                    }
+                }
+                Ok(deliveries)
+            },
+            None => {
+                let mut memory = self.memory.lock().unwrap();
+                let (due, pending): (Vec<_>, Vec<_>) = memory.deliveries.drain(..).partition(|(due, _)| *due <= now);
+                memory.deliveries = pending;
+                Ok(due.into_iter().map(|(_, delivery)| delivery).collect())
+            }
+        }
+    }
+
+    fn deliver_due(&self, client: &reqwest::blocking::Client) -> Result<()> {
+        for mut delivery in self.take_due()? {
+            delivery.attempts += 1;
+            let event = event_name(delivery.event);
+            match send(client, &delivery) {
+                Ok(()) => {
+                    metrics::WEBHOOK_DELIVERIES.with_label_values(&[&event, "delivered"]).inc();
+                    log::debug!("Delivered {} webhook to {}", event, delivery.url);
+                },
+                Err(err) if delivery.attempts < self.max_attempts => {
+                    metrics::WEBHOOK_DELIVERIES.with_label_values(&[&event, "retrying"]).inc();
+                    log::warn!("Delivering {} webhook to {} failed, retrying: {:?}", event, delivery.url, err);
+                    self.schedule(now() + retry_delay_secs(delivery.attempts), &delivery)?;
+                },
+                Err(err) => {
+                    metrics::WEBHOOK_DELIVERIES.with_label_values(&[&event, "failed"]).inc();
+                    log::warn!(
+                        "Delivering {} webhook to {} failed after {} attempts: {:?}",
+                        event, delivery.url, delivery.attempts, err
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn send(client: &reqwest::blocking::Client, delivery: &Delivery) -> Result<()> {
+    check_target(&delivery.url)?;
+    let response = client.post(&delivery.url)
+        .header("Content-Type", "application/json")
+        .header("X-Relayer-Event", event_name(delivery.event))
+        .header("X-Relayer-Signature", delivery.signature.as_str())
+        .timeout(Duration::from_secs(DELIVERY_TIMEOUT_SECS))
+        .body(delivery.body.clone())
+        .send()?;
+    anyhow::ensure!(response.status().is_success(), "Webhook responded with {}", response.status());
+    Ok(())
+}
+
+fn required_tenant(context: &Context) -> Result<String> {
+    context.tenant()?.ok_or_else(|| Rejection::new(Status::Unauthorized, "Webhooks require an API key").into())
+}
+
+pub fn webhook(context: &Context) -> Result<Webhook> {
+    context.webhooks().registration(&required_tenant(context)?)?
+        .map(|registration| Webhook { url: registration.url })
+        .ok_or_else(|| Rejection::new(Status::NotFound, "No webhook registered").into())
+}
+
+pub fn register(context: &Context, registration: WebhookRegistration) -> Result<Webhook> {
+    let url = registration.url.clone();
+    context.webhooks().register(&required_tenant(context)?, registration)?;
+    Ok(Webhook { url })
+}
+
+pub fn unregister(context: &Context) -> Result<()> {
+    context.webhooks().unregister(&required_tenant(context)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethereum_types::U256;
+
+    fn record(tenant: Option<&str>, callback_url: Option<&str>) -> RelayRecord {
+        RelayRecord {
+            id: 7,
+            chain_id: 4,
+            tenant: tenant.map(|tenant| tenant.to_string()),
+            request_id: None,
+            callback_url: callback_url.map(|url| url.to_string()),
+            service: "execute_safe".to_string(),
+            backend: None,
+            wallet: None,
+            calls: vec![],
+            fee: U256::zero(),
+            fee_token: None,
+            collected_fee: None,
+            batch_size: 1,
+            outcome: crate::models::RelayOutcome::Submitted,
+            hash: Some("0x01".to_string()),
+            error: None,
+            transaction_hash: None,
+            block_number: None,
+            gas_used: None,
+            effective_gas_price: None,
+            created_at: 0,
+            updated_at: 0
+        }
+    }
+
+    fn registration(url: &str) -> WebhookRegistration {
+        WebhookRegistration { url: url.to_string(), secret: "0123456789abcdef".to_string() }
+    }
+
+    #[test]
+    fn signature_is_hmac_sha256_of_body() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            sign("Jefe", "what do ya want for nothing?"),
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn registrations_are_validated() {
+        let webhooks = Webhooks::new(None, 8);
+        assert!(webhooks.register("acme", registration("ftp://acme.example")).is_err());
+        let short_secret = WebhookRegistration { secret: "short".to_string(), ..registration("https://acme.example") };
+        assert!(webhooks.register("acme", short_secret).is_err());
+        assert!(webhooks.unregister("acme").is_err());
+        webhooks.register("acme", registration("https://acme.example/hook")).unwrap();
+        assert_eq!(webhooks.registration("acme").unwrap().unwrap().url, "https://acme.example/hook");
+        webhooks.unregister("acme").unwrap();
+        assert!(webhooks.registration("acme").unwrap().is_none());
+    }
+
+    #[test]
+    fn callback_urls_require_a_tenant_webhook() {
+        let webhooks = Webhooks::new(None, 8);
+        let tenant = Some("acme".to_string());
+        assert_eq!(webhooks.callback_url(&tenant, None).unwrap(), None);
+        assert!(webhooks.callback_url(&tenant, Some("https://acme.example/relay/1")).is_err());
+        webhooks.register("acme", registration("https://acme.example/hook")).unwrap();
+        assert!(webhooks.callback_url(&tenant, Some("not a url")).is_err());
+        assert_eq!(
+            webhooks.callback_url(&tenant, Some("https://acme.example/relay/1")).unwrap().as_deref(),
+            Some("https://acme.example/relay/1")
+        );
+        let err = webhooks.callback_url(&None, Some("https://acme.example/relay/1")).err().unwrap();
+        assert_eq!(err.downcast_ref::<Rejection>().unwrap().status, Status::Unauthorized);
+    }
+
+    #[test]
+    fn internal_addresses_are_refused() {
+        for url in &[
+            "http://127.0.0.1/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://10.1.2.3/hook",
+            "https://192.168.0.1/hook",
+            "http://[::1]:8080/hook",
+            "http://[fd00::1]/hook",
+            "http://[::ffff:127.0.0.1]/hook",
+            "http://localhost:8000/hook",
+        ] {
+            assert!(check_url(url).is_err(), "{} should be refused", url);
+        }
+        assert!(check_url("https://8.8.8.8/hook").is_ok());
+        assert!(check_url("https://acme.example/hook").is_ok());
+
+        let webhooks = Webhooks::new(None, 8);
+        assert!(webhooks.register("acme", registration("http://169.254.169.254/hook")).is_err());
+        webhooks.register("acme", registration("https://acme.example/hook")).unwrap();
+        assert!(webhooks.callback_url(&Some("acme".to_string()), Some("http://10.0.0.1/relay")).is_err());
+        // Names are resolved before the delivery
+        assert!(check_target("http://localhost:8000/hook").is_err());
+        assert!(check_target("http://127.0.0.1/hook").is_err());
+    }
+
+    #[test]
+    fn events_are_queued_for_the_callback_or_tenant_webhook() {
+        let webhooks = Webhooks::new(None, 8);
+        webhooks.notify(WebhookEventType::Broadcast, &record(Some("acme"), None));
+        webhooks.notify(WebhookEventType::Broadcast, &record(None, Some("https://acme.example/relay/7")));
+        assert!(webhooks.take_due().unwrap().is_empty());
+
+        webhooks.register("acme", registration("https://acme.example/hook")).unwrap();
+        webhooks.notify(WebhookEventType::Broadcast, &record(Some("acme"), None));
+        webhooks.notify(WebhookEventType::Mined, &record(Some("acme"), Some("https://acme.example/relay/7")));
+        let deliveries = webhooks.take_due().unwrap();
+        assert_eq!(deliveries.len(), 2);
+        assert_eq!(deliveries[0].url, "https://acme.example/hook");
+        assert_eq!(deliveries[1].url, "https://acme.example/relay/7");
+        assert_eq!(deliveries[1].signature, sign("0123456789abcdef", &deliveries[1].body));
+        let payload: WebhookEvent = serde_json::from_str(&deliveries[1].body).unwrap();
+        assert_eq!(payload.event, WebhookEventType::Mined);
+        assert_eq!(payload.relay_id, 7);
+        assert!(webhooks.take_due().unwrap().is_empty());
+    }
+
+    #[test]
+    fn retries_back_off() {
+        let webhooks = Webhooks::new(None, 8);
+        let delivery = Delivery {
+            url: "https://acme.example".to_string(),
+            event: WebhookEventType::Mined,
+            body: "{}".to_string(),
+            signature: String::new(),
+            attempts: 1
+        };
+        webhooks.schedule(now() + retry_delay_secs(delivery.attempts), &delivery).unwrap();
+        assert!(webhooks.take_due().unwrap().is_empty());
+        assert_eq!(retry_delay_secs(3), 8);
+        assert_eq!(retry_delay_secs(20), 1024);
+    }
+}
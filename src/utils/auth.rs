@@ -0,0 +1,82 @@
+use rocket::http::Status;
+use rocket::request::{self, FromRequest, Request};
+use rocket::{Outcome, State};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+use crate::config::Config;
+
+/// Compares the tokens in constant time. Both are hashed first, so that neither the length nor a matching prefix of
+/// the configured token can be timed.
+fn tokens_match(provided: &str, token: &str) -> bool {
+    let (provided, token) = (Sha256::digest(provided.as_bytes()), Sha256::digest(token.as_bytes()));
+    provided.iter().zip(token.iter()).fold(0u8, |difference, (a, b)| difference | (a ^ b)) == 0
+}
+
+fn config(request: &Request) -> Arc<Config> {
+    request.guard::<State<Arc<Config>>>().unwrap().inner().clone()
+}
+
+/// Checks the `Authorization: Bearer <token>` header, routes without a configured token are disabled.
+fn bearer<T>(request: &Request, token: Option<&String>, guard: T) -> request::Outcome<T, ()> {
+    let token = match token {
+        Some(token) => token,
+        None => return Outcome::Failure((Status::Forbidden, ())),
+    };
+    let provided = request.headers().get_one("Authorization")
+        .and_then(|header| header.strip_prefix("Bearer "));
+    match provided {
+        Some(provided) if tokens_match(provided, token) => Outcome::Success(guard),
+        _ => Outcome::Failure((Status::Unauthorized, ())),
+    }
+}
+
+/// Request guard for admin routes, requires `Authorization: Bearer <ADMIN_TOKEN>`.
+/// Admin routes are disabled if no `ADMIN_TOKEN` is configured.
+pub struct AdminToken;
+
+impl<'a, 'r> FromRequest<'a, 'r> for AdminToken {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        bearer(request, config(request).admin_token.as_ref(), AdminToken)
+    }
+}
+
+/// Request guard for the inbound webhook, requires `Authorization: Bearer <WEBHOOK_TOKEN>`.
+/// The inbound webhook is disabled if no `WEBHOOK_TOKEN` is configured.
+pub struct WebhookToken;
+
+impl<'a, 'r> FromRequest<'a, 'r> for WebhookToken {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        bearer(request, config(request).webhook_token.as_ref(), WebhookToken)
+    }
+}
+
+/// Request guard for the metrics, requires `Authorization: Bearer <METRICS_TOKEN>` (or the `ADMIN_TOKEN` if no
+/// `METRICS_TOKEN` is configured). The metrics are disabled if neither is configured.
+pub struct MetricsToken;
+
+impl<'a, 'r> FromRequest<'a, 'r> for MetricsToken {
+    type Error = ();
+
+    fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
+        let config = config(request);
+        bearer(request, config.metrics_token.as_ref().or_else(|| config.admin_token.as_ref()), MetricsToken)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens_have_to_match_completely() {
+        assert!(tokens_match("secret-token", "secret-token"));
+        assert!(!tokens_match("secret-token", "secret-tokem"));
+        assert!(!tokens_match("secret", "secret-token"));
+        assert!(!tokens_match("", "secret-token"));
+    }
+}
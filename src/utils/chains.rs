@@ -0,0 +1,76 @@
+//! Chain scoped routes, `/v1/<chain_id>/...` is handled by the `/v1/...` routes for the given chain.
+
+use crate::config::Config;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::uri::Origin;
+use rocket::{Data, Request, State};
+use std::sync::Arc;
+
+/// Chain of the request, `None` for routes without a chain id, which use the default chain.
+pub struct ChainScope(pub Option<u64>);
+
+impl ChainScope {
+    pub fn of(request: &Request) -> Option<u64> {
+        request.local_cache(|| ChainScope(None)).0
+    }
+}
+
+/// Splits a chain scoped path into the chain id and the path of the route.
+fn scoped_path(path: &str) -> Option<(u64, String)> {
+    let mut segments = path.trim_start_matches('/').splitn(3, '/');
+    match (segments.next(), segments.next(), segments.next()) {
+        (Some("v1"), Some(chain_id), rest) => {
+            let chain_id = chain_id.parse().ok()?;
+            Some((chain_id, format!("/v1/{}", rest.unwrap_or(""))))
+        },
+        _ => None
+    }
+}
+
+pub struct ChainRoutes();
+
+impl Fairing for ChainRoutes {
+    fn info(&self) -> Info {
+        Info {
+            name: "Route chain scoped requests",
+            kind: Kind::Request,
+        }
+    }
+
+    /// Paths with an unknown chain id are left as they are, so that no route matches them.
+    fn on_request(&self, request: &mut Request, _: &Data) {
+        let (chain_id, path) = match scoped_path(request.uri().path()) {
+            Some(scoped) => scoped,
+            None => return
+        };
+        let config = request.guard::<State<Arc<Config>>>().unwrap().inner().clone();
+        if config.chain(chain_id).is_none() {
+            return;
+        }
+        let uri = match request.uri().query() {
+            Some(query) => format!("{}?{}", path, query),
+            None => path
+        };
+        if let Ok(uri) = Origin::parse_owned(uri) {
+            request.local_cache(|| ChainScope(Some(chain_id)));
+            request.set_uri(uri);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn splits_chain_scoped_paths() {
+        assert_eq!(
+            scoped_path("/v1/100/transactions/execute/safe"),
+            Some((100, "/v1/transactions/execute/safe".to_string()))
+        );
+        assert_eq!(scoped_path("/v1/4/deployment/predict"), Some((4, "/v1/deployment/predict".to_string())));
+        assert_eq!(scoped_path("/v1/transactions/execute/safe"), None);
+        assert_eq!(scoped_path("/v1/jobs/12"), None);
+        assert_eq!(scoped_path("/health/ready"), None);
+    }
+}
@@ -2,8 +2,27 @@ use rocket::request::{self, FromRequest, Request};
 use rocket::Outcome;
 use rocket::State;
 use rocket::http::uri::Origin;
+use rocket::http::Status;
+use anyhow::Result;
+use std::net::IpAddr;
+use std::sync::Arc;
 
-use crate::config::scheme;
+use crate::config::{ChainConfig, Config};
+use crate::models::RuntimeSettings;
+use crate::providers::store::Store;
+use crate::services::audit::AuditLog;
+use crate::services::batch::BatchQueue;
+use crate::services::idempotency::Idempotency;
+use crate::services::jobs::JobQueue;
+use crate::services::settings::{effective_chain, Settings};
+use crate::services::webhooks::Webhooks;
+use crate::providers::ethereum::backend::{backend_type, relay_backend, RelayBackend, RouteGroup};
+use crate::utils::chains::ChainScope;
+use crate::utils::errors::Rejection;
+use crate::utils::logging::RequestId;
+
+/// Runtime settings used for the whole request
+struct SettingsSnapshot(Arc<RuntimeSettings>);
 
 pub struct Context<'a, 'r> {
     request: &'a Request<'r>
@@ -14,10 +33,80 @@ impl<'a, 'r> Context<'a, 'r> {
         self.request.guard::<T>().unwrap()
     }
 
+    /// Configuration loaded at startup, the same instance the workers use
+    pub fn config(&self) -> Arc<Config> {
+        self.get::<State<Arc<Config>>>().inner().clone()
+    }
+
+    /// Chain of a chain scoped route, the default chain otherwise. Includes the fee overrides of the runtime settings.
+    pub fn chain(&self) -> Arc<ChainConfig> {
+        let config = self.config();
+        let chain = ChainScope::of(self.request)
+            .and_then(|chain_id| config.chain(chain_id))
+            .unwrap_or_else(|| config.default_chain());
+        effective_chain(&self.runtime_settings(), chain)
+    }
+
+    pub fn settings(&self) -> &'r Settings {
+        self.get::<State<Settings>>().inner()
+    }
+
+    /// Snapshot of the runtime settings, taken once per request so that changes don't apply halfway through it.
+    pub fn runtime_settings(&self) -> Arc<RuntimeSettings> {
+        self.request.local_cache(|| SettingsSnapshot(self.settings().current())).0.clone()
+    }
+
     pub fn client(&self) -> &'r reqwest::blocking::Client {
         self.get::<State<reqwest::blocking::Client>>().inner()
     }
 
+    pub fn batch_queue(&self) -> &'r BatchQueue {
+        self.get::<State<BatchQueue>>().inner()
+    }
+
+    pub fn store(&self) -> Option<&'r Store> {
+        self.get::<State<Option<Store>>>().inner().as_ref()
+    }
+
+    pub fn job_queue(&self) -> Option<&'r JobQueue> {
+        self.get::<State<Option<JobQueue>>>().inner().as_ref()
+    }
+
+    pub fn audit_log(&self) -> &'r AuditLog {
+        self.get::<State<AuditLog>>().inner()
+    }
+
+    pub fn webhooks(&self) -> &'r Webhooks {
+        self.get::<State<Webhooks>>().inner()
+    }
+
+    /// Callback URL of the `X-Callback-Url` header, notified about the outcome of the relay
+    pub fn callback_url(&self) -> Result<Option<String>> {
+        self.webhooks().callback_url(&self.tenant()?, self.request.headers().get_one("X-Callback-Url"))
+    }
+
+    pub fn idempotency(&self) -> &'r Idempotency {
+        self.get::<State<Idempotency>>().inner()
+    }
+
+    pub fn idempotency_key(&self) -> Option<String> {
+        self.request.headers().get_one("Idempotency-Key").map(|key| key.to_string())
+    }
+
+    pub fn request_id(&self) -> String {
+        RequestId::of(self.request)
+    }
+
+    /// Address of the client, used for rate limits. Requests of trusted proxies (`TRUSTED_PROXIES`) are attributed to
+    /// the address they forwarded in `X-Forwarded-For`, the header of other clients is ignored.
+    pub fn client_address(&self) -> Option<String> {
+        let remote = self.request.remote()?.ip();
+        let forwarded: Vec<&str> = self.request.headers().get("X-Forwarded-For")
+            .flat_map(|value| value.split(','))
+            .collect();
+        Some(forwarded_client(remote, &forwarded, &self.config().trusted_proxies).to_string())
+    }
+
     pub fn uri(&self) -> String {
         self.request.uri().to_string()
     }
@@ -26,9 +115,28 @@ impl<'a, 'r> Context<'a, 'r> {
         format!("{}{}", self.host().unwrap(), origin)
     }
 
+    /// Tenant identified by the `X-Api-Key` header, requests without a key have no tenant.
+    pub fn tenant(&self) -> Result<Option<String>> {
+        let api_key = match self.request.headers().get_one("X-Api-Key") {
+            Some(api_key) => api_key,
+            None => return Ok(None)
+        };
+        self.config().api_keys.get(api_key)
+            .map(|tenant| Some(tenant.clone()))
+            .ok_or_else(|| Rejection::new(Status::Unauthorized, "Unknown API key").into())
+    }
+
+    pub fn relay_backend(&self, group: RouteGroup) -> Result<Box<dyn RelayBackend>> {
+        let tenant = self.tenant()?;
+        let config = self.config();
+        let chain = self.chain();
+        let on_dropped = self.audit_log().on_dropped(chain.chain_id);
+        relay_backend(&config, &chain, backend_type(&config, group, tenant.as_deref())?, on_dropped)
+    }
+
     fn host(&self) -> Option<String> {
         self.request.headers().get_one("Host").map(|host| {
-            format!("{}://{}", scheme(), host)
+            format!("{}://{}", self.config().scheme, host)
         })
     }
 }
@@ -39,4 +147,40 @@ impl<'a, 'r> FromRequest<'a, 'r> for Context<'a, 'r> {
     fn from_request(request: &'a Request<'r>) -> request::Outcome<Self, Self::Error> {
         return Outcome::Success(Context { request });
     }
-}
\ No newline at end of file
+}
+
+/// Walks the forwarded addresses from the proxy closest to the relayer, as long as the address was added by a
+/// trusted proxy. Addresses further left were added by the client and can't be trusted.
+fn forwarded_client(remote: IpAddr, forwarded: &[&str], trusted_proxies: &[IpAddr]) -> IpAddr {
+    let mut client = remote;
+    for address in forwarded.iter().rev() {
+        if !trusted_proxies.contains(&client) {
+            break;
+        }
+        client = match address.trim().parse() {
+            Ok(address) => address,
+            Err(_) => break
+        };
+    }
+    client
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(address: &str) -> IpAddr {
+        address.parse().unwrap()
+    }
+
+    #[test]
+    fn forwarded_addresses_are_only_used_behind_trusted_proxies() {
+        let proxies = vec![ip("10.0.0.1"), ip("10.0.0.2")];
+        assert_eq!(forwarded_client(ip("1.2.3.4"), &["5.6.7.8"], &proxies), ip("1.2.3.4"));
+        assert_eq!(forwarded_client(ip("10.0.0.1"), &["5.6.7.8"], &proxies), ip("5.6.7.8"));
+        // Addresses prepended by the client are ignored
+        assert_eq!(forwarded_client(ip("10.0.0.1"), &["9.9.9.9", " 5.6.7.8", " 10.0.0.2"], &proxies), ip("5.6.7.8"));
+        assert_eq!(forwarded_client(ip("10.0.0.1"), &["unknown"], &proxies), ip("10.0.0.1"));
+        assert_eq!(forwarded_client(ip("10.0.0.1"), &[], &proxies), ip("10.0.0.1"));
+    }
+}
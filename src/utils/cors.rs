@@ -17,12 +17,13 @@ impl Fairing for CORS {
         response.set_header(Header::new("Access-Control-Allow-Origin", "*"));
         response.set_header(Header::new(
             "Access-Control-Allow-Methods",
-            "POST, GET, OPTIONS",
+            "POST, GET, PUT, DELETE, OPTIONS",
         ));
         response.set_header(Header::new(
             "Access-Control-Allow-Headers",
-            "X-Requested-With, Content-Type, Authorization",
+            "X-Requested-With, Content-Type, Authorization, X-Api-Key, Idempotency-Key, X-Request-Id, X-Callback-Url",
         ));
+        response.set_header(Header::new("Access-Control-Expose-Headers", "X-Request-Id"));
         response.set_header(Header::new("Access-Control-Allow-Credentials", "true"));
         if request.method() == Method::Options {
             response.set_status(Status::NoContent);
@@ -0,0 +1,91 @@
+use crate::providers::ethereum::revert::RevertError;
+use crate::services::settings::Paused;
+use rocket::http::Status;
+use rocket::request::Request;
+use rocket::response::{self, status, Responder};
+use rocket_contrib::json::JsonValue;
+use serde_json::Value;
+use thiserror::Error;
+
+pub type ApiResult<T> = Result<T, ApiError>;
+
+/// Error that should be reported to the client as is, instead of a generic server error.
+#[derive(Debug, Error)]
+#[error("{reason}")]
+pub struct Rejection {
+    pub status: Status,
+    pub reason: String,
+}
+
+impl Rejection {
+    pub fn new<S: Into<String>>(status: Status, reason: S) -> Self {
+        Rejection { status, reason: reason.into() }
+    }
+
+    pub fn unprocessable<S: Into<String>>(reason: S) -> Self {
+        Rejection::new(Status::UnprocessableEntity, reason)
+    }
+}
+
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: Status,
+    pub reason: String,
+    pub details: Option<Value>,
+}
+
+impl<E> From<E> for ApiError
+where
+    E: Into<anyhow::Error>,
+{
+    fn from(err: E) -> Self {
+        let err = err.into();
+        if let Some(rejection) = err.downcast_ref::<Rejection>() {
+            return ApiError {
+                status: rejection.status,
+                reason: rejection.reason.clone(),
+                details: None,
+            };
+        }
+        if let Some(revert) = err.downcast_ref::<RevertError>() {
+            return ApiError {
+                status: Status::UnprocessableEntity,
+                reason: revert.to_string(),
+                details: Some(json!({
+                    "revertReason": revert.reason,
+                    "revertData": revert.data
+                }).into()),
+            };
+        }
+        if let Some(Paused(pause)) = err.downcast_ref::<Paused>() {
+            return ApiError {
+                status: Status::ServiceUnavailable,
+                reason: err.to_string(),
+                details: Some(json!({
+                    "group": pause.group,
+                    "chainId": pause.chain_id,
+                    "reason": pause.reason
+                }).into()),
+            };
+        }
+        log::error!("{:?}", err);
+        ApiError {
+            status: Status::InternalServerError,
+            reason: "Server error occurred.".to_string(),
+            details: None,
+        }
+    }
+}
+
+impl<'r> Responder<'r> for ApiError {
+    fn respond_to(self, request: &Request) -> response::Result<'r> {
+        let mut body: JsonValue = json!({
+            "status": "error",
+            "reason": self.reason
+        });
+        if let Some(details) = self.details {
+            body["details"] = details;
+        }
+        status::Custom(self.status, body).respond_to(request)
+    }
+}
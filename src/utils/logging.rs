@@ -0,0 +1,174 @@
+//! Structured JSON logging. Every line contains the id of the request (or job) that is being processed
+//! on the current thread, key and signature material is redacted from the messages.
+
+use crate::providers::ethereum::hash::keccak256;
+use lazy_static::lazy_static;
+use log::{Log, Metadata, Record};
+use regex::Regex;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::{Data, Request, Response};
+use std::cell::RefCell;
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+lazy_static! {
+    /// Signatures and other long hex blobs (e.g. signed transactions)
+    static ref LONG_HEX: Regex = Regex::new(r"0x[0-9a-fA-F]{130,}").unwrap();
+    /// Values of the fields that contain secrets, in `name: value`, `name=value` and JSON notation. Only these names
+    /// are matched, as other fields with similar names (e.g. `paymentToken`) are needed to debug relays.
+    static ref SECRET_FIELD: Regex = Regex::new(
+        r#"(?i)("?\b(?:private_?key|[a-z_]*key_?bytes|signatures?|validation_?data|secret|admin_?token|webhook_?token|(?:x-)?api[-_]?key|authorization)\b"?\s*[:=]\s*)("[^"]*"|\[[^\]]*\]|(?:Bearer\s+)?[^\s,}]+)"#
+    ).unwrap();
+    static ref REQUEST_COUNTER: AtomicU64 = AtomicU64::new(0);
+}
+
+thread_local! {
+    static REQUEST_ID: RefCell<Option<String>> = RefCell::new(None);
+}
+
+pub fn redact(message: &str) -> String {
+    let message = LONG_HEX.replace_all(message, "0x<redacted>");
+    SECRET_FIELD.replace_all(&message, "${1}<redacted>").into_owned()
+}
+
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.with(|id| id.borrow().clone())
+}
+
+/// Sets the id that is added to the log lines of the current thread.
+pub fn set_request_id(request_id: Option<String>) {
+    REQUEST_ID.with(|id| *id.borrow_mut() = request_id);
+}
+
+fn generate_request_id() -> String {
+    let seed = format!(
+        "{}:{}:{}",
+        std::process::id(),
+        chrono::Utc::now().timestamp_nanos(),
+        REQUEST_COUNTER.fetch_add(1, Ordering::SeqCst)
+    );
+    keccak256(seed.as_bytes())[..8].iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Ids provided by the client are only used if they are short and contain no special characters.
+fn accepted_request_id(value: &str) -> Option<String> {
+    let valid = !value.is_empty() && value.len() <= 64
+        && value.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if valid { Some(value.to_string()) } else { None }
+}
+
+#[derive(Clone)]
+pub struct RequestId(pub String);
+
+impl RequestId {
+    pub fn of(request: &Request) -> String {
+        request.local_cache(|| {
+            let id = request.headers().get_one(REQUEST_ID_HEADER)
+                .and_then(accepted_request_id)
+                .unwrap_or_else(generate_request_id);
+            RequestId(id)
+        }).0.clone()
+    }
+}
+
+/// Assigns an id to every request and echoes it in the response headers.
+pub struct RequestIds();
+
+impl Fairing for RequestIds {
+    fn info(&self) -> Info {
+        Info {
+            name: "Assign request ids",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    fn on_request(&self, request: &mut Request, _: &Data) {
+        set_request_id(Some(RequestId::of(request)));
+        log::info!("{} {}", request.method(), request.uri());
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        let request_id = RequestId::of(request);
+        log::info!("{} {} -> {}", request.method(), request.uri(), response.status().code);
+        response.set_header(Header::new(REQUEST_ID_HEADER, request_id));
+        set_request_id(None);
+    }
+}
+
+pub struct JsonLogger {
+    filter: env_logger::filter::Filter
+}
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.filter.matches(record) {
+            return;
+        }
+        let mut line = serde_json::json!({
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "level": record.level().to_string(),
+            "target": record.target(),
+            "message": redact(&record.args().to_string())
+        });
+        if let Some(request_id) = current_request_id() {
+            line["requestId"] = serde_json::Value::String(request_id);
+        }
+        let _ = writeln!(std::io::stderr(), "{}", line);
+    }
+
+    fn flush(&self) {}
+}
+
+/// Installs the JSON logger, the levels are configured via `RUST_LOG` like before.
+pub fn init() {
+    let filter = env_logger::filter::Builder::from_env("RUST_LOG").build();
+    log::set_max_level(filter.filter());
+    log::set_boxed_logger(Box::new(JsonLogger { filter })).expect("Logger already initialized");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_signatures() {
+        let signature = format!("0x{}", "ab".repeat(65));
+        assert_eq!(redact(&format!("signed {}", signature)), "signed 0x<redacted>");
+    }
+
+    #[test]
+    fn redacts_secret_fields() {
+        assert_eq!(redact("key_bytes=0x1234 nonce=1"), "key_bytes=<redacted> nonce=1");
+        assert_eq!(redact(r#"{"signatures":"0x1234","to":"0x01"}"#), r#"{"signatures":<redacted>,"to":"0x01"}"#);
+        assert_eq!(redact("Authorization: Bearer abc"), "Authorization: <redacted>");
+        assert_eq!(redact("ADMIN_TOKEN=abc"), "ADMIN_TOKEN=<redacted>");
+        assert_eq!(redact("webhook_token: abc"), "webhook_token: <redacted>");
+        assert_eq!(redact("X-Api-Key: abc"), "X-Api-Key: <redacted>");
+        assert_eq!(redact("ITX_KEY_BYTES=abc"), "ITX_KEY_BYTES=<redacted>");
+        // Token addresses are not secret
+        assert_eq!(redact(r#"{"token":"0x01"}"#), r#"{"token":"0x01"}"#);
+        assert_eq!(redact(r#"{"paymentToken":"0x01","fee_token":"0x02"}"#), r#"{"paymentToken":"0x01","fee_token":"0x02"}"#);
+        assert_eq!(redact("payment_token=0x01"), "payment_token=0x01");
+    }
+
+    #[test]
+    fn keeps_hashes() {
+        let hash = format!("0x{}", "ab".repeat(32));
+        assert_eq!(redact(&format!("relayed {}", hash)), format!("relayed {}", hash));
+    }
+
+    #[test]
+    fn client_request_ids_are_validated() {
+        assert_eq!(accepted_request_id("abc-123"), Some("abc-123".to_string()));
+        assert_eq!(accepted_request_id("abc\n123"), None);
+        assert_eq!(accepted_request_id(&"a".repeat(65)), None);
+        assert_eq!(generate_request_id().len(), 16);
+    }
+}
@@ -0,0 +1,159 @@
+//! Prometheus metrics of the relayer, exposed via `/metrics`.
+
+use lazy_static::lazy_static;
+use prometheus::{
+    register_counter_vec, register_histogram, register_histogram_vec, register_int_counter_vec,
+    register_int_gauge_vec, CounterVec, Encoder, Histogram, HistogramVec, IntCounterVec, IntGaugeVec, TextEncoder
+};
+use crate::providers::ethereum::TransactionReceipt;
+use ethereum_types::H256;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::{Data, Request, Response};
+use std::collections::HashSet;
+use std::sync::Mutex;
+use std::time::Instant;
+
+lazy_static! {
+    pub static ref REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "relayer_requests_total", "Requests by route and outcome", &["route", "outcome"]
+    ).unwrap();
+    pub static ref REQUEST_DURATION: HistogramVec = register_histogram_vec!(
+        "relayer_request_duration_seconds", "Duration of requests by route", &["route"]
+    ).unwrap();
+    pub static ref RELAYS: IntCounterVec = register_int_counter_vec!(
+        "relayer_relays_total", "Relayed transactions by service and outcome", &["service", "outcome"]
+    ).unwrap();
+    pub static ref RPC_DURATION: HistogramVec = register_histogram_vec!(
+        "relayer_rpc_duration_seconds", "Latency of JSON-RPC calls by method", &["method"]
+    ).unwrap();
+    pub static ref RPC_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "relayer_rpc_errors_total", "JSON-RPC calls that could not be performed by method", &["method"]
+    ).unwrap();
+    pub static ref ESTIMATION_ITERATIONS: Histogram = register_histogram!(
+        "relayer_estimation_iterations", "Simulations needed to find a gas limit that succeeds",
+        vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 8.0]
+    ).unwrap();
+    pub static ref GAS_LIMIT: Histogram = register_histogram!(
+        "relayer_gas_limit", "Gas limit of relayed transactions",
+        prometheus::exponential_buckets(25_000.0, 2.0, 10).unwrap()
+    ).unwrap();
+    pub static ref GAS_USED: Histogram = register_histogram!(
+        "relayer_gas_used", "Gas used by mined relayer transactions",
+        prometheus::exponential_buckets(25_000.0, 2.0, 10).unwrap()
+    ).unwrap();
+    pub static ref FEES: CounterVec = register_counter_vec!(
+        "relayer_fees_collected_wei_total", "Native fees collected by mined relays", &["service"]
+    ).unwrap();
+    pub static ref GAS_COSTS: CounterVec = register_counter_vec!(
+        "relayer_gas_costs_wei_total", "Gas costs of mined relays", &["service"]
+    ).unwrap();
+    pub static ref RELAY_LOSSES: IntCounterVec = register_int_counter_vec!(
+        "relayer_relay_losses_total", "Mined relays with a fee below their gas costs", &["service"]
+    ).unwrap();
+    pub static ref WEBHOOK_DELIVERIES: IntCounterVec = register_int_counter_vec!(
+        "relayer_webhook_deliveries_total", "Webhook delivery attempts by event and outcome", &["event", "outcome"]
+    ).unwrap();
+    pub static ref RELAYER_BALANCE: IntGaugeVec = register_int_gauge_vec!(
+        "relayer_balance_gwei", "Balance of the relayer account", &["chain"]
+    ).unwrap();
+    pub static ref NONCE_GAP: IntGaugeVec = register_int_gauge_vec!(
+        "relayer_pending_nonce_gap", "Transactions of the relayer that are pending", &["chain"]
+    ).unwrap();
+    /// Receipts can be loaded multiple times, the gas used is only observed once per transaction
+    static ref OBSERVED_RECEIPTS: Mutex<HashSet<H256>> = Mutex::new(HashSet::new());
+}
+
+pub fn encode() -> anyhow::Result<String> {
+    let mut buffer = vec![];
+    TextEncoder::new().encode(&prometheus::gather(), &mut buffer)?;
+    Ok(String::from_utf8(buffer)?)
+}
+
+/// Counts the outcome of a relay by service.
+pub fn observe_relay<T>(service: &str, result: &anyhow::Result<T>) {
+    let outcome = if result.is_ok() { "success" } else { "error" };
+    RELAYS.with_label_values(&[service, outcome]).inc();
+}
+
+/// Observes the gas used by a mined transaction of the relayer.
+pub fn observe_receipt(receipt: &TransactionReceipt) {
+    if receipt.block_number.is_none() {
+        return;
+    }
+    let mut observed = OBSERVED_RECEIPTS.lock().unwrap();
+    if observed.len() >= 10_000 {
+        observed.clear();
+    }
+    if observed.insert(receipt.transaction_hash) {
+        GAS_USED.observe(receipt.gas_used.low_u64() as f64);
+    }
+}
+
+struct RequestStart(Instant);
+
+/// Tracks requests by the route that handled them, unmatched requests are reported as `unknown`.
+pub struct RequestMetrics();
+
+impl Fairing for RequestMetrics {
+    fn info(&self) -> Info {
+        Info {
+            name: "Collect request metrics",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    fn on_request(&self, request: &mut Request, _: &Data) {
+        request.local_cache(|| RequestStart(Instant::now()));
+    }
+
+    fn on_response(&self, request: &Request, response: &mut Response) {
+        let route = request.route()
+            .map(|route| format!("{} {}", route.method, route.uri.path()))
+            .unwrap_or_else(|| "unknown".to_string());
+        let outcome = match response.status().code {
+            200..=399 => "success",
+            400..=499 => "rejected",
+            _ => "error"
+        };
+        REQUESTS.with_label_values(&[&route, outcome]).inc();
+        let start = request.local_cache(|| RequestStart(Instant::now()));
+        REQUEST_DURATION.with_label_values(&[&route]).observe(start.0.elapsed().as_secs_f64());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::http::Status;
+    use rocket::local::Client;
+
+    #[get("/metrics-test/<status>")]
+    fn respond(status: u16) -> Status {
+        Status::from_code(status).unwrap()
+    }
+
+    fn requests(route: &str, outcome: &str) -> u64 {
+        REQUESTS.with_label_values(&[route, outcome]).get()
+    }
+
+    #[test]
+    fn requests_are_counted_by_route_and_outcome() {
+        let rocket = rocket::ignite().attach(RequestMetrics()).mount("/", routes![respond]);
+        let client = Client::new(rocket).unwrap();
+        let route = "GET /metrics-test/<status>";
+        let unknown = requests("unknown", "rejected");
+
+        client.get("/metrics-test/200").dispatch();
+        client.get("/metrics-test/422").dispatch();
+        client.get("/metrics-test/500").dispatch();
+        client.get("/metrics-test/500").dispatch();
+        client.get("/not-mounted").dispatch();
+
+        assert_eq!(requests(route, "success"), 1);
+        assert_eq!(requests(route, "rejected"), 1);
+        assert_eq!(requests(route, "error"), 2);
+        assert!(requests("unknown", "rejected") > unknown);
+        assert_eq!(REQUEST_DURATION.with_label_values(&[route]).get_sample_count(), 4);
+        assert!(encode().unwrap().contains("relayer_requests_total{outcome=\"rejected\",route=\"GET /metrics-test/<status>\"} 1"));
+    }
+}
@@ -1,9 +1,14 @@
 use std::collections::hash_map::DefaultHasher;
 use std::hash::{Hash, Hasher};
 
+pub mod auth;
+pub mod chains;
 pub mod cors;
 pub mod context;
+pub mod errors;
 pub mod json;
+pub mod logging;
+pub mod metrics;
 
 pub fn hex_hash<T: Hash>(t: &T) -> String {
     let mut s = DefaultHasher::new();